@@ -1,6 +1,10 @@
+use clap::Parser;
 use reqwest::Error as ReqwestError;
 use scraper::{error::SelectorErrorKind, Html, Selector};
+use std::time::Instant;
 use thiserror::Error;
+use tracing::{field, info, instrument, warn, Span};
+use writing_web_crawlers::logging::{init_logging, LogArgs};
 
 #[derive(Debug, Error)]
 enum AppError {
@@ -8,10 +12,22 @@ enum AppError {
     Http(#[from] ReqwestError),
     #[error("Selector parsing error: {0}")]
     SelectorParse(#[from] SelectorErrorKind<'static>),
+    #[error("Logging setup error: {0}")]
+    Logging(#[from] writing_web_crawlers::error::AppError),
+}
+
+// The scraper has no arguments of its own, only the crawlers' log flags
+#[derive(Parser)]
+struct Cli {
+    #[command(flatten)]
+    logging: LogArgs,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), AppError> {
+    let cli = Cli::parse();
+    init_logging(&cli.logging.config())?;
+
     let title = get_title("http://www.pythonscraping.com/pages/page1.html").await?;
 
     if let Some(title) = title {
@@ -39,13 +55,35 @@ async fn get_title(url: &str) -> Result<Option<String>, AppError> {
     }
 }
 
+// The request's span gets its status, size and duration once the response is read
+#[instrument(
+    name = "request",
+    skip_all,
+    fields(
+        method = "GET",
+        url = %url,
+        status = field::Empty,
+        bytes = field::Empty,
+        duration_ms = field::Empty,
+        retry = 0,
+    ),
+    err
+)]
 async fn get_site_html(url: &str) -> Result<Option<String>, AppError> {
+    let started = Instant::now();
     let response = reqwest::get(url).await?;
+    let span = Span::current();
+    span.record("status", response.status().as_u16());
 
     if response.status().is_success() {
         let body = response.text().await?;
+        span.record("bytes", body.len());
+        span.record("duration_ms", started.elapsed().as_secs_f64() * 1000.0);
+        info!("response");
         Ok(Some(body))
     } else {
+        span.record("duration_ms", started.elapsed().as_secs_f64() * 1000.0);
+        warn!("unsuccessful response");
         Ok(None)
     }
 }
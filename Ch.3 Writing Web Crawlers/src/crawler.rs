@@ -0,0 +1,842 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::future;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use reqwest::Client;
+use scraper::Html;
+use tokio::signal;
+use tokio::task::JoinSet;
+use tokio::time;
+use tracing::{info_span, Instrument};
+use url::Url;
+
+use crate::dedup::{canonical_url, simhash, visible_text, DuplicateCluster, DuplicateDetector};
+use crate::error::AppError;
+use crate::fetch::{Fetch, FetchStop, PageFetcher, RedirectFinding};
+use crate::frontier::Frontier;
+use crate::graph::LinkGraph;
+use crate::headers::{HeaderProfile, RequestHeaders};
+use crate::links::{find_document_links, normalize_url, Link};
+use crate::metrics::CrawlMetrics;
+use crate::robots::RobotsGate;
+use crate::scope::Scope;
+use crate::sitemap::{discover_sitemaps, SitemapEntry};
+use crate::state::{CrawlStore, PageState};
+
+pub const USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+// Crawl state is committed to disk after this many pages or this much time, whichever
+// comes first
+const CHECKPOINT_PAGES: usize = 200;
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub struct CrawlLimits {
+    pub max_depth: usize,
+    pub max_pages: usize,
+    pub max_duration: Option<Duration>,
+    pub workers: usize,
+    pub max_per_host: usize,
+    // Extra attempts for pages that fail to download
+    pub retries: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    #[default]
+    Off,
+    // Cluster near-duplicate pages in the report but crawl them like any other page
+    Report,
+    // Also leave the links of near-duplicates unfollowed
+    Skip,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CrawlOptions {
+    pub respect_robots: bool,
+    // Seed the frontier with the pages listed in the site's sitemaps
+    pub use_sitemaps: bool,
+    pub scope: Scope,
+    // SQLite file the crawl state is kept in. A crawl that finds state from an earlier
+    // run of the same site carries on where it stopped.
+    pub state: Option<PathBuf>,
+    pub duplicates: DuplicatePolicy,
+    // Fingerprints at most this many bits apart belong to near-duplicate pages
+    pub duplicate_distance: u32,
+    pub metrics: Option<Arc<CrawlMetrics>>,
+    // Sent with every page request. robots.txt and sitemaps are fetched with its first
+    // user agent.
+    pub headers: HeaderProfile,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    FrontierExhausted,
+    MaxPages,
+    MaxDuration,
+    Interrupted,
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::FrontierExhausted => write!(f, "no pages left to visit"),
+            Self::MaxPages => write!(f, "page limit reached"),
+            Self::MaxDuration => write!(f, "time limit reached"),
+            Self::Interrupted => write!(f, "interrupted"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CrawlError {
+    pub url: String,
+    pub depth: usize,
+    pub message: String,
+}
+
+#[derive(Debug)]
+pub struct CrawlReport {
+    pub pages_fetched: usize,
+    pub status_counts: BTreeMap<u16, usize>,
+    pub errors: Vec<CrawlError>,
+    pub disallowed: Vec<String>,
+    pub sitemap_pages: usize,
+    pub external_links: usize,
+    // Pages queued by an earlier run that this one picked up
+    pub resumed_pages: usize,
+    pub duplicate_clusters: Vec<DuplicateCluster>,
+    pub redirect_findings: Vec<RedirectFinding>,
+    pub elapsed: Duration,
+    pub stop_reason: StopReason,
+}
+
+impl CrawlReport {
+    pub fn print(&self) {
+        let seconds = self.elapsed.as_secs_f64();
+        println!("\nCrawl report");
+        println!("  Stopped: {}", self.stop_reason);
+        println!("  Pages fetched: {}", self.pages_fetched);
+        if self.resumed_pages > 0 {
+            println!("  Pages resumed from saved state: {}", self.resumed_pages);
+        }
+        println!("  Pages seeded from sitemaps: {}", self.sitemap_pages);
+        println!("  External links: {}", self.external_links);
+        println!(
+            "  Elapsed: {:.2}s ({:.2} pages/s)",
+            seconds,
+            self.pages_fetched as f64 / seconds.max(f64::EPSILON)
+        );
+
+        println!("  Responses by status:");
+        for (status, count) in &self.status_counts {
+            println!("    {}: {}", status, count);
+        }
+
+        println!("  Disallowed by robots.txt: {}", self.disallowed.len());
+        for url in &self.disallowed {
+            println!("    {}", url);
+        }
+
+        if !self.duplicate_clusters.is_empty() {
+            println!(
+                "  Near-duplicate clusters: {}",
+                self.duplicate_clusters.len()
+            );
+            for cluster in &self.duplicate_clusters {
+                println!("    {}", cluster.original);
+                for duplicate in &cluster.duplicates {
+                    println!("      = {}", duplicate);
+                }
+            }
+        }
+
+        if !self.redirect_findings.is_empty() {
+            println!("  Redirect findings: {}", self.redirect_findings.len());
+            for finding in &self.redirect_findings {
+                println!("    {}: {}", finding.issue, finding.chain.join(" -> "));
+            }
+        }
+
+        println!("  Errors: {}", self.errors.len());
+        for error in &self.errors {
+            println!(
+                "    [depth {}] {}: {}",
+                error.depth, error.url, error.message
+            );
+        }
+    }
+}
+
+// A fetched page, reduced to what the coordinator needs once the worker is done. Links
+// and fingerprint belong to the page the redirect chain ended on.
+struct FetchedPage {
+    fetch: Fetch,
+    links: Vec<Link>,
+    fingerprint: Option<u64>,
+    // From the page's `<link rel="canonical">` or its Link header
+    canonical: Option<Url>,
+}
+
+// Mutable crawl state. Only the coordinator touches it, so workers never race on
+// the frontier or the seen set. With a store, the seen set, the frontier and the
+// external pages seen live on disk instead of in `seen_pages`, `frontier` and
+// `seen_external`, and are looked up by URL, so they don't have to fit in memory.
+struct CrawlRun {
+    scope: Arc<Scope>,
+    graph: LinkGraph,
+    frontier: Frontier,
+    seen_pages: HashSet<String>,
+    seen_external: HashSet<String>,
+    store: Option<CrawlStore>,
+    // Pages taken from the store's frontier that haven't been recorded yet
+    fetching: HashSet<String>,
+    stored_queue: usize,
+    duplicates: Option<DuplicateDetector>,
+    skip_duplicates: bool,
+    redirect_findings: Vec<RedirectFinding>,
+    attempts: HashMap<String, usize>,
+    host_load: HashMap<String, usize>,
+    status_counts: BTreeMap<u16, usize>,
+    errors: Vec<CrawlError>,
+    disallowed: Vec<String>,
+    sitemap_pages: usize,
+    resumed_pages: usize,
+    pages_scheduled: usize,
+    pages_fetched: usize,
+    pages_since_checkpoint: usize,
+    last_checkpoint: Instant,
+}
+
+impl CrawlRun {
+    fn new(
+        start_url: Url,
+        options: &CrawlOptions,
+        store: Option<CrawlStore>,
+    ) -> Result<Self, AppError> {
+        let scope = Arc::new(options.scope.clone().for_start(&start_url));
+        let duplicates = (options.duplicates != DuplicatePolicy::Off)
+            .then(|| DuplicateDetector::new(options.duplicate_distance));
+        let mut run = Self {
+            scope,
+            graph: LinkGraph::new(),
+            frontier: Frontier::new(),
+            seen_pages: HashSet::new(),
+            seen_external: HashSet::new(),
+            store,
+            fetching: HashSet::new(),
+            stored_queue: 0,
+            duplicates,
+            skip_duplicates: options.duplicates == DuplicatePolicy::Skip,
+            redirect_findings: Vec::new(),
+            attempts: HashMap::new(),
+            host_load: HashMap::new(),
+            status_counts: BTreeMap::new(),
+            errors: Vec::new(),
+            disallowed: Vec::new(),
+            sitemap_pages: 0,
+            resumed_pages: 0,
+            pages_scheduled: 0,
+            pages_fetched: 0,
+            pages_since_checkpoint: 0,
+            last_checkpoint: Instant::now(),
+        };
+
+        let saved_start = match &run.store {
+            Some(store) => store.start_url()?,
+            None => None,
+        };
+        match saved_start {
+            Some(saved_start) if saved_start == start_url.as_str() => run.restore()?,
+            Some(saved_start) => return Err(AppError::StateMismatch(saved_start)),
+            None => {
+                if let Some(store) = &run.store {
+                    store.set_start_url(&start_url)?;
+                }
+                run.graph.add_root(start_url.as_str());
+                run.enqueue(&start_url, 0, None)?;
+            }
+        }
+
+        Ok(run)
+    }
+
+    fn is_resumed(&self) -> bool {
+        self.resumed_pages > 0 || self.pages_fetched > 0
+    }
+
+    // Picks up an earlier run: its graph, its counters and the pages it still had queued
+    fn restore(&mut self) -> Result<(), AppError> {
+        let Some(store) = &self.store else {
+            return Ok(());
+        };
+
+        self.graph = store.load_graph()?;
+        let summary = store.summary()?;
+        self.status_counts = summary.status_counts;
+        self.disallowed = store.disallowed_pages()?;
+        self.errors = store
+            .failed_pages()?
+            .into_iter()
+            .map(|page| CrawlError {
+                url: page.url,
+                depth: page.depth,
+                message: page.error,
+            })
+            .collect();
+        self.pages_fetched = self.status_counts.values().sum::<usize>() + self.errors.len();
+        self.pages_scheduled = self.pages_fetched;
+        self.resumed_pages = store.queued_count()?;
+        self.stored_queue = self.resumed_pages;
+
+        Ok(())
+    }
+
+    // Returns false when the page was already seen. A stored page starts out queued, so
+    // the caller records what became of it.
+    fn mark_seen(&mut self, url: &Url, depth: usize) -> Result<bool, AppError> {
+        match &self.store {
+            Some(store) => store.add_page(url, depth, None),
+            None => Ok(self.seen_pages.insert(url.to_string())),
+        }
+    }
+
+    // Queues the page unless it was already seen, and returns whether it was queued
+    fn enqueue(
+        &mut self,
+        url: &Url,
+        depth: usize,
+        hint: Option<&SitemapEntry>,
+    ) -> Result<bool, AppError> {
+        let queued = match &self.store {
+            Some(store) => {
+                let queued = store.add_page(url, depth, hint)?;
+                if queued {
+                    self.stored_queue += 1;
+                }
+                queued
+            }
+            None => {
+                let queued = self.seen_pages.insert(url.to_string());
+                if queued {
+                    self.frontier.push_with_hint(url.clone(), depth, hint);
+                }
+                queued
+            }
+        };
+        Ok(queued)
+    }
+
+    fn queue_len(&self) -> usize {
+        match &self.store {
+            Some(_) => self.stored_queue,
+            None => self.frontier.len(),
+        }
+    }
+
+    // Sitemap pages start at depth 0 like the starting site, so pages nothing links
+    // to are still crawled and their own links followed
+    fn seed_from_sitemap(&mut self, entries: Vec<SitemapEntry>) -> Result<(), AppError> {
+        for entry in entries {
+            if self.enqueue(&entry.url, 0, Some(&entry))? {
+                self.graph.add_node(entry.url.as_str(), true);
+                self.sitemap_pages += 1;
+            }
+        }
+        Ok(())
+    }
+
+    // Takes the first queued page whose host still has a free connection slot
+    fn next_page(&mut self, max_per_host: usize) -> Result<Option<(Url, usize)>, AppError> {
+        let host_load = &self.host_load;
+        let available =
+            |url: &Url| host_load.get(&host_key(url)).copied().unwrap_or(0) < max_per_host;
+
+        let next = match &self.store {
+            Some(store) => {
+                let fetching = &self.fetching;
+                match store.next_queued(|url| !fetching.contains(url.as_str()) && available(url))? {
+                    Some(page) => {
+                        if page.attempts > 0 {
+                            self.attempts.insert(page.url.to_string(), page.attempts);
+                        }
+                        self.fetching.insert(page.url.to_string());
+                        self.stored_queue -= 1;
+                        Some((page.url, page.depth))
+                    }
+                    None => None,
+                }
+            }
+            None => self.frontier.pop_where(available),
+        };
+        let Some((url, depth)) = next else {
+            return Ok(None);
+        };
+
+        *self.host_load.entry(host_key(&url)).or_insert(0) += 1;
+        self.pages_scheduled += 1;
+        Ok(Some((url, depth)))
+    }
+
+    fn record(
+        &mut self,
+        page_url: Url,
+        depth: usize,
+        result: Result<FetchedPage, AppError>,
+        limits: &CrawlLimits,
+    ) -> Result<(), AppError> {
+        if let Some(load) = self.host_load.get_mut(&host_key(&page_url)) {
+            *load -= 1;
+        }
+        self.fetching.remove(page_url.as_str());
+        self.pages_since_checkpoint += 1;
+
+        let page = match result {
+            Ok(page)
+                if page.fetch.redirects.is_empty()
+                    && page.fetch.stop == Some(FetchStop::Disallowed) =>
+            {
+                // robots.txt disallowed the page itself, so nothing was fetched
+                if let Some(store) = &self.store {
+                    store.record_page(&page_url, depth, PageState::Disallowed, None, 0, None)?;
+                }
+                self.disallowed.push(page_url.to_string());
+                return Ok(());
+            }
+            Ok(page) => page,
+            Err(err) => {
+                self.pages_fetched += 1;
+                let attempts = self.attempts.remove(page_url.as_str()).unwrap_or(0) + 1;
+                let message = err.to_string();
+
+                // Failed pages go to the back of their depth and are tried again later
+                let state = if attempts <= limits.retries {
+                    self.attempts.insert(page_url.to_string(), attempts);
+                    PageState::Queued
+                } else {
+                    PageState::Failed
+                };
+                if let Some(store) = &self.store {
+                    store.record_page(&page_url, depth, state, None, attempts, Some(&message))?;
+                }
+
+                if state == PageState::Queued {
+                    match &self.store {
+                        Some(_) => self.stored_queue += 1,
+                        None => self.frontier.push(page_url, depth),
+                    }
+                } else {
+                    self.errors.push(CrawlError {
+                        url: page_url.to_string(),
+                        depth,
+                        message,
+                    });
+                }
+                return Ok(());
+            }
+        };
+
+        self.pages_fetched += 1;
+        let attempts = self.attempts.remove(page_url.as_str()).unwrap_or(0) + 1;
+        if let Some(finding) = page.fetch.audit() {
+            self.redirect_findings.push(finding);
+        }
+
+        let Some(status) = page.fetch.first_status() else {
+            return Ok(());
+        };
+        self.record_response(&page_url, depth, status, attempts)?;
+
+        // Every page along the redirect chain is a page of its own, so a URL that many
+        // links redirect to is only crawled once
+        for (index, redirect) in page.fetch.redirects.iter().enumerate() {
+            let link = Link {
+                url: redirect.to.clone(),
+                anchor_text: String::new(),
+                rel: vec!["redirect".to_string()],
+            };
+            if !self.record_link(&redirect.from, &link)? {
+                return Ok(());
+            }
+
+            let is_last = index + 1 == page.fetch.redirects.len();
+            match (is_last, page.fetch.stop) {
+                (true, Some(FetchStop::Disallowed)) => {
+                    if self.mark_seen(&redirect.to, depth)? {
+                        if let Some(store) = &self.store {
+                            store.record_page(
+                                &redirect.to,
+                                depth,
+                                PageState::Disallowed,
+                                None,
+                                0,
+                                None,
+                            )?;
+                        }
+                        self.disallowed.push(redirect.to.to_string());
+                    }
+                    return Ok(());
+                }
+                // The target of a loop was already seen, and a chain that's too long
+                // isn't followed any further
+                (true, Some(_)) => return Ok(()),
+                _ => {}
+            }
+
+            if !self.mark_seen(&redirect.to, depth)? {
+                return Ok(());
+            }
+            let status = match page.fetch.redirects.get(index + 1) {
+                Some(next) => next.status,
+                None => match page.fetch.status {
+                    Some(status) => status,
+                    None => return Ok(()),
+                },
+            };
+            self.record_response(&redirect.to, depth, status, 1)?;
+        }
+
+        if self.is_duplicate(depth, &page)? {
+            return Ok(());
+        }
+
+        for link in &page.links {
+            if self.record_link(&page.fetch.url, link)? && depth < limits.max_depth {
+                self.enqueue(&link.url, depth + 1, None)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn record_response(
+        &mut self,
+        url: &Url,
+        depth: usize,
+        status: u16,
+        attempts: usize,
+    ) -> Result<(), AppError> {
+        *self.status_counts.entry(status).or_insert(0) += 1;
+        self.graph.set_status(url.as_str(), true, status);
+        if let Some(store) = &self.store {
+            store.record_page(url, depth, PageState::Done, Some(status), attempts, None)?;
+        }
+        Ok(())
+    }
+
+    // Adds the link to the graph and returns whether its target is in scope. External
+    // targets are printed the first time they show up.
+    fn record_link(&mut self, source: &Url, link: &Link) -> Result<bool, AppError> {
+        let internal = self.scope.contains(&link.url);
+        let new_external = !internal
+            && match &self.store {
+                Some(store) => !store.has_external(&link.url)?,
+                None => self.seen_external.insert(link.url.to_string()),
+            };
+
+        self.graph.add_edge(source.as_str(), link, internal);
+        if let Some(store) = &self.store {
+            store.record_link(source, link, internal)?;
+        }
+
+        if new_external {
+            println!("{}", link.url);
+        }
+        Ok(internal)
+    }
+
+    // A page whose canonical URL is another page in scope is an alias of that page: the
+    // canonical page is queued in its place and the alias's links aren't followed. Other
+    // pages are compared by fingerprint when duplicate detection is on.
+    fn is_duplicate(&mut self, depth: usize, page: &FetchedPage) -> Result<bool, AppError> {
+        let page_url = &page.fetch.url;
+        let canonical = page
+            .canonical
+            .as_ref()
+            .filter(|canonical| *canonical != page_url && self.scope.contains(canonical));
+        if let Some(canonical) = canonical {
+            let link = Link {
+                url: canonical.clone(),
+                anchor_text: String::new(),
+                rel: vec!["canonical".to_string()],
+            };
+            self.record_link(page_url, &link)?;
+            if let Some(duplicates) = self.duplicates.as_mut() {
+                duplicates.add_alias(canonical.as_str(), page_url.as_str());
+            }
+            self.enqueue(canonical, depth, None)?;
+            return Ok(true);
+        }
+
+        let duplicate_of = match (self.duplicates.as_mut(), page.fingerprint) {
+            (Some(duplicates), Some(fingerprint)) => {
+                duplicates.check(page_url.as_str(), fingerprint)
+            }
+            _ => None,
+        };
+        Ok(duplicate_of.is_some() && self.skip_duplicates)
+    }
+
+    fn checkpoint(&mut self, force: bool) -> Result<(), AppError> {
+        let due = self.pages_since_checkpoint >= CHECKPOINT_PAGES
+            || self.last_checkpoint.elapsed() >= CHECKPOINT_INTERVAL;
+
+        if let (Some(store), true) = (self.store.as_mut(), force || due) {
+            store.checkpoint()?;
+            self.pages_since_checkpoint = 0;
+            self.last_checkpoint = Instant::now();
+        }
+        Ok(())
+    }
+
+    fn finish(
+        mut self,
+        stop_reason: StopReason,
+        elapsed: Duration,
+    ) -> Result<(LinkGraph, CrawlReport), AppError> {
+        self.checkpoint(true)?;
+        let external_links = match &self.store {
+            Some(store) => store.external_count()?,
+            None => self.seen_external.len(),
+        };
+
+        let report = CrawlReport {
+            pages_fetched: self.pages_fetched,
+            status_counts: self.status_counts,
+            errors: self.errors,
+            disallowed: self.disallowed,
+            sitemap_pages: self.sitemap_pages,
+            external_links,
+            resumed_pages: self.resumed_pages,
+            duplicate_clusters: self
+                .duplicates
+                .map(|duplicates| duplicates.clusters())
+                .unwrap_or_default(),
+            redirect_findings: self.redirect_findings,
+            elapsed,
+            stop_reason,
+        };
+
+        Ok((self.graph, report))
+    }
+}
+
+pub struct Crawler {
+    client: Client,
+    fetcher: PageFetcher,
+    limits: CrawlLimits,
+    options: CrawlOptions,
+    robots: Option<Arc<RobotsGate>>,
+}
+
+impl Crawler {
+    pub fn new(limits: CrawlLimits, options: CrawlOptions) -> Result<Self, AppError> {
+        let user_agent = options.headers.user_agent(USER_AGENT);
+        let client = Client::builder().user_agent(user_agent).build()?;
+        let robots = options
+            .respect_robots
+            .then(|| Arc::new(RobotsGate::new(client.clone(), user_agent)));
+
+        let mut fetcher = PageFetcher::new(user_agent)?
+            .with_headers(RequestHeaders::new(options.headers.clone()));
+        if let Some(metrics) = &options.metrics {
+            fetcher = fetcher.with_metrics(metrics.clone());
+        }
+
+        Ok(Self {
+            client,
+            fetcher,
+            limits,
+            options,
+            robots,
+        })
+    }
+
+    async fn seed_from_sitemaps(
+        &self,
+        run: &mut CrawlRun,
+        start_url: &Url,
+    ) -> Result<(), AppError> {
+        // Sitemap lines are read from robots.txt even when its rules are ignored
+        let robots = match &self.robots {
+            Some(robots) => robots.robots_for(start_url).await,
+            None => {
+                RobotsGate::new(
+                    self.client.clone(),
+                    self.options.headers.user_agent(USER_AGENT),
+                )
+                .robots_for(start_url)
+                .await
+            }
+        };
+
+        let discovery = discover_sitemaps(&self.client, start_url, &robots, &run.scope).await;
+        for (url, message) in discovery.errors {
+            run.errors.push(CrawlError {
+                url,
+                depth: 0,
+                message,
+            });
+        }
+        run.seed_from_sitemap(discovery.entries)
+    }
+
+    pub async fn crawl(&self, starting_site: &str) -> Result<(LinkGraph, CrawlReport), AppError> {
+        let start_url = normalize_url(Url::parse(starting_site)?)
+            .ok_or_else(|| AppError::UnsupportedScheme(starting_site.to_string()))?;
+
+        let started = Instant::now();
+        let deadline = self.limits.max_duration.map(|limit| started + limit);
+        let store = match &self.options.state {
+            Some(path) => Some(CrawlStore::open(path)?),
+            None => None,
+        };
+        let mut run = CrawlRun::new(start_url.clone(), &self.options, store)?;
+
+        if run.is_resumed() {
+            println!(
+                "Resuming the crawl of {}: {} pages done, {} queued",
+                start_url, run.pages_fetched, run.resumed_pages
+            );
+        } else if self.options.use_sitemaps {
+            self.seed_from_sitemaps(&mut run, &start_url).await?;
+        }
+
+        let mut workers = JoinSet::new();
+        let mut stop_reason = None;
+
+        let ctrl_c = signal::ctrl_c();
+        tokio::pin!(ctrl_c);
+
+        loop {
+            if stop_reason.is_none() {
+                while workers.len() < self.limits.workers
+                    && run.pages_scheduled < self.limits.max_pages
+                {
+                    let Some((page_url, depth)) = run.next_page(self.limits.max_per_host)? else {
+                        break;
+                    };
+                    let fetcher = self.fetcher.clone();
+                    let robots = self.robots.clone();
+                    let scope = run.scope.clone();
+                    let fingerprint = run.duplicates.is_some();
+                    let retry = run.attempts.get(page_url.as_str()).copied().unwrap_or(0);
+                    let span = info_span!("page", url = %page_url, depth, retry);
+                    workers.spawn(
+                        async move {
+                            let result = fetch_page(
+                                &fetcher,
+                                robots.as_deref(),
+                                &scope,
+                                fingerprint,
+                                &page_url,
+                                retry,
+                            )
+                            .await;
+                            (page_url, depth, result)
+                        }
+                        .instrument(span),
+                    );
+                }
+
+                if workers.is_empty() {
+                    stop_reason = Some(if run.queue_len() == 0 {
+                        StopReason::FrontierExhausted
+                    } else {
+                        StopReason::MaxPages
+                    });
+                }
+            }
+
+            if let Some(metrics) = &self.options.metrics {
+                metrics.set_queue_depth(run.queue_len());
+            }
+            if workers.is_empty() {
+                break;
+            }
+
+            tokio::select! {
+                Some(joined) = workers.join_next() => {
+                    // Workers only fail to join when they were aborted on Ctrl-C
+                    if let Ok((page_url, depth, result)) = joined {
+                        run.record(page_url, depth, result, &self.limits)?;
+                        run.checkpoint(false)?;
+                    }
+                }
+                _ = &mut ctrl_c, if stop_reason.is_none() => {
+                    println!("Interrupted, stopping the crawl");
+                    stop_reason = Some(StopReason::Interrupted);
+                    workers.abort_all();
+                }
+                _ = sleep_until_deadline(deadline), if stop_reason.is_none() => {
+                    // Pages already being fetched are still recorded
+                    stop_reason = Some(StopReason::MaxDuration);
+                }
+            }
+        }
+
+        let stop_reason = stop_reason.unwrap_or(StopReason::FrontierExhausted);
+        run.finish(stop_reason, started.elapsed())
+    }
+}
+
+// Only successful responses with a Content-Type in scope are parsed for links
+async fn fetch_page(
+    fetcher: &PageFetcher,
+    robots: Option<&RobotsGate>,
+    scope: &Scope,
+    fingerprint: bool,
+    url: &Url,
+    retry: usize,
+) -> Result<FetchedPage, AppError> {
+    let mut fetch = fetcher.fetch_attempt(robots, scope, url, retry).await?;
+    let body = fetch.body.take();
+    let mut page = FetchedPage {
+        links: Vec::new(),
+        fingerprint: None,
+        canonical: fetch.canonical.clone(),
+        fetch,
+    };
+
+    let Some(body) = body else {
+        return Ok(page);
+    };
+    if !page
+        .fetch
+        .status
+        .is_some_and(|status| (200..300).contains(&status))
+    {
+        return Ok(page);
+    }
+
+    let document = Html::parse_document(&body);
+    let page_url = &page.fetch.url;
+    if fingerprint {
+        page.fingerprint = simhash(&visible_text(&document));
+    }
+    if let Some(canonical) = canonical_url(&document, page_url) {
+        page.canonical = Some(canonical);
+    }
+    page.links = find_document_links(&document, page_url);
+
+    Ok(page)
+}
+
+async fn sleep_until_deadline(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => time::sleep_until(deadline.into()).await,
+        None => future::pending().await,
+    }
+}
+
+// Connection slots are shared by everything on the same host and port
+fn host_key(url: &Url) -> String {
+    format!(
+        "{}:{}",
+        url.host_str().unwrap_or_default(),
+        url.port_or_known_default().unwrap_or_default()
+    )
+}
@@ -0,0 +1,264 @@
+use std::collections::{BTreeMap, HashMap};
+
+use scraper::{Html, Node, Selector};
+use url::Url;
+
+use crate::links::{base_url, resolve_href};
+
+// Words per shingle. Longer shingles make the fingerprint more sensitive to word order.
+const SHINGLE_WORDS: usize = 4;
+
+// Elements whose text never shows up on the page
+const HIDDEN_ELEMENTS: [&str; 5] = ["script", "style", "noscript", "template", "head"];
+
+// The text a reader would see, with whitespace collapsed
+pub fn visible_text(document: &Html) -> String {
+    let mut words = Vec::new();
+
+    for node in document.tree.root().descendants() {
+        let Node::Text(text) = node.value() else {
+            continue;
+        };
+        let hidden = node.ancestors().any(|ancestor| {
+            ancestor
+                .value()
+                .as_element()
+                .is_some_and(|element| HIDDEN_ELEMENTS.contains(&element.name()))
+        });
+        if !hidden {
+            words.extend(text.split_whitespace());
+        }
+    }
+
+    words.join(" ")
+}
+
+// The page's `<link rel="canonical">`, if it names another http(s) URL
+pub fn canonical_url(document: &Html, page_url: &Url) -> Option<Url> {
+    let selector = Selector::parse("link[rel][href]").unwrap();
+    let base_url = base_url(document, page_url);
+    document
+        .select(&selector)
+        .filter(|element| {
+            element.value().attr("rel").is_some_and(|rel| {
+                rel.split_whitespace()
+                    .any(|rel| rel.eq_ignore_ascii_case("canonical"))
+            })
+        })
+        .filter_map(|element| element.value().attr("href"))
+        .find_map(|href| resolve_href(&base_url, href))
+}
+
+// 64-bit SimHash of the text's word shingles. Pages that differ only in a few words,
+// like the same article with another sort order or session id in its navigation,
+// get fingerprints a few bits apart. Pages without any text have no fingerprint.
+pub fn simhash(text: &str) -> Option<u64> {
+    let words = text
+        .split_whitespace()
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>();
+    if words.is_empty() {
+        return None;
+    }
+    let shingles = words
+        .windows(SHINGLE_WORDS.min(words.len()).max(1))
+        .map(|window| fnv1a(window.join(" ").as_bytes()));
+
+    let mut weights = [0i64; 64];
+    for hash in shingles {
+        for (bit, weight) in weights.iter_mut().enumerate() {
+            if hash & (1 << bit) != 0 {
+                *weight += 1;
+            } else {
+                *weight -= 1;
+            }
+        }
+    }
+
+    let fingerprint = weights
+        .iter()
+        .enumerate()
+        .filter(|(_, &weight)| weight > 0)
+        .fold(0, |fingerprint, (bit, _)| fingerprint | (1 << bit));
+    Some(fingerprint)
+}
+
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+// A fixed hash, so fingerprints are the same from run to run
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[derive(Debug, Clone)]
+pub struct DuplicateCluster {
+    // The first page seen with this content
+    pub original: String,
+    pub duplicates: Vec<String>,
+}
+
+// Finds pages whose fingerprints are within `max_distance` bits of a page seen before.
+// Fingerprints are split into `max_distance + 1` bands: two fingerprints that close
+// must agree exactly on at least one band, so only pages sharing a band are compared.
+#[derive(Debug)]
+pub struct DuplicateDetector {
+    max_distance: u32,
+    bands: Vec<(u32, u32)>,
+    pages: Vec<(Option<u64>, String)>,
+    ids: HashMap<String, usize>,
+    // Which cluster each duplicate belongs to
+    duplicate_of: HashMap<String, usize>,
+    index: HashMap<(usize, u64), Vec<usize>>,
+    clusters: BTreeMap<usize, Vec<String>>,
+}
+
+impl DuplicateDetector {
+    pub fn new(max_distance: u32) -> Self {
+        let band_count = (max_distance + 1).min(64);
+        let width = 64 / band_count;
+        let bands = (0..band_count)
+            .map(|band| {
+                let shift = band * width;
+                let bits = if band == band_count - 1 {
+                    64 - shift
+                } else {
+                    width
+                };
+                (shift, bits)
+            })
+            .collect();
+
+        Self {
+            max_distance,
+            bands,
+            pages: Vec::new(),
+            ids: HashMap::new(),
+            duplicate_of: HashMap::new(),
+            index: HashMap::new(),
+            clusters: BTreeMap::new(),
+        }
+    }
+
+    // Returns the earlier page this one duplicates, or records it as a new original
+    pub fn check(&mut self, url: &str, fingerprint: u64) -> Option<String> {
+        let keys = self
+            .bands
+            .iter()
+            .enumerate()
+            .map(|(band, &(shift, bits))| (band, band_value(fingerprint, shift, bits)))
+            .collect::<Vec<_>>();
+
+        let original = keys
+            .iter()
+            .filter_map(|key| self.index.get(key))
+            .flatten()
+            .copied()
+            .filter(|&id| {
+                self.pages[id].0.is_some_and(|original| {
+                    hamming_distance(original, fingerprint) <= self.max_distance
+                })
+            })
+            .min();
+
+        if let Some(id) = original {
+            self.join_cluster(id, url);
+            return Some(self.pages[id].1.clone());
+        }
+
+        let id = self.page_id(url);
+        self.pages[id].0 = Some(fingerprint);
+        for key in keys {
+            self.index.entry(key).or_default().push(id);
+        }
+        None
+    }
+
+    // Records a page known to duplicate another, e.g. through its canonical link
+    pub fn add_alias(&mut self, original: &str, url: &str) {
+        let id = match self.duplicate_of.get(original) {
+            Some(&id) => id,
+            None => self.page_id(original),
+        };
+        self.join_cluster(id, url);
+    }
+
+    // A page that turns out to be a duplicate brings along any aliases it had gathered
+    fn join_cluster(&mut self, id: usize, url: &str) {
+        let mut members = vec![url.to_string()];
+        let own_id = self.ids.get(url).copied().filter(|&own_id| own_id != id);
+        if let Some(own_id) = own_id {
+            members.extend(self.clusters.remove(&own_id).unwrap_or_default());
+        }
+
+        // The aliases already point at the page's own cluster, so they move over too
+        for member in members {
+            let previous = self.duplicate_of.insert(member.clone(), id);
+            if previous.is_none() || previous == own_id {
+                self.clusters.entry(id).or_default().push(member);
+            }
+        }
+    }
+
+    // Pages named as canonical get an id before they are fetched and fingerprinted
+    fn page_id(&mut self, url: &str) -> usize {
+        if let Some(&id) = self.ids.get(url) {
+            return id;
+        }
+        self.pages.push((None, url.to_string()));
+        self.ids.insert(url.to_string(), self.pages.len() - 1);
+        self.pages.len() - 1
+    }
+
+    pub fn clusters(&self) -> Vec<DuplicateCluster> {
+        self.clusters
+            .iter()
+            .map(|(&id, duplicates)| DuplicateCluster {
+                original: self.pages[id].1.clone(),
+                duplicates: duplicates.clone(),
+            })
+            .collect()
+    }
+}
+
+fn band_value(fingerprint: u64, shift: u32, bits: u32) -> u64 {
+    let mask = if bits >= 64 {
+        u64::MAX
+    } else {
+        (1 << bits) - 1
+    };
+    (fingerprint >> shift) & mask
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duplicate_pages_bring_their_aliases_along() {
+        let mut detector = DuplicateDetector::new(3);
+        detector.add_alias(
+            "http://example.com/copy",
+            "http://example.com/copy?ref=home",
+        );
+        assert_eq!(detector.check("http://example.com/", 0xF0F0), None);
+        assert_eq!(
+            detector.check("http://example.com/copy", 0xF0F1).as_deref(),
+            Some("http://example.com/")
+        );
+
+        let clusters = detector.clusters();
+        assert_eq!(clusters.len(), 1);
+        assert_eq!(clusters[0].original, "http://example.com/");
+        assert_eq!(
+            clusters[0].duplicates,
+            [
+                "http://example.com/copy",
+                "http://example.com/copy?ref=home"
+            ]
+        );
+    }
+}
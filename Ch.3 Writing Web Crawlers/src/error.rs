@@ -0,0 +1,67 @@
+use std::path::PathBuf;
+
+#[derive(thiserror::Error, Debug)]
+pub enum AppError {
+    #[error("HTTP request error: {0}")]
+    HttpRequest(#[from] reqwest::Error),
+
+    #[error("Invalid URL: {0}")]
+    InvalidUrl(#[from] url::ParseError),
+
+    #[error("Only http and https URLs can be crawled: {0}")]
+    UnsupportedScheme(String),
+
+    #[error("Restart probability must be between 0 and 1: {0}")]
+    InvalidProbability(f64),
+
+    #[error("Disallowed by robots.txt: {0}")]
+    DisallowedByRobots(String),
+
+    #[error("Unexpected HTTP status {0}: {1}")]
+    UnexpectedStatus(u16, String),
+
+    #[error("Redirect stopped, {0}: {1}")]
+    Redirect(crate::fetch::FetchStop, String),
+
+    #[error("Invalid link pattern: {0}")]
+    InvalidPattern(#[from] fancy_regex::Error),
+
+    #[error("XML parsing error: {0}")]
+    Xml(#[from] quick_xml::Error),
+
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Invalid public suffix list: {0}")]
+    PublicSuffixList(#[from] publicsuffix::Error),
+
+    #[error("Sitemap larger than {0} bytes uncompressed")]
+    SitemapTooLarge(u64),
+
+    #[error("Crawl state error: {0}")]
+    State(#[from] rusqlite::Error),
+
+    #[error("The crawl state belongs to a crawl of {0}")]
+    StateMismatch(String),
+
+    #[error("Invalid log filter: {0}")]
+    LogFilter(#[from] tracing_subscriber::filter::ParseError),
+
+    #[error("Logging setup error: {0}")]
+    Logging(#[from] tracing_subscriber::util::TryInitError),
+
+    #[error("Invalid request log line {0}: {1}")]
+    RequestLog(usize, serde_json::Error),
+
+    #[error("Invalid header profiles in {0}: {1}")]
+    HeaderProfiles(PathBuf, String),
+
+    #[error("Unknown header profile: {0}")]
+    UnknownHeaderProfile(String),
+
+    #[error("Unsupported graph export format: {0}")]
+    UnsupportedExportFormat(PathBuf),
+}
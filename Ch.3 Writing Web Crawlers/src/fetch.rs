@@ -0,0 +1,531 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use reqwest::header::{CONTENT_TYPE, LINK, LOCATION};
+use reqwest::redirect::Policy;
+use reqwest::{Client, Method, Response};
+use scraper::{Html, Selector};
+use url::Url;
+
+use crate::error::AppError;
+use crate::headers::RequestHeaders;
+use crate::links::{base_url, resolve_href};
+use crate::metrics::{error_kind, CrawlMetrics};
+use crate::request_log::RequestSpan;
+use crate::robots::RobotsGate;
+use crate::scope::{Scope, DEFAULT_CONTENT_TYPES};
+
+// Browsers give up after about 20 redirects; a page needing more than 10 is broken
+pub const MAX_REDIRECTS: usize = 10;
+
+// Chains with more hops than this are reported, since every hop costs a round trip
+pub const LONG_REDIRECT_CHAIN: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedirectKind {
+    // A 3xx response with a Location header
+    Http,
+    // `<meta http-equiv="refresh" content="0; url=...">` in a page's head
+    MetaRefresh,
+}
+
+#[derive(Debug, Clone)]
+pub struct Redirect {
+    pub from: Url,
+    // Status of the response that redirected
+    pub status: u16,
+    pub to: Url,
+    pub kind: RedirectKind,
+}
+
+// Why a fetch ended before reaching a page
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FetchStop {
+    // robots.txt disallows the URL the chain reached
+    Disallowed,
+    // A redirect leads out of the crawl's scope
+    OutOfScope,
+    RedirectLoop,
+    TooManyRedirects,
+}
+
+impl FetchStop {
+    // A name for the stop that can go in a metric label
+    pub fn kind(self) -> &'static str {
+        match self {
+            Self::Disallowed => "disallowed",
+            Self::OutOfScope => "out_of_scope",
+            Self::RedirectLoop => "redirect_loop",
+            Self::TooManyRedirects => "too_many_redirects",
+        }
+    }
+}
+
+impl fmt::Display for FetchStop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Disallowed => write!(f, "disallowed by robots.txt"),
+            Self::OutOfScope => write!(f, "redirected out of scope"),
+            Self::RedirectLoop => write!(f, "redirect loop"),
+            Self::TooManyRedirects => write!(f, "too many redirects"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Fetch {
+    pub requested: Url,
+    // Where the redirect chain ended
+    pub url: Url,
+    // Status of the response for `url`. None when the chain stopped before requesting it.
+    pub status: Option<u16>,
+    pub redirects: Vec<Redirect>,
+    pub stop: Option<FetchStop>,
+    // Only kept for responses with one of the scope's content types
+    pub body: Option<String>,
+    // From a `Link: <...>; rel="canonical"` response header
+    pub canonical: Option<Url>,
+}
+
+impl Fetch {
+    fn new(url: &Url) -> Self {
+        Self {
+            requested: url.clone(),
+            url: url.clone(),
+            status: None,
+            redirects: Vec::new(),
+            stop: None,
+            body: None,
+            canonical: None,
+        }
+    }
+
+    // The requested URL followed by every URL the chain went through
+    pub fn chain(&self) -> Vec<String> {
+        std::iter::once(self.requested.to_string())
+            .chain(
+                self.redirects
+                    .iter()
+                    .map(|redirect| redirect.to.to_string()),
+            )
+            .collect()
+    }
+
+    // Status of the first response, which is the requested URL's own status
+    pub fn first_status(&self) -> Option<u16> {
+        self.redirects
+            .first()
+            .map(|redirect| redirect.status)
+            .or(self.status)
+    }
+
+    pub fn audit(&self) -> Option<RedirectFinding> {
+        let issue = match self.stop {
+            Some(FetchStop::RedirectLoop) => RedirectIssue::Loop,
+            Some(FetchStop::TooManyRedirects) => RedirectIssue::TooManyRedirects,
+            _ if self.redirects.len() > LONG_REDIRECT_CHAIN => RedirectIssue::LongChain,
+            _ => return None,
+        };
+
+        Some(RedirectFinding {
+            issue,
+            chain: self.chain(),
+        })
+    }
+
+    // The final URL and body of a successful fetch, for callers that treat every other
+    // outcome as an error. Responses of other content types have an empty body.
+    pub fn into_page(self) -> Result<(Url, String), AppError> {
+        match self.stop {
+            Some(FetchStop::Disallowed) => {
+                return Err(AppError::DisallowedByRobots(self.url.to_string()))
+            }
+            Some(stop) => return Err(AppError::Redirect(stop, self.chain().join(" -> "))),
+            None => {}
+        }
+
+        match self.status {
+            Some(status) if (200..300).contains(&status) => {
+                Ok((self.url, self.body.unwrap_or_default()))
+            }
+            status => Err(AppError::UnexpectedStatus(
+                status.unwrap_or_default(),
+                self.url.to_string(),
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedirectIssue {
+    Loop,
+    TooManyRedirects,
+    LongChain,
+}
+
+impl fmt::Display for RedirectIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Loop => f.pad("loop"),
+            Self::TooManyRedirects => f.pad("too many redirects"),
+            Self::LongChain => f.pad("long chain"),
+        }
+    }
+}
+
+// A redirect chain worth fixing on the site
+#[derive(Debug, Clone)]
+pub struct RedirectFinding {
+    pub issue: RedirectIssue,
+    pub chain: Vec<String>,
+}
+
+// Fetches pages one hop at a time instead of letting reqwest follow redirects, so the
+// whole chain is known and every hop is checked against robots.txt and the scope
+#[derive(Debug, Clone)]
+pub struct PageFetcher {
+    client: Client,
+    metrics: Option<Arc<CrawlMetrics>>,
+    headers: Option<RequestHeaders>,
+    timeout: Option<Duration>,
+}
+
+impl PageFetcher {
+    pub fn new(user_agent: &str) -> Result<Self, AppError> {
+        let client = Client::builder()
+            .user_agent(user_agent)
+            .redirect(Policy::none())
+            .build()?;
+        Ok(Self {
+            client,
+            metrics: None,
+            headers: None,
+            timeout: None,
+        })
+    }
+
+    // Sends the headers of a profile with every request, the user agent included
+    pub fn with_headers(mut self, headers: RequestHeaders) -> Self {
+        self.headers = Some(headers);
+        self
+    }
+
+    // Counts every page, request and failure in `metrics`
+    pub fn with_metrics(mut self, metrics: Arc<CrawlMetrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    // Gives up on a URL once its requests, redirects included, have taken `timeout`.
+    // Waiting for robots.txt and its Crawl-delay doesn't count.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub async fn fetch(
+        &self,
+        robots: Option<&RobotsGate>,
+        scope: &Scope,
+        url: &Url,
+    ) -> Result<Fetch, AppError> {
+        self.fetch_attempt(robots, scope, url, 0).await
+    }
+
+    // Like `fetch`, for a page that failed `retry` times before. The count goes in the
+    // span of every request.
+    pub async fn fetch_attempt(
+        &self,
+        robots: Option<&RobotsGate>,
+        scope: &Scope,
+        url: &Url,
+        retry: usize,
+    ) -> Result<Fetch, AppError> {
+        let fetch = self
+            .follow(Method::GET, robots, Some(scope), url, retry)
+            .await;
+        if let Some(metrics) = &self.metrics {
+            metrics.record_page();
+        }
+        fetch
+    }
+
+    // Follows redirects wherever they lead, for checking a link rather than crawling
+    // it. Only HTML bodies of GET requests are kept.
+    pub async fn check(
+        &self,
+        method: Method,
+        robots: Option<&RobotsGate>,
+        url: &Url,
+    ) -> Result<Fetch, AppError> {
+        self.follow(method, robots, None, url, 0).await
+    }
+
+    // Counts and logs a failed request
+    fn failed(&self, request: &RequestSpan, err: reqwest::Error) -> reqwest::Error {
+        request.failed(&err);
+        if let Some(metrics) = &self.metrics {
+            metrics.record_error(error_kind(&err));
+        }
+        err
+    }
+
+    async fn follow(
+        &self,
+        method: Method,
+        robots: Option<&RobotsGate>,
+        scope: Option<&Scope>,
+        url: &Url,
+        retry: usize,
+    ) -> Result<Fetch, AppError> {
+        let mut fetch = Fetch::new(url);
+        let mut remaining = self.timeout;
+
+        loop {
+            if let Some(robots) = robots {
+                if !robots.check(&fetch.url).await {
+                    fetch.stop = Some(FetchStop::Disallowed);
+                    return Ok(fetch);
+                }
+            }
+
+            let request = RequestSpan::new(&method, &fetch.url, retry);
+            let mut builder = self.client.request(method.clone(), fetch.url.as_str());
+            if let Some(headers) = &self.headers {
+                builder = headers.apply(builder, &fetch.url);
+            }
+            if let Some(remaining) = remaining {
+                builder = builder.timeout(remaining);
+            }
+            let sent = Instant::now();
+            let response = builder
+                .send()
+                .await
+                .map_err(|err| self.failed(&request, err))?;
+            let status = response.status().as_u16();
+            fetch.status = Some(status);
+
+            let next = match location(&response, &fetch.url) {
+                Some(location) => Some((location, RedirectKind::Http)),
+                None => {
+                    fetch.canonical = canonical_header(&response, &fetch.url);
+                    let keep_body = match scope {
+                        Some(scope) => scope.accepts_response(&response),
+                        None => method == Method::GET && is_html(&response),
+                    };
+                    fetch.body = if keep_body {
+                        Some(
+                            response
+                                .text()
+                                .await
+                                .map_err(|err| self.failed(&request, err))?,
+                        )
+                    } else {
+                        None
+                    };
+                    fetch
+                        .body
+                        .as_deref()
+                        .and_then(|body| meta_refresh(body, &fetch.url))
+                        .map(|target| (target, RedirectKind::MetaRefresh))
+                }
+            };
+            remaining = remaining.map(|remaining| remaining.saturating_sub(sent.elapsed()));
+            let bytes = fetch.body.as_ref().map(String::len);
+            let latency = request.finish(status, bytes);
+            if let Some(metrics) = &self.metrics {
+                metrics.record_response(status, bytes.unwrap_or(0), latency);
+            }
+
+            let Some((next, kind)) = next else {
+                return Ok(fetch);
+            };
+
+            let looped = next == fetch.requested
+                || fetch.redirects.iter().any(|redirect| redirect.to == next);
+            fetch.redirects.push(Redirect {
+                from: fetch.url.clone(),
+                status,
+                to: next.clone(),
+                kind,
+            });
+            fetch.status = None;
+            fetch.body = None;
+            fetch.canonical = None;
+
+            let stop = if looped {
+                Some(FetchStop::RedirectLoop)
+            } else if fetch.redirects.len() > MAX_REDIRECTS {
+                Some(FetchStop::TooManyRedirects)
+            } else if scope.is_some_and(|scope| !scope.contains(&next)) {
+                Some(FetchStop::OutOfScope)
+            } else {
+                None
+            };
+
+            if let (
+                Some(metrics),
+                Some(stop @ (FetchStop::RedirectLoop | FetchStop::TooManyRedirects)),
+            ) = (&self.metrics, stop)
+            {
+                metrics.record_error(stop.kind());
+            }
+            fetch.url = next;
+            if stop.is_some() {
+                fetch.stop = stop;
+                return Ok(fetch);
+            }
+        }
+    }
+}
+
+fn is_html(response: &Response) -> bool {
+    response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|mime| DEFAULT_CONTENT_TYPES.contains(&mime.trim().to_lowercase().as_str()))
+}
+
+fn location(response: &Response, url: &Url) -> Option<Url> {
+    if !response.status().is_redirection() {
+        return None;
+    }
+    let location = response.headers().get(LOCATION)?.to_str().ok()?;
+    resolve_href(url, location)
+}
+
+// `Link: </page>; rel="canonical", </page.amp>; rel="amphtml"`
+fn canonical_header(response: &Response, url: &Url) -> Option<Url> {
+    response
+        .headers()
+        .get_all(LINK)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(link_entries)
+        .find_map(|(target, params)| {
+            let is_canonical = params.split(';').any(|param| {
+                let Some((name, value)) = param.split_once('=') else {
+                    return false;
+                };
+                name.trim().eq_ignore_ascii_case("rel")
+                    && value
+                        .trim()
+                        .trim_matches('"')
+                        .split_whitespace()
+                        .any(|rel| rel.eq_ignore_ascii_case("canonical"))
+            });
+            is_canonical.then(|| resolve_href(url, target)).flatten()
+        })
+}
+
+// The `<target>; params` entries of a Link header. Targets may contain commas, and so may
+// quoted parameters, so entries are split on the commas between them only.
+fn link_entries(value: &str) -> Vec<(&str, &str)> {
+    let mut entries = Vec::new();
+    let mut rest = value;
+    while let Some(start) = rest.find('<') {
+        let Some(length) = rest[start..].find('>') else {
+            break;
+        };
+        let target = &rest[start + 1..start + length];
+        let after = &rest[start + length + 1..];
+
+        let mut quoted = false;
+        let end = after
+            .char_indices()
+            .find(|&(_, c)| {
+                if c == '"' {
+                    quoted = !quoted;
+                }
+                c == ',' && !quoted
+            })
+            .map_or(after.len(), |(index, _)| index);
+        entries.push((target.trim(), &after[..end]));
+        rest = &after[end..];
+    }
+    entries
+}
+
+// `<meta http-equiv="refresh" content="0; url=/new-page">`. A refresh without a URL
+// only reloads the page and isn't a redirect.
+fn meta_refresh(body: &str, url: &Url) -> Option<Url> {
+    // Only the head is parsed, and only when it mentions a refresh, since the whole page
+    // is parsed again by whoever reads it
+    let head = head_of(body);
+    if !head.to_ascii_lowercase().contains("refresh") {
+        return None;
+    }
+    let document = Html::parse_document(head);
+    let selector = Selector::parse("meta[http-equiv][content]").unwrap();
+    let content = document
+        .select(&selector)
+        .find(|meta| {
+            meta.value()
+                .attr("http-equiv")
+                .is_some_and(|equiv| equiv.eq_ignore_ascii_case("refresh"))
+        })?
+        .value()
+        .attr("content")?;
+
+    let (_, target) = content.split_once([';', ','])?;
+    let target = target.trim();
+    let target = match target.get(..4) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("url=") => &target[4..],
+        _ => target,
+    };
+    let target = target.trim().trim_matches(['\'', '"']);
+
+    resolve_href(&base_url(&document, url), target).filter(|target| target != url)
+}
+
+// The start of a page up to where its body begins
+fn head_of(body: &str) -> &str {
+    let end = body
+        .match_indices('<')
+        .map(|(index, _)| index)
+        .find(|&index| {
+            ["</head", "<body"].iter().any(|tag| {
+                body.as_bytes()
+                    .get(index..index + tag.len())
+                    .is_some_and(|start| start.eq_ignore_ascii_case(tag.as_bytes()))
+            })
+        })
+        .unwrap_or(body.len());
+    &body[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn link_header_entries_keep_commas_in_targets_and_quotes() {
+        let value =
+            r#"<https://example.com/a,b>; rel="canonical", </amp>; title="x, y"; rel=amphtml"#;
+        assert_eq!(
+            link_entries(value),
+            vec![
+                ("https://example.com/a,b", r#"; rel="canonical""#),
+                ("/amp", r#"; title="x, y"; rel=amphtml"#),
+            ]
+        );
+    }
+
+    #[test]
+    fn meta_refresh_is_read_from_the_head() {
+        let url = Url::parse("https://example.com/old").unwrap();
+        let page = r#"<html><head><META HTTP-EQUIV="Refresh" CONTENT="0; URL='/new'"></head>
+            <body><p>Moved</p></body></html>"#;
+        assert_eq!(
+            meta_refresh(page, &url).map(String::from),
+            Some("https://example.com/new".to_string())
+        );
+
+        let in_body = r#"<html><head></head><body><meta http-equiv="refresh" content="0; url=/new"></body></html>"#;
+        assert_eq!(meta_refresh(in_body, &url), None);
+        assert_eq!(head_of(in_body), "<html><head>");
+    }
+}
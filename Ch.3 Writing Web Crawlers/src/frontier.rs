@@ -0,0 +1,100 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use url::Url;
+
+use crate::sitemap::{ChangeFreq, SitemapEntry};
+
+// Pages without a sitemap entry rank as if they had the sitemap protocol's defaults
+const DEFAULT_PRIORITY: f32 = 0.5;
+const DEFAULT_CHANGEFREQ: ChangeFreq = ChangeFreq::Weekly;
+
+// What a sitemap says about a page, for ordering it within its depth. The priority is
+// kept in thousandths, so it orders exactly.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SitemapRank {
+    pub priority: u32,
+    pub changefreq: ChangeFreq,
+    pub lastmod: Option<DateTime<Utc>>,
+}
+
+impl SitemapRank {
+    pub fn of(hint: Option<&SitemapEntry>) -> Self {
+        let priority = hint
+            .and_then(|entry| entry.priority)
+            .unwrap_or(DEFAULT_PRIORITY);
+        Self {
+            priority: (priority * 1000.0).round() as u32,
+            changefreq: hint
+                .and_then(|entry| entry.changefreq)
+                .unwrap_or(DEFAULT_CHANGEFREQ),
+            lastmod: hint.and_then(|entry| entry.lastmod),
+        }
+    }
+}
+
+// Shallower pages come first, so the crawl stays breadth-first. Within a depth, pages
+// a sitemap marks as important, frequently changing or recently modified come first,
+// and everything else keeps discovery order.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct FrontierKey {
+    depth: usize,
+    priority: Reverse<u32>,
+    changefreq: ChangeFreq,
+    lastmod: Reverse<Option<DateTime<Utc>>>,
+    sequence: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct Frontier {
+    queue: BTreeMap<FrontierKey, Url>,
+    next_sequence: u64,
+}
+
+impl Frontier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    pub fn push(&mut self, url: Url, depth: usize) {
+        self.push_with_hint(url, depth, None);
+    }
+
+    pub fn push_with_hint(&mut self, url: Url, depth: usize, hint: Option<&SitemapEntry>) {
+        let rank = SitemapRank::of(hint);
+        let key = FrontierKey {
+            depth,
+            priority: Reverse(rank.priority),
+            changefreq: rank.changefreq,
+            lastmod: Reverse(rank.lastmod),
+            sequence: self.next_sequence,
+        };
+
+        self.next_sequence += 1;
+        self.queue.insert(key, url);
+    }
+
+    // Removes the first page, in frontier order, that `available` accepts
+    pub fn pop_where<F>(&mut self, available: F) -> Option<(Url, usize)>
+    where
+        F: Fn(&Url) -> bool,
+    {
+        let key = self
+            .queue
+            .iter()
+            .find(|(_, url)| available(url))
+            .map(|(key, _)| key.clone())?;
+
+        let url = self.queue.remove(&key)?;
+        Some((url, key.depth))
+    }
+}
@@ -0,0 +1,431 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::links::Link;
+
+const GRAPHML_KEYS: [(&str, &str, &str); 8] = [
+    ("url", "node", "string"),
+    ("internal", "node", "boolean"),
+    ("status", "node", "int"),
+    ("in_degree", "node", "int"),
+    ("out_degree", "node", "int"),
+    ("pagerank", "node", "double"),
+    ("anchor_text", "edge", "string"),
+    ("rel", "edge", "string"),
+];
+
+const GEXF_NODE_ATTRIBUTES: [(&str, &str); 5] = [
+    ("internal", "boolean"),
+    ("status", "integer"),
+    ("in_degree", "integer"),
+    ("out_degree", "integer"),
+    ("pagerank", "double"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    GraphMl,
+    Gexf,
+    Dot,
+    Csv,
+}
+
+impl GraphFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "graphml" => Some(Self::GraphMl),
+            "gexf" => Some(Self::Gexf),
+            "dot" | "gv" => Some(Self::Dot),
+            "csv" => Some(Self::Csv),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub url: String,
+    pub internal: bool,
+    pub status: Option<u16>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Edge {
+    pub source: usize,
+    pub target: usize,
+    pub anchor_text: String,
+    pub rel: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct LinkGraph {
+    nodes: Vec<Node>,
+    index: HashMap<String, usize>,
+    edges: Vec<Edge>,
+    roots: HashSet<usize>,
+}
+
+impl LinkGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    pub fn edges(&self) -> &[Edge] {
+        &self.edges
+    }
+
+    pub fn add_node(&mut self, url: &str, internal: bool) -> usize {
+        if let Some(&id) = self.index.get(url) {
+            return id;
+        }
+
+        let id = self.nodes.len();
+        self.nodes.push(Node {
+            url: url.to_string(),
+            internal,
+            status: None,
+        });
+        self.index.insert(url.to_string(), id);
+        id
+    }
+
+    // Roots are the pages a crawl starts from, so they are never reported as orphans
+    pub fn add_root(&mut self, url: &str) {
+        let id = self.add_node(url, true);
+        self.roots.insert(id);
+    }
+
+    pub fn set_status(&mut self, url: &str, internal: bool, status: u16) {
+        let id = self.add_node(url, internal);
+        self.nodes[id].status = Some(status);
+    }
+
+    pub fn add_edge(&mut self, source: &str, link: &Link, internal: bool) {
+        let source = self.add_node(source, true);
+        let target = self.add_node(link.url.as_str(), internal);
+        self.edges.push(Edge {
+            source,
+            target,
+            anchor_text: link.anchor_text.clone(),
+            rel: link.rel.clone(),
+        });
+    }
+
+    // Distinct targets per node, ignoring self-links and repeated links to the same page
+    fn adjacency(&self) -> Vec<HashSet<usize>> {
+        let mut adjacency = vec![HashSet::new(); self.nodes.len()];
+        for edge in self.edges.iter().filter(|edge| edge.source != edge.target) {
+            adjacency[edge.source].insert(edge.target);
+        }
+        adjacency
+    }
+
+    pub fn out_degree(&self) -> Vec<usize> {
+        self.adjacency().iter().map(HashSet::len).collect()
+    }
+
+    pub fn in_degree(&self) -> Vec<usize> {
+        let mut in_degree = vec![0; self.nodes.len()];
+        for targets in self.adjacency() {
+            for target in targets {
+                in_degree[target] += 1;
+            }
+        }
+        in_degree
+    }
+
+    pub fn page_rank(&self, damping: f64, iterations: usize) -> Vec<f64> {
+        let n = self.nodes.len();
+        if n == 0 {
+            return Vec::new();
+        }
+
+        let adjacency = self.adjacency();
+        let base = (1.0 - damping) / n as f64;
+        let mut ranks = vec![1.0 / n as f64; n];
+
+        for _ in 0..iterations {
+            // Rank held by pages without outgoing links is spread evenly over the graph
+            let dangling: f64 = adjacency
+                .iter()
+                .zip(&ranks)
+                .filter(|(targets, _)| targets.is_empty())
+                .map(|(_, rank)| rank)
+                .sum();
+
+            let mut next = vec![base + damping * dangling / n as f64; n];
+            for (source, targets) in adjacency.iter().enumerate() {
+                let share = damping * ranks[source] / targets.len().max(1) as f64;
+                for &target in targets {
+                    next[target] += share;
+                }
+            }
+
+            ranks = next;
+        }
+
+        ranks
+    }
+
+    // Crawled internal pages that no other page links to
+    pub fn orphan_pages(&self) -> Vec<&Node> {
+        let in_degree = self.in_degree();
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|&(id, node)| {
+                node.internal
+                    && node.status.is_some()
+                    && in_degree[id] == 0
+                    && !self.roots.contains(&id)
+            })
+            .map(|(_, node)| node)
+            .collect()
+    }
+
+    pub fn export<W: Write>(&self, format: GraphFormat, writer: W) -> io::Result<()> {
+        match format {
+            GraphFormat::GraphMl => self.write_graphml(writer),
+            GraphFormat::Gexf => self.write_gexf(writer),
+            GraphFormat::Dot => self.write_dot(writer),
+            GraphFormat::Csv => self.write_csv(writer),
+        }
+    }
+
+    fn write_graphml<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let in_degree = self.in_degree();
+        let out_degree = self.out_degree();
+        let ranks = self.page_rank(0.85, 50);
+
+        writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            writer,
+            r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+        )?;
+        for (key, domain, kind) in GRAPHML_KEYS {
+            writeln!(
+                writer,
+                r#"  <key id="{}" for="{}" attr.name="{}" attr.type="{}"/>"#,
+                key, domain, key, kind
+            )?;
+        }
+        writeln!(writer, r#"  <graph id="links" edgedefault="directed">"#)?;
+
+        for (id, node) in self.nodes.iter().enumerate() {
+            writeln!(writer, r#"    <node id="n{}">"#, id)?;
+            writeln!(
+                writer,
+                r#"      <data key="url">{}</data>"#,
+                escape_xml(&node.url)
+            )?;
+            writeln!(
+                writer,
+                r#"      <data key="internal">{}</data>"#,
+                node.internal
+            )?;
+            if let Some(status) = node.status {
+                writeln!(writer, r#"      <data key="status">{}</data>"#, status)?;
+            }
+            writeln!(
+                writer,
+                r#"      <data key="in_degree">{}</data>"#,
+                in_degree[id]
+            )?;
+            writeln!(
+                writer,
+                r#"      <data key="out_degree">{}</data>"#,
+                out_degree[id]
+            )?;
+            writeln!(writer, r#"      <data key="pagerank">{}</data>"#, ranks[id])?;
+            writeln!(writer, "    </node>")?;
+        }
+
+        for (id, edge) in self.edges.iter().enumerate() {
+            writeln!(
+                writer,
+                r#"    <edge id="e{}" source="n{}" target="n{}">"#,
+                id, edge.source, edge.target
+            )?;
+            writeln!(
+                writer,
+                r#"      <data key="anchor_text">{}</data>"#,
+                escape_xml(&edge.anchor_text)
+            )?;
+            writeln!(
+                writer,
+                r#"      <data key="rel">{}</data>"#,
+                escape_xml(&edge.rel.join(" "))
+            )?;
+            writeln!(writer, "    </edge>")?;
+        }
+
+        writeln!(writer, "  </graph>")?;
+        writeln!(writer, "</graphml>")
+    }
+
+    fn write_gexf<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let in_degree = self.in_degree();
+        let out_degree = self.out_degree();
+        let ranks = self.page_rank(0.85, 50);
+
+        writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            writer,
+            r#"<gexf xmlns="http://gexf.net/1.3" version="1.3">"#
+        )?;
+        writeln!(writer, r#"  <graph defaultedgetype="directed">"#)?;
+        writeln!(writer, r#"    <attributes class="node">"#)?;
+        for (id, (title, kind)) in GEXF_NODE_ATTRIBUTES.iter().enumerate() {
+            writeln!(
+                writer,
+                r#"      <attribute id="{}" title="{}" type="{}"/>"#,
+                id, title, kind
+            )?;
+        }
+        writeln!(writer, "    </attributes>")?;
+        writeln!(writer, r#"    <attributes class="edge">"#)?;
+        writeln!(
+            writer,
+            r#"      <attribute id="0" title="rel" type="string"/>"#
+        )?;
+        writeln!(writer, "    </attributes>")?;
+
+        writeln!(writer, "    <nodes>")?;
+        for (id, node) in self.nodes.iter().enumerate() {
+            writeln!(
+                writer,
+                r#"      <node id="{}" label="{}">"#,
+                id,
+                escape_xml(&node.url)
+            )?;
+            writeln!(writer, "        <attvalues>")?;
+            writeln!(
+                writer,
+                r#"          <attvalue for="0" value="{}"/>"#,
+                node.internal
+            )?;
+            if let Some(status) = node.status {
+                writeln!(
+                    writer,
+                    r#"          <attvalue for="1" value="{}"/>"#,
+                    status
+                )?;
+            }
+            writeln!(
+                writer,
+                r#"          <attvalue for="2" value="{}"/>"#,
+                in_degree[id]
+            )?;
+            writeln!(
+                writer,
+                r#"          <attvalue for="3" value="{}"/>"#,
+                out_degree[id]
+            )?;
+            writeln!(
+                writer,
+                r#"          <attvalue for="4" value="{}"/>"#,
+                ranks[id]
+            )?;
+            writeln!(writer, "        </attvalues>")?;
+            writeln!(writer, "      </node>")?;
+        }
+        writeln!(writer, "    </nodes>")?;
+
+        writeln!(writer, "    <edges>")?;
+        for (id, edge) in self.edges.iter().enumerate() {
+            writeln!(
+                writer,
+                r#"      <edge id="{}" source="{}" target="{}" label="{}">"#,
+                id,
+                edge.source,
+                edge.target,
+                escape_xml(&edge.anchor_text)
+            )?;
+            writeln!(writer, "        <attvalues>")?;
+            writeln!(
+                writer,
+                r#"          <attvalue for="0" value="{}"/>"#,
+                escape_xml(&edge.rel.join(" "))
+            )?;
+            writeln!(writer, "        </attvalues>")?;
+            writeln!(writer, "      </edge>")?;
+        }
+        writeln!(writer, "    </edges>")?;
+
+        writeln!(writer, "  </graph>")?;
+        writeln!(writer, "</gexf>")
+    }
+
+    fn write_dot<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "digraph links {{")?;
+
+        for (id, node) in self.nodes.iter().enumerate() {
+            let shape = if node.internal { "box" } else { "ellipse" };
+            let status = node
+                .status
+                .map(|status| status.to_string())
+                .unwrap_or_default();
+            writeln!(
+                writer,
+                "    n{} [label=\"{}\", shape={}, status=\"{}\"];",
+                id,
+                escape_dot(&node.url),
+                shape,
+                status
+            )?;
+        }
+
+        for edge in &self.edges {
+            writeln!(
+                writer,
+                "    n{} -> n{} [label=\"{}\", rel=\"{}\"];",
+                edge.source,
+                edge.target,
+                escape_dot(&edge.anchor_text),
+                escape_dot(&edge.rel.join(" "))
+            )?;
+        }
+
+        writeln!(writer, "}}")
+    }
+
+    fn write_csv<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut csv_writer = csv::Writer::from_writer(writer);
+        csv_writer.write_record(["source", "target", "anchor_text", "rel", "target_status"])?;
+
+        for edge in &self.edges {
+            let target = &self.nodes[edge.target];
+            let status = target
+                .status
+                .map(|status| status.to_string())
+                .unwrap_or_default();
+            csv_writer.write_record([
+                self.nodes[edge.source].url.as_str(),
+                target.url.as_str(),
+                edge.anchor_text.as_str(),
+                edge.rel.join(" ").as_str(),
+                status.as_str(),
+            ])?;
+        }
+
+        csv_writer.flush()
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
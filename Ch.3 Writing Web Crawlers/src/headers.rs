@@ -0,0 +1,195 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use rand::Rng;
+use reqwest::header::{HeaderName, HeaderValue, ACCEPT, ACCEPT_LANGUAGE, REFERER, USER_AGENT};
+use reqwest::RequestBuilder;
+use serde::Deserialize;
+use url::Url;
+
+use crate::error::AppError;
+
+// The profile of sites and commands that don't name one
+pub const DEFAULT_PROFILE: &str = "default";
+
+// What browsers ask for when they navigate to a page
+const HTML_ACCEPT: &str = "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rotation {
+    // Always the first user agent of the pool
+    #[default]
+    Off,
+    // The user agents of the pool in turn, one per request
+    RoundRobin,
+    // A user agent of the pool picked at random for every request
+    Random,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RefererPolicy {
+    #[default]
+    None,
+    // The origin of the requested page, like a visitor coming from another page of the
+    // same site
+    Origin,
+}
+
+// The headers sent with every request for a page
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HeaderProfile {
+    // Without any, the client's own user agent is sent
+    pub user_agents: Vec<String>,
+    pub rotation: Rotation,
+    pub accept: Option<String>,
+    pub accept_language: Option<String>,
+    pub referer: RefererPolicy,
+    // Any other headers, by name. A Referer here is sent as it is.
+    pub headers: BTreeMap<String, String>,
+}
+
+impl HeaderProfile {
+    // What a crawl should send unless a site needs something else: the crawler's name
+    // and version, and a URL where the site's owners can find out who runs it
+    pub fn identify(product: &str, contact: Option<&str>) -> Self {
+        let user_agent = match contact {
+            Some(contact) => format!("{} (+{})", product, contact),
+            None => product.to_string(),
+        };
+        Self {
+            user_agents: vec![user_agent],
+            accept: Some(HTML_ACCEPT.to_string()),
+            ..Self::default()
+        }
+    }
+
+    // The user agent for clients that send a single one, like the one reading robots.txt.
+    // robots.txt rules are matched against it too.
+    pub fn user_agent<'a>(&'a self, default: &'a str) -> &'a str {
+        self.user_agents.first().map_or(default, String::as_str)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        for user_agent in &self.user_agents {
+            HeaderValue::from_str(user_agent)
+                .map_err(|_| format!("invalid user agent `{}`", user_agent))?;
+        }
+        let values = [&self.accept, &self.accept_language];
+        for value in values.into_iter().flatten() {
+            HeaderValue::from_str(value).map_err(|_| format!("invalid value `{}`", value))?;
+        }
+        for (name, value) in &self.headers {
+            HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("invalid header name `{}`", name))?;
+            HeaderValue::from_str(value)
+                .map_err(|_| format!("invalid value `{}` for {}", value, name))?;
+        }
+        Ok(())
+    }
+}
+
+// Named header profiles. There is always a `default` one.
+#[derive(Debug, Clone)]
+pub struct HeaderProfiles {
+    profiles: BTreeMap<String, HeaderProfile>,
+}
+
+impl HeaderProfiles {
+    pub fn new(default: HeaderProfile) -> Self {
+        Self {
+            profiles: BTreeMap::from([(DEFAULT_PROFILE.to_string(), default)]),
+        }
+    }
+
+    // Adds the profiles of a TOML file with a table for each. A profile without user
+    // agents sends the default profile's, and one named `default` replaces it.
+    pub fn load(&mut self, path: &Path) -> Result<(), AppError> {
+        let text = std::fs::read_to_string(path)?;
+        let profiles: BTreeMap<String, HeaderProfile> = toml::from_str(&text)
+            .map_err(|err| AppError::HeaderProfiles(path.to_path_buf(), err.to_string()))?;
+
+        if let Some(default) = profiles.get(DEFAULT_PROFILE) {
+            self.add(DEFAULT_PROFILE, default.clone())
+                .map_err(|err| AppError::HeaderProfiles(path.to_path_buf(), err))?;
+        }
+        for (name, profile) in profiles {
+            self.add(&name, profile)
+                .map_err(|err| AppError::HeaderProfiles(path.to_path_buf(), err))?;
+        }
+        Ok(())
+    }
+
+    fn add(&mut self, name: &str, mut profile: HeaderProfile) -> Result<(), String> {
+        profile
+            .validate()
+            .map_err(|err| format!("profile {}: {}", name, err))?;
+        if profile.user_agents.is_empty() {
+            profile.user_agents = self.profiles[DEFAULT_PROFILE].user_agents.clone();
+        }
+        self.profiles.insert(name.to_string(), profile);
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Result<&HeaderProfile, AppError> {
+        self.profiles
+            .get(name)
+            .ok_or_else(|| AppError::UnknownHeaderProfile(name.to_string()))
+    }
+
+    pub fn default_profile(&self) -> &HeaderProfile {
+        &self.profiles[DEFAULT_PROFILE]
+    }
+}
+
+// A profile applied to requests. Clones share their place in the user agent rotation.
+#[derive(Debug, Clone)]
+pub struct RequestHeaders {
+    profile: Arc<HeaderProfile>,
+    next: Arc<AtomicUsize>,
+}
+
+impl RequestHeaders {
+    pub fn new(profile: HeaderProfile) -> Self {
+        Self {
+            profile: Arc::new(profile),
+            next: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    fn user_agent(&self) -> Option<&str> {
+        let pool = &self.profile.user_agents;
+        if pool.is_empty() {
+            return None;
+        }
+        let index = match self.profile.rotation {
+            Rotation::Off => 0,
+            Rotation::RoundRobin => self.next.fetch_add(1, Ordering::Relaxed) % pool.len(),
+            Rotation::Random => rand::thread_rng().gen_range(0..pool.len()),
+        };
+        Some(&pool[index])
+    }
+
+    pub fn apply(&self, mut request: RequestBuilder, url: &Url) -> RequestBuilder {
+        if let Some(user_agent) = self.user_agent() {
+            request = request.header(USER_AGENT, user_agent);
+        }
+        if let Some(accept) = &self.profile.accept {
+            request = request.header(ACCEPT, accept);
+        }
+        if let Some(language) = &self.profile.accept_language {
+            request = request.header(ACCEPT_LANGUAGE, language);
+        }
+        if self.profile.referer == RefererPolicy::Origin {
+            request = request.header(REFERER, format!("{}/", url.origin().ascii_serialization()));
+        }
+        for (name, value) in &self.profile.headers {
+            request = request.header(name.as_str(), value);
+        }
+        request
+    }
+}
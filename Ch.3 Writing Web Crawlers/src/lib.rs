@@ -0,0 +1,18 @@
+pub mod crawler;
+pub mod dedup;
+pub mod error;
+pub mod fetch;
+pub mod frontier;
+pub mod graph;
+pub mod headers;
+pub mod link_checker;
+pub mod links;
+pub mod logging;
+pub mod metrics;
+pub mod path_finder;
+pub mod random_walk;
+pub mod request_log;
+pub mod robots;
+pub mod scope;
+pub mod sitemap;
+pub mod state;
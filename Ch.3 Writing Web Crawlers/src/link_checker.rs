@@ -0,0 +1,645 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use percent_encoding::percent_decode_str;
+use reqwest::Method;
+use scraper::{Html, Selector};
+use tokio::task::JoinSet;
+use url::Url;
+
+use crate::crawler::USER_AGENT;
+use crate::error::AppError;
+use crate::fetch::{Fetch, FetchStop, PageFetcher};
+use crate::headers::{HeaderProfile, RequestHeaders};
+use crate::links::{base_url, normalize_url};
+use crate::robots::RobotsGate;
+use crate::scope::Scope;
+
+#[derive(Debug, Clone)]
+pub struct LinkCheckConfig {
+    // Internal pages up to this many links from the starting page are searched for links
+    pub max_depth: usize,
+    pub max_pages: usize,
+    pub workers: usize,
+    // For each URL, including its redirects but not the waits for Crawl-delay
+    pub timeout: Duration,
+    pub respect_robots: bool,
+    pub check_external: bool,
+    pub scope: Scope,
+    // Also sent when checking external links
+    pub headers: HeaderProfile,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkProblem {
+    Status(u16),
+    Error(String),
+    Redirect(FetchStop),
+    MissingFragment(String),
+}
+
+impl fmt::Display for LinkProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Status(status) => write!(f, "HTTP {}", status),
+            Self::Error(message) => write!(f, "{}", message),
+            Self::Redirect(stop) => write!(f, "{}", stop),
+            Self::MissingFragment(fragment) => write!(f, "no element with id \"{}\"", fragment),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CheckedLink {
+    // As linked, including any #fragment
+    pub target: String,
+    pub anchor_text: String,
+    pub method: Method,
+    pub status: Option<u16>,
+    pub elapsed: Duration,
+    // Where the target redirects to
+    pub redirect: Option<String>,
+    pub problem: Option<LinkProblem>,
+    // robots.txt disallows the target, so it wasn't requested
+    pub skipped: bool,
+}
+
+impl CheckedLink {
+    fn outcome(&self) -> Option<String> {
+        match &self.problem {
+            Some(problem) => Some(problem.to_string()),
+            None => self.skipped.then(|| "skipped (robots)".to_string()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct LinkCheckReport {
+    pub start_url: String,
+    pub pages_crawled: usize,
+    pub urls_checked: usize,
+    // Links by the page they were found on
+    pub pages: BTreeMap<String, Vec<CheckedLink>>,
+    pub elapsed: Duration,
+}
+
+impl LinkCheckReport {
+    pub fn links(&self) -> impl Iterator<Item = (&str, &CheckedLink)> {
+        self.pages
+            .iter()
+            .flat_map(|(source, links)| links.iter().map(move |link| (source.as_str(), link)))
+    }
+
+    pub fn broken_links(&self) -> usize {
+        self.links()
+            .filter(|(_, link)| link.problem.is_some())
+            .count()
+    }
+
+    // Lists only the broken links unless `all` is set
+    pub fn print(&self, all: bool) {
+        println!("\nLink check of {}", self.start_url);
+        println!("  Pages crawled: {}", self.pages_crawled);
+        println!("  URLs checked: {}", self.urls_checked);
+        println!("  Links: {}", self.links().count());
+        println!("  Broken links: {}", self.broken_links());
+        println!("  Elapsed: {:.2}s", self.elapsed.as_secs_f64());
+
+        for (source, links) in &self.pages {
+            let shown = links
+                .iter()
+                .filter(|link| all || link.problem.is_some())
+                .collect::<Vec<_>>();
+            if shown.is_empty() {
+                continue;
+            }
+
+            println!("\n{}", source);
+            for link in shown {
+                let status = link
+                    .status
+                    .map(|status| status.to_string())
+                    .unwrap_or_else(|| "---".to_string());
+                let mut line = format!(
+                    "  {} {} {} ({} ms)",
+                    status,
+                    link.method,
+                    link.target,
+                    link.elapsed.as_millis()
+                );
+                if let Some(redirect) = &link.redirect {
+                    line.push_str(&format!(" -> {}", redirect));
+                }
+                if let Some(outcome) = link.outcome() {
+                    line.push_str(&format!(": {}", outcome));
+                }
+                println!("{}", line);
+            }
+        }
+    }
+
+    pub fn write_csv<W: std::io::Write>(&self, writer: W) -> std::io::Result<()> {
+        let mut csv_writer = csv::Writer::from_writer(writer);
+        csv_writer.write_record([
+            "source",
+            "target",
+            "anchor_text",
+            "method",
+            "status",
+            "elapsed_ms",
+            "redirect",
+            "problem",
+        ])?;
+
+        for (source, link) in self.links() {
+            csv_writer.write_record([
+                source,
+                link.target.as_str(),
+                link.anchor_text.as_str(),
+                link.method.as_str(),
+                &link
+                    .status
+                    .map(|status| status.to_string())
+                    .unwrap_or_default(),
+                &link.elapsed.as_millis().to_string(),
+                link.redirect.as_deref().unwrap_or_default(),
+                &link.outcome().unwrap_or_default(),
+            ])?;
+        }
+
+        csv_writer.flush()
+    }
+}
+
+// A link as found on a page, before it is checked
+#[derive(Debug)]
+struct FoundLink {
+    source: Url,
+    url: Url,
+    fragment: Option<String>,
+    anchor_text: String,
+}
+
+// The outcome of requesting one URL, shared by every link pointing at it
+#[derive(Debug, Clone)]
+struct TargetCheck {
+    method: Method,
+    status: Option<u16>,
+    elapsed: Duration,
+    redirect: Option<Url>,
+    stop: Option<FetchStop>,
+    error: Option<String>,
+    // Ids on the page the URL ends on, when it was downloaded
+    ids: Option<HashSet<String>>,
+}
+
+impl TargetCheck {
+    fn new(method: Method, fetch: &Fetch, started: Instant) -> Self {
+        Self {
+            method,
+            // A chain that stopped early never got a final response
+            status: fetch
+                .status
+                .or_else(|| fetch.redirects.last().map(|redirect| redirect.status)),
+            elapsed: started.elapsed(),
+            redirect: (!fetch.redirects.is_empty()).then(|| fetch.url.clone()),
+            stop: fetch.stop,
+            error: None,
+            ids: None,
+        }
+    }
+
+    fn failed(method: Method, started: Instant, error: String) -> Self {
+        Self {
+            method,
+            status: None,
+            elapsed: started.elapsed(),
+            redirect: None,
+            stop: None,
+            error: Some(error),
+            ids: None,
+        }
+    }
+
+    fn problem(&self) -> Option<LinkProblem> {
+        if let Some(error) = &self.error {
+            return Some(LinkProblem::Error(error.clone()));
+        }
+        match (self.stop, self.status) {
+            (Some(stop @ (FetchStop::RedirectLoop | FetchStop::TooManyRedirects)), _) => {
+                Some(LinkProblem::Redirect(stop))
+            }
+            (Some(_), _) => None,
+            (None, Some(status)) if status >= 400 => Some(LinkProblem::Status(status)),
+            (None, _) => None,
+        }
+    }
+
+    fn check_link(&self, link: &FoundLink) -> CheckedLink {
+        // Fragments can only be looked up on pages that were downloaded
+        let problem = self.problem().or_else(|| {
+            let fragment = link.fragment.as_deref()?;
+            let ids = self.ids.as_ref()?;
+            (!has_fragment(ids, fragment)).then(|| LinkProblem::MissingFragment(fragment.into()))
+        });
+
+        let mut target = link.url.clone();
+        target.set_fragment(link.fragment.as_deref());
+
+        CheckedLink {
+            target: target.to_string(),
+            anchor_text: link.anchor_text.clone(),
+            method: self.method.clone(),
+            status: self.status,
+            elapsed: self.elapsed,
+            redirect: self.redirect.as_ref().map(Url::to_string),
+            problem,
+            skipped: self.stop == Some(FetchStop::Disallowed),
+        }
+    }
+}
+
+// Crawls a site for its links, then checks every link found, internal and external.
+// robots.txt decides which pages are crawled and which links are requested; each
+// allowed link is checked with a single request, the way a visitor following it would.
+pub struct LinkChecker {
+    fetcher: PageFetcher,
+    robots: Option<Arc<RobotsGate>>,
+    config: LinkCheckConfig,
+}
+
+impl LinkChecker {
+    pub fn new(config: LinkCheckConfig) -> Result<Self, AppError> {
+        let user_agent = config.headers.user_agent(USER_AGENT);
+        let client = reqwest::Client::builder().user_agent(user_agent).build()?;
+        let robots = config
+            .respect_robots
+            .then(|| Arc::new(RobotsGate::new(client, user_agent)));
+        let fetcher = PageFetcher::new(user_agent)?
+            .with_headers(RequestHeaders::new(config.headers.clone()))
+            .with_timeout(config.timeout);
+
+        Ok(Self {
+            fetcher,
+            robots,
+            config,
+        })
+    }
+
+    pub async fn check(&self, starting_site: &str) -> Result<LinkCheckReport, AppError> {
+        let start_url = normalize_url(Url::parse(starting_site)?)
+            .ok_or_else(|| AppError::UnsupportedScheme(starting_site.to_string()))?;
+        let scope = Arc::new(self.config.scope.clone().for_start(&start_url));
+        let started = Instant::now();
+
+        let (mut targets, found, pages_crawled) = self.crawl(&start_url, &scope).await;
+        self.check_targets(&mut targets, &found, &scope).await;
+
+        let mut pages: BTreeMap<String, Vec<CheckedLink>> = BTreeMap::new();
+        for link in &found {
+            if let Some(target) = targets.get(link.url.as_str()) {
+                pages
+                    .entry(link.source.to_string())
+                    .or_default()
+                    .push(target.check_link(link));
+            }
+        }
+
+        Ok(LinkCheckReport {
+            start_url: start_url.to_string(),
+            pages_crawled,
+            urls_checked: targets.len(),
+            pages,
+            elapsed: started.elapsed(),
+        })
+    }
+
+    // Fetches internal pages breadth first and collects their links. Each page's fetch
+    // also serves as the check of the links pointing at it.
+    async fn crawl(
+        &self,
+        start_url: &Url,
+        scope: &Arc<Scope>,
+    ) -> (HashMap<String, TargetCheck>, Vec<FoundLink>, usize) {
+        let mut targets = HashMap::new();
+        let mut found = Vec::new();
+        let mut seen = HashSet::from([start_url.to_string()]);
+        // Pages reached through several redirecting URLs are only read once
+        let mut read = HashSet::new();
+        let mut pending = VecDeque::from([(start_url.clone(), 0)]);
+        let mut pages_crawled = 0;
+        let mut workers = JoinSet::new();
+
+        loop {
+            while workers.len() < self.config.workers.max(1)
+                && pages_crawled < self.config.max_pages
+            {
+                let Some((url, depth)) = pending.pop_front() else {
+                    break;
+                };
+                pages_crawled += 1;
+
+                let fetcher = self.fetcher.clone();
+                let robots = self.robots.clone();
+                let scope = scope.clone();
+                let timeout = self.config.timeout;
+                workers.spawn(async move {
+                    let (check, final_url, links) =
+                        crawl_page(&fetcher, robots.as_deref(), &scope, &url, timeout).await;
+                    (url, depth, check, final_url, links)
+                });
+            }
+
+            let Some(joined) = workers.join_next().await else {
+                break;
+            };
+            let Ok((url, depth, check, final_url, links)) = joined else {
+                continue;
+            };
+
+            // Pages that weren't crawled are checked like external links instead
+            if !matches!(
+                check.stop,
+                Some(FetchStop::Disallowed | FetchStop::OutOfScope)
+            ) {
+                targets.insert(url.to_string(), check);
+            }
+            if !read.insert(final_url.to_string()) {
+                continue;
+            }
+
+            for link in links {
+                if depth < self.config.max_depth
+                    && scope.contains(&link.url)
+                    && seen.insert(link.url.to_string())
+                {
+                    pending.push_back((link.url.clone(), depth + 1));
+                }
+                found.push(link);
+            }
+        }
+
+        (targets, found, pages_crawled)
+    }
+
+    // Checks every linked URL the crawl didn't fetch: external links, and internal
+    // pages beyond the crawl's limits. URLs robots.txt disallows aren't requested.
+    async fn check_targets(
+        &self,
+        targets: &mut HashMap<String, TargetCheck>,
+        found: &[FoundLink],
+        scope: &Scope,
+    ) {
+        let mut unchecked: HashMap<String, (Url, bool)> = HashMap::new();
+        for link in found {
+            if targets.contains_key(link.url.as_str())
+                || (!self.config.check_external && !scope.contains(&link.url))
+            {
+                continue;
+            }
+            let (_, needs_ids) = unchecked
+                .entry(link.url.to_string())
+                .or_insert_with(|| (link.url.clone(), false));
+            *needs_ids |= link.fragment.is_some();
+        }
+
+        let mut pending = unchecked.into_values();
+        let mut workers = JoinSet::new();
+        loop {
+            while workers.len() < self.config.workers.max(1) {
+                let Some((url, needs_ids)) = pending.next() else {
+                    break;
+                };
+                let fetcher = self.fetcher.clone();
+                let robots = self.robots.clone();
+                let timeout = self.config.timeout;
+                workers.spawn(async move {
+                    let check =
+                        check_url(&fetcher, robots.as_deref(), &url, needs_ids, timeout).await;
+                    (url, check)
+                });
+            }
+
+            let Some(joined) = workers.join_next().await else {
+                break;
+            };
+            if let Ok((url, check)) = joined {
+                targets.insert(url.to_string(), check);
+            }
+        }
+    }
+}
+
+async fn crawl_page(
+    fetcher: &PageFetcher,
+    robots: Option<&RobotsGate>,
+    scope: &Scope,
+    url: &Url,
+    timeout: Duration,
+) -> (TargetCheck, Url, Vec<FoundLink>) {
+    let started = Instant::now();
+    let mut fetch = match fetcher.fetch(robots, scope, url).await {
+        Ok(fetch) => fetch,
+        Err(err) => {
+            let check = TargetCheck::failed(Method::GET, started, failure(err, timeout));
+            return (check, url.clone(), Vec::new());
+        }
+    };
+
+    let body = fetch.body.take();
+    let mut check = TargetCheck::new(Method::GET, &fetch, started);
+    let success = fetch.stop.is_none() && check.problem().is_none();
+    let links = match body.filter(|_| success) {
+        Some(body) => {
+            let document = Html::parse_document(&body);
+            check.ids = Some(element_ids(&document));
+            page_links(&document, &fetch.url)
+        }
+        None => Vec::new(),
+    };
+
+    (check, fetch.url, links)
+}
+
+// Tries a HEAD request first, since it doesn't download the page. Links with a
+// #fragment need the page itself to look the fragment up.
+async fn check_url(
+    fetcher: &PageFetcher,
+    robots: Option<&RobotsGate>,
+    url: &Url,
+    needs_ids: bool,
+    timeout: Duration,
+) -> TargetCheck {
+    let started = Instant::now();
+    if !needs_ids {
+        let head = fetcher.check(Method::HEAD, robots, url).await;
+        // Some servers reject or mishandle HEAD, so an error status is confirmed with GET
+        if let Ok(fetch) = head {
+            let check = TargetCheck::new(Method::HEAD, &fetch, started);
+            if !matches!(check.problem(), Some(LinkProblem::Status(_))) {
+                return check;
+            }
+        }
+    }
+
+    match fetcher.check(Method::GET, robots, url).await {
+        Ok(mut fetch) => {
+            let body = fetch.body.take();
+            let mut check = TargetCheck::new(Method::GET, &fetch, started);
+            check.ids = body.map(|body| element_ids(&Html::parse_document(&body)));
+            check
+        }
+        Err(err) => TargetCheck::failed(Method::GET, started, failure(err, timeout)),
+    }
+}
+
+fn failure(err: AppError, timeout: Duration) -> String {
+    match err {
+        AppError::HttpRequest(err) if err.is_timeout() => {
+            format!("timed out after {}s", timeout.as_secs_f64())
+        }
+        err => err.to_string(),
+    }
+}
+
+// Unlike `find_links`, keeps links to other parts of the same page and the fragment
+// of every link
+fn page_links(document: &Html, page_url: &Url) -> Vec<FoundLink> {
+    let selector = Selector::parse("a[href]").unwrap();
+    let base_url = base_url(document, page_url);
+
+    document
+        .select(&selector)
+        .filter_map(|element| {
+            let href = element.value().attr("href")?.trim();
+            let url = base_url.join(href).ok()?;
+            let fragment = url.fragment().map(str::to_string);
+            let anchor_text = element
+                .text()
+                .flat_map(str::split_whitespace)
+                .collect::<Vec<_>>()
+                .join(" ");
+
+            Some(FoundLink {
+                source: page_url.clone(),
+                url: normalize_url(url)?,
+                fragment,
+                anchor_text,
+            })
+        })
+        .collect()
+}
+
+// Fragments point at an element's id, or at the name of an `<a>`
+fn element_ids(document: &Html) -> HashSet<String> {
+    let selector = Selector::parse("[id], a[name]").unwrap();
+    let mut ids = HashSet::new();
+
+    for element in document.select(&selector) {
+        let element = element.value();
+        ids.extend(element.id().map(str::to_string));
+        if element.name() == "a" {
+            ids.extend(element.attr("name").map(str::to_string));
+        }
+    }
+    ids
+}
+
+// An empty fragment, `#top` and text fragments (`#:~:text=...`) work on any page
+fn has_fragment(ids: &HashSet<String>, fragment: &str) -> bool {
+    let decoded = percent_decode_str(fragment).decode_utf8_lossy();
+    fragment.is_empty()
+        || fragment.eq_ignore_ascii_case("top")
+        || fragment.starts_with(":~:")
+        || ids.contains(fragment)
+        || ids.contains(decoded.as_ref())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use std::sync::Mutex;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    // A site whose robots.txt disallows /private and asks for a second between requests
+    async fn polite_site() -> (Url, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let origin = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        let requested = Arc::new(Mutex::new(Vec::new()));
+
+        let log = requested.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buffer = [0; 1024];
+                let read = stream.read(&mut buffer).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&buffer[..read]);
+                let path = request.split_whitespace().nth(1).unwrap_or("").to_string();
+                let (content_type, body) = match path.as_str() {
+                    "/robots.txt" => (
+                        "text/plain",
+                        "User-agent: *\nDisallow: /private\nCrawl-delay: 1\n",
+                    ),
+                    "/" => (
+                        "text/html",
+                        r#"<html><body><a href="/a">A</a><a href="/private">Private</a></body></html>"#,
+                    ),
+                    _ => ("text/html", "<html><body>Page</body></html>"),
+                };
+                log.lock().unwrap().push(path);
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    content_type,
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+
+        (origin, requested)
+    }
+
+    #[tokio::test]
+    async fn robots_txt_is_honored_without_eating_into_the_timeout() {
+        let (origin, requested) = polite_site().await;
+        let config = LinkCheckConfig {
+            max_depth: 1,
+            max_pages: 10,
+            workers: 2,
+            timeout: Duration::from_millis(500),
+            respect_robots: true,
+            check_external: true,
+            scope: Scope::new(),
+            headers: HeaderProfile::identify(USER_AGENT, None),
+        };
+
+        let report = LinkChecker::new(config)
+            .unwrap()
+            .check(origin.as_str())
+            .await
+            .unwrap();
+
+        let links = report.links().map(|(_, link)| link).collect::<Vec<_>>();
+        assert_eq!(links.len(), 2);
+        let page = links
+            .iter()
+            .find(|link| link.target.ends_with("/a"))
+            .unwrap();
+        assert_eq!(page.status, Some(200));
+        assert_eq!(page.problem, None);
+        let private = links
+            .iter()
+            .find(|link| link.target.ends_with("/private"))
+            .unwrap();
+        assert!(private.skipped);
+        assert_eq!(private.outcome().as_deref(), Some("skipped (robots)"));
+        assert_eq!(report.broken_links(), 0);
+        assert!(!requested.lock().unwrap().contains(&"/private".to_string()));
+    }
+}
@@ -0,0 +1,270 @@
+use scraper::{ElementRef, Html, Selector};
+use url::Url;
+
+// Query parameters that only identify a campaign or click and never change the page
+const TRACKING_PARAMS: [&str; 9] = [
+    "fbclid", "gclid", "dclid", "msclkid", "yclid", "igshid", "mc_cid", "mc_eid", "_ga",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    pub url: Url,
+    pub anchor_text: String,
+    pub rel: Vec<String>,
+}
+
+impl Link {
+    fn from_element(element: &ElementRef, url: Url) -> Self {
+        let anchor_text = element
+            .text()
+            .flat_map(str::split_whitespace)
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let rel = element
+            .value()
+            .attr("rel")
+            .map(|rel| rel.split_whitespace().map(str::to_lowercase).collect())
+            .unwrap_or_default();
+
+        Self {
+            url,
+            anchor_text,
+            rel,
+        }
+    }
+}
+
+pub fn find_links(html: &str, page_url: &Url) -> Vec<Link> {
+    find_document_links(&Html::parse_document(html), page_url)
+}
+
+// For callers that also need the parsed page for something else
+pub fn find_document_links(document: &Html, page_url: &Url) -> Vec<Link> {
+    let a_selector = Selector::parse("a[href]").unwrap();
+    let base_url = base_url(document, page_url);
+    let mut links = Vec::new();
+
+    for element in document.select(&a_selector) {
+        if let Some(href) = element.value().attr("href") {
+            if let Some(url) = resolve_href(&base_url, href) {
+                links.push(Link::from_element(&element, url));
+            }
+        }
+    }
+
+    links
+}
+
+// Relative links resolve against `<base href>` when the page declares one
+pub fn base_url(document: &Html, page_url: &Url) -> Url {
+    let base_selector = Selector::parse("base[href]").unwrap();
+    document
+        .select(&base_selector)
+        .next()
+        .and_then(|base| base.value().attr("href"))
+        .and_then(|href| page_url.join(href.trim()).ok())
+        .unwrap_or_else(|| page_url.clone())
+}
+
+pub fn resolve_href(base_url: &Url, href: &str) -> Option<Url> {
+    let href = href.trim();
+
+    // In-page anchors point back at the current page rather than to another one
+    if href.is_empty() || href.starts_with('#') {
+        return None;
+    }
+
+    normalize_url(base_url.join(href).ok()?)
+}
+
+// Parsing already lowercases the host, drops default ports and resolves dot segments.
+// On top of that, fragments and tracking parameters are removed and the remaining
+// query parameters are sorted so equivalent links compare equal.
+pub fn normalize_url(mut url: Url) -> Option<Url> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return None;
+    }
+
+    url.set_fragment(None);
+
+    if url.query().is_some() {
+        let mut params = url
+            .query_pairs()
+            .filter(|(name, _)| !is_tracking_param(name))
+            .map(|(name, value)| (name.into_owned(), value.into_owned()))
+            .collect::<Vec<_>>();
+        params.sort();
+
+        if params.is_empty() {
+            url.set_query(None);
+        } else {
+            url.query_pairs_mut().clear().extend_pairs(params);
+        }
+    }
+
+    Some(url)
+}
+
+fn is_tracking_param(name: &str) -> bool {
+    let name = name.to_lowercase();
+    name.starts_with("utm_") || TRACKING_PARAMS.contains(&name.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(text: &str) -> Url {
+        Url::parse(text).unwrap()
+    }
+
+    fn fixture_links() -> Vec<Link> {
+        find_links(
+            include_str!("../tests/fixtures/links.html"),
+            &url("https://example.com/docs/page.html"),
+        )
+    }
+
+    #[test]
+    fn fixture_links_resolve_against_base_href() {
+        let links = fixture_links()
+            .into_iter()
+            .map(|link| link.url.to_string())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            links,
+            [
+                "https://example.com/docs/guide/intro.html",
+                "https://example.com/about",
+                "https://example.com/contact",
+                "https://cdn.example.org/lib.js",
+                "https://example.com/docs/?page=2",
+                "http://www.example.com/Path",
+                "https://example.com/search?a=1&q=rust",
+                "https://example.com/news",
+            ]
+        );
+    }
+
+    #[test]
+    fn fixture_links_keep_anchor_text_and_rel() {
+        let links = fixture_links();
+        assert_eq!(links[0].anchor_text, "Relative to the base");
+        assert_eq!(links.last().unwrap().rel, ["nofollow", "noopener"]);
+    }
+
+    #[test]
+    fn base_url_defaults_to_page_url() {
+        let page = url("https://example.com/a/b.html");
+        let document = Html::parse_document("<a href='c.html'>c</a>");
+        assert_eq!(base_url(&document, &page), page);
+        assert_eq!(
+            resolve_href(&base_url(&document, &page), "c.html")
+                .unwrap()
+                .as_str(),
+            "https://example.com/a/c.html"
+        );
+    }
+
+    #[test]
+    fn relative_base_href_resolves_against_page() {
+        let document = Html::parse_document("<base href='/static/'>");
+        let base = base_url(&document, &url("https://example.com/a/b.html"));
+        assert_eq!(base.as_str(), "https://example.com/static/");
+    }
+
+    #[test]
+    fn protocol_relative_href_keeps_page_scheme() {
+        let base = url("http://example.com/");
+        assert_eq!(
+            resolve_href(&base, "//other.example.com/x")
+                .unwrap()
+                .as_str(),
+            "http://other.example.com/x"
+        );
+    }
+
+    #[test]
+    fn non_page_links_are_dropped() {
+        let base = url("https://example.com/page");
+        for href in [
+            "",
+            "  ",
+            "#",
+            "#section",
+            "javascript:alert(1)",
+            "mailto:someone@example.com",
+            "tel:+15550100",
+            "ftp://example.com/file",
+            "data:text/plain,hi",
+        ] {
+            assert_eq!(resolve_href(&base, href), None, "{:?}", href);
+        }
+    }
+
+    #[test]
+    fn fragments_are_stripped() {
+        let base = url("https://example.com/page");
+        assert_eq!(
+            resolve_href(&base, "/other#part").unwrap().as_str(),
+            "https://example.com/other"
+        );
+        assert_eq!(
+            normalize_url(url("https://example.com/page?b=2#top"))
+                .unwrap()
+                .as_str(),
+            "https://example.com/page?b=2"
+        );
+    }
+
+    #[test]
+    fn default_ports_are_dropped_and_others_kept() {
+        for (text, normalized) in [
+            ("http://example.com:80/", "http://example.com/"),
+            ("https://example.com:443/", "https://example.com/"),
+            ("https://example.com:8443/", "https://example.com:8443/"),
+            ("http://example.com:443/", "http://example.com:443/"),
+        ] {
+            assert_eq!(normalize_url(url(text)).unwrap().as_str(), normalized);
+        }
+    }
+
+    #[test]
+    fn scheme_and_host_are_lowercased_but_path_is_not() {
+        assert_eq!(
+            normalize_url(url("HTTPS://WWW.EXAMPLE.COM/About/Team"))
+                .unwrap()
+                .as_str(),
+            "https://www.example.com/About/Team"
+        );
+    }
+
+    #[test]
+    fn dot_segments_are_resolved() {
+        let base = url("https://example.com/a/b/c.html");
+        assert_eq!(
+            resolve_href(&base, "./../d/./e.html").unwrap().as_str(),
+            "https://example.com/a/d/e.html"
+        );
+    }
+
+    #[test]
+    fn tracking_params_are_removed_and_query_sorted() {
+        assert_eq!(
+            normalize_url(url(
+                "https://example.com/?z=1&UTM_Campaign=x&fbclid=abc&a=2"
+            ))
+            .unwrap()
+            .as_str(),
+            "https://example.com/?a=2&z=1"
+        );
+        assert_eq!(
+            normalize_url(url("https://example.com/?utm_source=x"))
+                .unwrap()
+                .as_str(),
+            "https://example.com/"
+        );
+    }
+}
@@ -0,0 +1,93 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::{Args, ValueEnum};
+use tracing_subscriber::filter::{filter_fn, EnvFilter};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, Layer};
+
+use crate::error::AppError;
+use crate::request_log::RequestLog;
+
+// Shown when neither the config nor RUST_LOG says what to log
+const DEFAULT_FILTER: &str = "warn";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    // One line per event, with the fields of its spans
+    #[default]
+    Text,
+    // Several lines per event, for reading a few requests closely
+    Pretty,
+    // One JSON object per line, for log collectors
+    Json,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LogConfig {
+    pub format: LogFormat,
+    // Directives like `info` or `writing_web_crawlers=debug`. RUST_LOG is read when
+    // there are none.
+    pub filter: Option<String>,
+    // Every HTTP request is written to this file, whatever the filter leaves out of the
+    // log on stderr
+    pub request_log: Option<PathBuf>,
+}
+
+// The log flags of every crawler in the book, to flatten into its command line
+#[derive(Debug, Clone, Args)]
+pub struct LogArgs {
+    /// How lines of the log on stderr are written
+    #[arg(long, value_enum, global = true, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+
+    /// What to log, e.g. info or writing_web_crawlers=debug [default: RUST_LOG, or warn]
+    #[arg(long, global = true, value_name = "FILTER")]
+    pub log: Option<String>,
+
+    /// Write every HTTP request to this file as JSON lines, which the Chapter 3
+    /// crawler's `replay` command can send again
+    #[arg(long, global = true, value_name = "PATH")]
+    pub request_log: Option<PathBuf>,
+}
+
+impl LogArgs {
+    pub fn config(&self) -> LogConfig {
+        LogConfig {
+            format: self.log_format,
+            filter: self.log.clone(),
+            request_log: self.request_log.clone(),
+        }
+    }
+}
+
+// Installs the global subscriber. The log goes to stderr, so it never mixes with a
+// command's output.
+pub fn init_logging(config: &LogConfig) -> Result<(), AppError> {
+    let filter = match &config.filter {
+        Some(directives) => EnvFilter::try_new(directives)?,
+        None => {
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER))
+        }
+    };
+
+    let output = fmt::layer().with_writer(io::stderr);
+    let output = match config.format {
+        LogFormat::Text => output.boxed(),
+        LogFormat::Pretty => output.pretty().boxed(),
+        LogFormat::Json => output.json().boxed(),
+    };
+    let request_log = match &config.request_log {
+        Some(path) => {
+            Some(RequestLog::create(path)?.with_filter(filter_fn(RequestLog::is_request)))
+        }
+        None => None,
+    };
+
+    tracing_subscriber::registry()
+        .with(output.with_filter(filter))
+        .with(request_log)
+        .try_init()?;
+    Ok(())
+}
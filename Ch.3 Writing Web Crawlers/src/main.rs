@@ -1,86 +1,735 @@
-use async_recursion::async_recursion;
-use scraper::{Html, Selector};
-use std::collections::HashSet;
-use std::error::Error;
-use url::Url;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use fancy_regex::Regex;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    let starting_site = "http://oreilly.com";
-    let mut all_ext_links = HashSet::new();
-    let mut all_int_links = HashSet::new();
+use writing_web_crawlers::crawler::{
+    CrawlLimits, CrawlOptions, Crawler, DuplicatePolicy, USER_AGENT,
+};
+use writing_web_crawlers::error::AppError;
+use writing_web_crawlers::graph::{GraphFormat, LinkGraph};
+use writing_web_crawlers::headers::{HeaderProfile, HeaderProfiles, RequestHeaders};
+use writing_web_crawlers::link_checker::{LinkCheckConfig, LinkChecker};
+use writing_web_crawlers::logging::{init_logging, LogArgs};
+use writing_web_crawlers::metrics::{serve_metrics, CrawlMetrics, Progress};
+use writing_web_crawlers::path_finder::{
+    BacklinkSource, MediaWikiBacklinks, PathFinder, PathSearchConfig,
+};
+use writing_web_crawlers::random_walk::{DeadEndPolicy, RandomWalker, WalkConfig};
+use writing_web_crawlers::request_log::{read_request_log, replay};
+use writing_web_crawlers::scope::{DomainMatch, Scope};
+use writing_web_crawlers::state::CrawlStore;
 
-    get_all_external_links(starting_site, &mut all_ext_links, &mut all_int_links).await?;
+#[derive(Parser)]
+#[command(about = "Crawl a site and collect the links between its pages")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
 
-    Ok(())
+    #[command(flatten)]
+    logging: LogArgs,
+
+    #[command(flatten)]
+    headers: HeaderArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Crawl a site, print its external links and record the link graph
+    Crawl(CrawlArgs),
+    /// Wander a site by following a random internal link from each page
+    Walk(WalkArgs),
+    /// Find the shortest chains of links leading from one page to another
+    Path(PathArgs),
+    /// Show the progress saved in a crawl state file
+    State(StateArgs),
+    /// Check every link on a site and exit with an error when any are broken
+    Check(CheckArgs),
+    /// Send the requests of a request log again and report the ones answered differently
+    Replay(ReplayArgs),
+}
+
+#[derive(Args)]
+struct CrawlArgs {
+    #[arg(default_value = "http://oreilly.com")]
+    starting_site: String,
+
+    /// Write the link graph to a file, in the format given by its extension
+    /// (.graphml, .gexf, .dot or .csv). May be repeated.
+    #[arg(long = "export", value_name = "PATH")]
+    exports: Vec<PathBuf>,
+
+    /// Maximum number of links to follow away from the starting site
+    #[arg(long, default_value_t = 5)]
+    max_depth: usize,
+
+    /// Stop after fetching this many pages
+    #[arg(long, default_value_t = 1000)]
+    max_pages: usize,
+
+    /// Stop after this many seconds
+    #[arg(long, value_name = "SECONDS")]
+    max_duration: Option<u64>,
+
+    /// Number of pages fetched at the same time
+    #[arg(long, default_value_t = 8)]
+    workers: usize,
+
+    /// Number of pages fetched at the same time from a single host
+    #[arg(long, default_value_t = 4)]
+    max_per_host: usize,
+
+    /// Fetch pages even when the site's robots.txt disallows them
+    #[arg(long)]
+    ignore_robots: bool,
+
+    /// Also start from the pages listed in the site's sitemaps and feeds
+    #[arg(long)]
+    sitemaps: bool,
+
+    /// Extra attempts for pages that fail to download
+    #[arg(long, default_value_t = 2)]
+    retries: usize,
+
+    /// Keep the crawl state in this SQLite file and resume from it when it exists
+    #[arg(long, value_name = "PATH")]
+    state: Option<PathBuf>,
+
+    /// What to do with pages whose text nearly matches a page crawled before
+    #[arg(long, value_enum, default_value_t = Duplicates::Off)]
+    duplicates: Duplicates,
+
+    /// Largest number of differing fingerprint bits between near-duplicate pages
+    #[arg(long, default_value_t = 3)]
+    duplicate_distance: u32,
+
+    #[command(flatten)]
+    scope: ScopeArgs,
+
+    #[command(flatten)]
+    metrics: MetricsArgs,
+}
+
+#[derive(Args)]
+struct WalkArgs {
+    #[arg(default_value = "http://en.wikipedia.org/wiki/Kevin_Bacon")]
+    starting_site: String,
+
+    /// Number of moves before the walk ends
+    #[arg(long, default_value_t = 100)]
+    max_steps: usize,
+
+    /// Chance, at each step, of jumping back to the starting page
+    #[arg(long, default_value_t = 0.0, value_parser = parse_probability)]
+    restart_probability: f64,
+
+    /// Seed for the random number generator; reuse a printed seed to replay a walk
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// What to do on a page with no links left to follow
+    #[arg(long, value_enum, default_value_t = DeadEnd::Restart)]
+    on_dead_end: DeadEnd,
+
+    /// Only follow links whose path matches this regex, e.g. '^/wiki/((?!:).)*$'
+    #[arg(long, value_name = "REGEX")]
+    link_pattern: Option<String>,
+
+    /// Fetch pages even when the site's robots.txt disallows them
+    #[arg(long)]
+    ignore_robots: bool,
+
+    /// Write the path taken to a CSV file
+    #[arg(long, value_name = "PATH")]
+    export: Option<PathBuf>,
+
+    #[command(flatten)]
+    scope: ScopeArgs,
+}
+
+#[derive(Args)]
+struct PathArgs {
+    #[arg(default_value = "https://en.wikipedia.org/wiki/Kevin_Bacon")]
+    from: String,
+
+    #[arg(default_value = "https://en.wikipedia.org/wiki/Eric_Idle")]
+    to: String,
+
+    /// Only go through pages whose path matches this regex, e.g. '^/wiki/((?!:).)*$'
+    #[arg(long, value_name = "REGEX")]
+    link_pattern: Option<String>,
+
+    /// Longest path, in links, to look for
+    #[arg(long, default_value_t = 6)]
+    max_depth: usize,
+
+    /// Give up after fetching this many pages
+    #[arg(long, default_value_t = 500)]
+    max_pages: usize,
+
+    /// Number of shortest paths to print when there are several
+    #[arg(long, default_value_t = 3)]
+    max_paths: usize,
+
+    /// Number of pages fetched at the same time
+    #[arg(long, default_value_t = 8)]
+    workers: usize,
+
+    /// Where to look up the pages linking to a page, so the search can also work
+    /// backwards from the target
+    #[arg(long, value_enum, default_value_t = Backlinks::None)]
+    backlinks: Backlinks,
+
+    /// Fetch pages even when the site's robots.txt disallows them
+    #[arg(long)]
+    ignore_robots: bool,
+
+    #[command(flatten)]
+    scope: ScopeArgs,
+}
+
+#[derive(Args)]
+struct CheckArgs {
+    #[arg(default_value = "http://oreilly.com")]
+    starting_site: String,
+
+    /// Maximum number of links to follow away from the starting site looking for links
+    #[arg(long, default_value_t = 5)]
+    max_depth: usize,
+
+    /// Stop looking for links after crawling this many pages
+    #[arg(long, default_value_t = 1000)]
+    max_pages: usize,
+
+    /// Number of requests made at the same time
+    #[arg(long, default_value_t = 8)]
+    workers: usize,
+
+    /// Give up on a link after this many seconds
+    #[arg(long, value_name = "SECONDS", default_value_t = 10)]
+    timeout: u64,
+
+    /// Only check links within the crawl's scope
+    #[arg(long)]
+    internal_only: bool,
+
+    /// List every link in the report, not only the broken ones
+    #[arg(long)]
+    all: bool,
+
+    /// Write every checked link to a CSV file
+    #[arg(long, value_name = "PATH")]
+    export: Option<PathBuf>,
+
+    /// Request pages even when the site's robots.txt disallows them
+    #[arg(long)]
+    ignore_robots: bool,
+
+    #[command(flatten)]
+    scope: ScopeArgs,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Duplicates {
+    Off,
+    Report,
+    Skip,
+}
+
+impl From<Duplicates> for DuplicatePolicy {
+    fn from(duplicates: Duplicates) -> Self {
+        match duplicates {
+            Duplicates::Off => Self::Off,
+            Duplicates::Report => Self::Report,
+            Duplicates::Skip => Self::Skip,
+        }
+    }
+}
+
+#[derive(Args)]
+struct ReplayArgs {
+    /// Request log written with `--request-log`
+    path: PathBuf,
+
+    /// Space the requests out the way they were in the logged run
+    #[arg(long)]
+    keep_timing: bool,
+}
+
+#[derive(Args)]
+struct StateArgs {
+    /// Crawl state file written by `crawl --state`
+    path: PathBuf,
+}
+
+#[derive(Args)]
+struct MetricsArgs {
+    /// Show a live status line with pages per second, bytes, queue depth and errors
+    #[arg(long)]
+    progress: bool,
+
+    /// Serve Prometheus metrics at http://127.0.0.1:PORT/metrics during the crawl
+    #[arg(long, value_name = "PORT")]
+    metrics_port: Option<u16>,
+
+    /// Write a JSON summary of the crawl's metrics to this file when it ends
+    #[arg(long, value_name = "PATH")]
+    metrics_json: Option<PathBuf>,
+}
+
+impl MetricsArgs {
+    fn enabled(&self) -> bool {
+        self.progress || self.metrics_port.is_some() || self.metrics_json.is_some()
+    }
+}
+
+// What the crawler says about itself in its requests, shared by every command
+#[derive(Args)]
+struct HeaderArgs {
+    /// Header profile to send with page requests
+    #[arg(
+        long = "headers",
+        global = true,
+        default_value = "default",
+        value_name = "NAME"
+    )]
+    profile: String,
+
+    /// TOML file of named header profiles, each a table of user_agents, rotation,
+    /// accept, accept_language, referer and headers
+    #[arg(long, global = true, value_name = "PATH")]
+    header_profiles: Option<PathBuf>,
+
+    /// URL where site owners can learn about the crawl and reach whoever runs it,
+    /// added to the default user agent. Recommended for any crawl of a site you don't own.
+    #[arg(long, global = true, value_name = "URL")]
+    contact: Option<String>,
+}
+
+impl HeaderArgs {
+    fn profile(&self) -> Result<HeaderProfile, AppError> {
+        let mut profiles =
+            HeaderProfiles::new(HeaderProfile::identify(USER_AGENT, self.contact.as_deref()));
+        if let Some(path) = &self.header_profiles {
+            profiles.load(path)?;
+        }
+        Ok(profiles.get(&self.profile)?.clone())
+    }
+}
+
+// Which URLs count as part of the site, shared by every command
+#[derive(Args)]
+struct ScopeArgs {
+    /// Stay on this domain instead of the starting page's. May be repeated.
+    #[arg(long = "allow-domain", value_name = "DOMAIN")]
+    allowed_domains: Vec<String>,
+
+    /// Never crawl this domain or its subdomains. May be repeated.
+    #[arg(long = "block-domain", value_name = "DOMAIN")]
+    blocked_domains: Vec<String>,
+
+    /// How hosts are compared with the allowed domains
+    #[arg(long, value_enum, default_value_t = DomainScope::Registrable)]
+    domain_scope: DomainScope,
+
+    /// Only crawl URLs whose path starts with this prefix. May be repeated.
+    #[arg(long = "path-prefix", value_name = "PREFIX")]
+    path_prefixes: Vec<String>,
+
+    /// Only crawl URLs whose path and query match this regex. May be repeated.
+    #[arg(long = "include", value_name = "REGEX")]
+    include_patterns: Vec<String>,
+
+    /// Never crawl URLs whose path and query match this regex. May be repeated.
+    #[arg(long = "exclude", value_name = "REGEX")]
+    exclude_patterns: Vec<String>,
+
+    /// Parse responses of this Content-Type, e.g. text/html or text/*, instead of
+    /// HTML only. May be repeated.
+    #[arg(long = "content-type", value_name = "MIME")]
+    content_types: Vec<String>,
+
+    /// Also skip URLs ending in this file extension. May be repeated.
+    #[arg(long = "block-extension", value_name = "EXTENSION")]
+    blocked_extensions: Vec<String>,
+
+    /// Full Public Suffix List to use instead of the built-in excerpt
+    #[arg(long, value_name = "PATH")]
+    public_suffix_list: Option<PathBuf>,
 }
 
-async fn fetch_html(url: &str) -> Result<String, reqwest::Error> {
-    let response = reqwest::get(url).await?;
-    let body = response.text().await?;
-    Ok(body)
+impl ScopeArgs {
+    fn into_scope(self) -> Result<Scope, AppError> {
+        let mut scope = Scope::new();
+        scope.allowed_domains = self.allowed_domains;
+        scope.blocked_domains = self.blocked_domains;
+        scope.domain_match = self.domain_scope.into();
+        scope.path_prefixes = self.path_prefixes;
+        scope.include_patterns = compile_patterns(&self.include_patterns)?;
+        scope.exclude_patterns = compile_patterns(&self.exclude_patterns)?;
+        if !self.content_types.is_empty() {
+            scope.content_types = self.content_types;
+        }
+        scope.blocked_extensions.extend(
+            self.blocked_extensions
+                .iter()
+                .map(|extension| extension.trim_start_matches('.').to_lowercase()),
+        );
+        if let Some(path) = &self.public_suffix_list {
+            scope.load_public_suffix_list(path)?;
+        }
+        Ok(scope)
+    }
 }
 
-#[async_recursion]
-async fn get_all_external_links(
-    site_url: &str,
-    all_ext_links: &mut HashSet<String>,
-    all_int_links: &mut HashSet<String>,
-) -> Result<(), Box<dyn Error>> {
-    let html = fetch_html(site_url).await?;
-    let domain = Url::parse(site_url)?.origin();
-    let internal_links = find_internal_links(&html, &domain);
-    let external_links = find_external_links(&html, &domain);
+fn compile_patterns(patterns: &[String]) -> Result<Vec<Regex>, AppError> {
+    patterns
+        .iter()
+        .map(|pattern| Ok(Regex::new(pattern)?))
+        .collect()
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum DomainScope {
+    Exact,
+    Subdomains,
+    Registrable,
+}
 
-    for link in external_links.iter() {
-        if !all_ext_links.contains(link) {
-            all_ext_links.insert(link.clone());
-            println!("{}", link);
+impl From<DomainScope> for DomainMatch {
+    fn from(domain_scope: DomainScope) -> Self {
+        match domain_scope {
+            DomainScope::Exact => Self::Exact,
+            DomainScope::Subdomains => Self::Subdomains,
+            DomainScope::Registrable => Self::Registrable,
         }
     }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Backlinks {
+    None,
+    Mediawiki,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum DeadEnd {
+    Restart,
+    Backtrack,
+    Stop,
+}
 
-    for link in internal_links.iter() {
-        if !all_int_links.contains(link) {
-            all_int_links.insert(link.clone());
-            get_all_external_links(link, all_ext_links, all_int_links).await?;
+impl From<DeadEnd> for DeadEndPolicy {
+    fn from(dead_end: DeadEnd) -> Self {
+        match dead_end {
+            DeadEnd::Restart => Self::Restart,
+            DeadEnd::Backtrack => Self::Backtrack,
+            DeadEnd::Stop => Self::Stop,
         }
     }
+}
+
+fn parse_probability(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(probability) if (0.0..=1.0).contains(&probability) => Ok(probability),
+        _ => Err(format!("{} is not a probability between 0 and 1", value)),
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<ExitCode, AppError> {
+    let cli = Cli::parse();
+    init_logging(&cli.logging.config())?;
+    let headers = cli.headers.profile()?;
+
+    match cli.command {
+        Command::Crawl(args) => crawl(args, headers).await?,
+        Command::Walk(args) => walk(args, headers).await?,
+        Command::Path(args) => find_path(args, headers).await?,
+        Command::State(args) => show_state(args)?,
+        Command::Check(args) => return check_links(args, headers).await,
+        Command::Replay(args) => return replay_requests(args, headers).await,
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn crawl(args: CrawlArgs, headers: HeaderProfile) -> Result<(), AppError> {
+    let formats = args
+        .exports
+        .iter()
+        .map(|path| {
+            GraphFormat::from_path(path)
+                .ok_or_else(|| AppError::UnsupportedExportFormat(path.clone()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let limits = CrawlLimits {
+        max_depth: args.max_depth,
+        max_pages: args.max_pages,
+        max_duration: args.max_duration.map(Duration::from_secs),
+        workers: args.workers.max(1),
+        max_per_host: args.max_per_host.max(1),
+        retries: args.retries,
+    };
+    let metrics = args
+        .metrics
+        .enabled()
+        .then(|| Arc::new(CrawlMetrics::new()));
+    let options = CrawlOptions {
+        respect_robots: !args.ignore_robots,
+        use_sitemaps: args.sitemaps,
+        scope: args.scope.into_scope()?,
+        state: args.state,
+        duplicates: args.duplicates.into(),
+        duplicate_distance: args.duplicate_distance,
+        metrics: metrics.clone(),
+        headers,
+    };
+
+    let server = match (&metrics, args.metrics.metrics_port) {
+        (Some(metrics), Some(port)) => Some(serve_metrics(metrics.clone(), port).await?),
+        _ => None,
+    };
+    let progress = match &metrics {
+        Some(metrics) if args.metrics.progress => Some(Progress::start(metrics.clone())),
+        _ => None,
+    };
+
+    let crawler = Crawler::new(limits, options)?;
+    let result = crawler.crawl(&args.starting_site).await;
+    drop(progress);
+    if let Some(server) = server {
+        server.abort();
+    }
+    let (graph, report) = result?;
+
+    report.print();
+    if let (Some(metrics), Some(path)) = (&metrics, &args.metrics.metrics_json) {
+        metrics.write_summary(path)?;
+        println!("Metrics summary written to {}", path.display());
+    }
+    print_graph_summary(&graph);
+
+    for (path, format) in args.exports.iter().zip(formats) {
+        let file = BufWriter::new(File::create(path)?);
+        graph.export(format, file)?;
+        println!("Link graph written to {}", path.display());
+    }
 
     Ok(())
 }
 
-fn find_internal_links(html: &str, domain: &url::Origin) -> Vec<String> {
-    let document = Html::parse_document(html);
-    let a_selector = Selector::parse("a[href]").unwrap();
-    let mut internal_links = Vec::new();
+async fn walk(args: WalkArgs, headers: HeaderProfile) -> Result<(), AppError> {
+    let config = WalkConfig {
+        max_steps: args.max_steps,
+        restart_probability: args.restart_probability,
+        seed: args.seed,
+        dead_end: args.on_dead_end.into(),
+        link_pattern: args.link_pattern.as_deref().map(Regex::new).transpose()?,
+        respect_robots: !args.ignore_robots,
+        scope: args.scope.into_scope()?,
+        headers,
+    };
 
-    for element in document.select(&a_selector) {
-        if let Some(href) = element.value().attr("href") {
-            if let Ok(url) = Url::parse(href) {
-                if url.origin() == *domain {
-                    internal_links.push(url.to_string());
-                }
-            }
+    let walk = RandomWalker::new(config)?.walk(&args.starting_site).await?;
+
+    for (index, step) in walk.steps.iter().enumerate() {
+        println!("{:>4} {:<9} {}", index, step.kind, step.url);
+    }
+
+    println!(
+        "\n{} steps over {} distinct pages (seed {})",
+        walk.steps.len() - 1,
+        walk.distinct_pages(),
+        walk.seed
+    );
+
+    if !walk.errors.is_empty() {
+        println!("Pages that could not be fetched:");
+        for (url, message) in &walk.errors {
+            println!("  {}: {}", url, message);
         }
     }
 
-    internal_links
+    if let Some(path) = &args.export {
+        walk.write_csv(BufWriter::new(File::create(path)?))?;
+        println!("Walk written to {}", path.display());
+    }
+
+    Ok(())
 }
 
-fn find_external_links(html: &str, exclude_domain: &url::Origin) -> Vec<String> {
-    let document = Html::parse_document(html);
-    let a_selector = Selector::parse("a[href]").unwrap();
-    let mut external_links = Vec::new();
+async fn find_path(args: PathArgs, headers: HeaderProfile) -> Result<(), AppError> {
+    let link_pattern = args.link_pattern.as_deref().map(Regex::new).transpose()?;
+    let backlinks: Option<Arc<dyn BacklinkSource>> = match args.backlinks {
+        Backlinks::None => None,
+        Backlinks::Mediawiki => Some(Arc::new(MediaWikiBacklinks)),
+    };
+
+    let config = PathSearchConfig {
+        filter: Arc::new(move |url| {
+            let Some(pattern) = &link_pattern else {
+                return true;
+            };
+            pattern.is_match(url.path()).unwrap_or(false)
+        }),
+        backlinks,
+        max_depth: args.max_depth,
+        max_pages: args.max_pages,
+        max_paths: args.max_paths,
+        workers: args.workers.max(1),
+        respect_robots: !args.ignore_robots,
+        scope: args.scope.into_scope()?,
+        headers,
+    };
 
-    for element in document.select(&a_selector) {
-        if let Some(href) = element.value().attr("href") {
-            if let Ok(url) = Url::parse(href) {
-                if url.origin() != *exclude_domain {
-                    external_links.push(url.to_string());
-                }
+    let result = PathFinder::new(config)?.find(&args.from, &args.to).await?;
+
+    for (index, path) in result.paths.iter().enumerate() {
+        println!("Path {} ({} links):", index + 1, path.len() - 1);
+        for step in path {
+            match &step.anchor_text {
+                Some(anchor_text) => println!("  --[{}]--> {}", anchor_text, step.url),
+                None if step.url == path[0].url => println!("  {}", step.url),
+                None => println!("  --> {}", step.url),
             }
         }
     }
 
-    external_links
+    if result.paths.is_empty() {
+        let reason = if result.budget_exhausted {
+            "the page budget ran out"
+        } else {
+            "no path within the maximum depth"
+        };
+        println!("No path found: {}", reason);
+    }
+    println!("{} pages fetched", result.pages_fetched);
+
+    if !result.errors.is_empty() {
+        println!("Pages that could not be fetched:");
+        for (url, message) in &result.errors {
+            println!("  {}: {}", url, message);
+        }
+    }
+
+    Ok(())
+}
+
+// Fails when broken links were found, so the check can gate a deploy
+async fn check_links(args: CheckArgs, headers: HeaderProfile) -> Result<ExitCode, AppError> {
+    let config = LinkCheckConfig {
+        max_depth: args.max_depth,
+        max_pages: args.max_pages,
+        workers: args.workers.max(1),
+        timeout: Duration::from_secs(args.timeout),
+        respect_robots: !args.ignore_robots,
+        check_external: !args.internal_only,
+        scope: args.scope.into_scope()?,
+        headers,
+    };
+
+    let report = LinkChecker::new(config)?.check(&args.starting_site).await?;
+    report.print(args.all);
+
+    if let Some(path) = &args.export {
+        report.write_csv(BufWriter::new(File::create(path)?))?;
+        println!("Link report written to {}", path.display());
+    }
+
+    Ok(match report.broken_links() {
+        0 => ExitCode::SUCCESS,
+        _ => ExitCode::FAILURE,
+    })
+}
+
+async fn replay_requests(args: ReplayArgs, headers: HeaderProfile) -> Result<ExitCode, AppError> {
+    let records = read_request_log(&args.path)?;
+    let replayed = replay(records, &RequestHeaders::new(headers), args.keep_timing).await?;
+    let changed = replayed
+        .iter()
+        .filter(|request| request.changed())
+        .collect::<Vec<_>>();
+
+    println!(
+        "Replayed {} requests, {} answered differently",
+        replayed.len(),
+        changed.len()
+    );
+    for request in changed {
+        let status = |status: Option<u16>| status.map_or("failed".to_string(), |s| s.to_string());
+        print!(
+            "  {} -> {} {} {}",
+            status(request.logged.status),
+            status(request.status),
+            request.logged.method,
+            request.logged.url
+        );
+        match &request.error {
+            Some(error) => println!(": {}", error),
+            None => println!(),
+        }
+    }
+
+    Ok(match replayed.iter().any(|request| request.changed()) {
+        false => ExitCode::SUCCESS,
+        true => ExitCode::FAILURE,
+    })
+}
+
+fn show_state(args: StateArgs) -> Result<(), AppError> {
+    if !args.path.exists() {
+        return Err(AppError::Io(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("{} does not exist", args.path.display()),
+        )));
+    }
+
+    let store = CrawlStore::open(&args.path)?;
+    store.summary()?.print();
+
+    let failed = store.failed_pages()?;
+    println!("  Failed pages: {}", failed.len());
+    for page in failed {
+        println!(
+            "    [depth {}, {} attempts] {}: {}",
+            page.depth, page.attempts, page.url, page.error
+        );
+    }
+
+    Ok(())
+}
+
+fn print_graph_summary(graph: &LinkGraph) {
+    let nodes = graph.nodes();
+    let in_degree = graph.in_degree();
+    let out_degree = graph.out_degree();
+    let ranks = graph.page_rank(0.85, 50);
+
+    println!(
+        "\nLink graph: {} pages, {} links",
+        nodes.len(),
+        graph.edges().len()
+    );
+
+    let mut ranked = (0..nodes.len()).collect::<Vec<_>>();
+    ranked.sort_by(|&a, &b| ranks[b].total_cmp(&ranks[a]));
+
+    println!("Top pages by PageRank:");
+    for &id in ranked.iter().take(10) {
+        println!(
+            "  {:.5}  in: {:<4} out: {:<4} {}",
+            ranks[id], in_degree[id], out_degree[id], nodes[id].url
+        );
+    }
+
+    let orphans = graph.orphan_pages();
+    println!("Orphan pages: {}", orphans.len());
+    for orphan in orphans {
+        println!("  {}", orphan.url);
+    }
 }
@@ -0,0 +1,449 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, IsTerminal, Write as _};
+use std::net::Ipv4Addr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+use crate::error::AppError;
+
+// How often the progress line is redrawn
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+// Requests to the metrics endpoint larger than this are cut off
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+
+// How long the metrics endpoint waits before accepting again after accept() fails, for
+// example when the process is out of file descriptors
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+// Upper bounds of the request latency histogram's buckets, in seconds. Slower requests
+// fall in a last bucket without a bound.
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Debug, Default)]
+struct Counters {
+    pages: u64,
+    requests: u64,
+    bytes: u64,
+    status_counts: BTreeMap<u16, u64>,
+    // Requests by latency bucket, not cumulative, with the unbounded bucket last
+    latency_buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    latency_total: Duration,
+    latency_max: Duration,
+    queue_depth: usize,
+    errors: BTreeMap<String, u64>,
+}
+
+// Counts what a crawl does as it goes. It is shared between the fetcher, the crawl
+// loop and whatever reports on it, so every method takes `&self`.
+#[derive(Debug)]
+pub struct CrawlMetrics {
+    started: Instant,
+    counters: Mutex<Counters>,
+}
+
+impl Default for CrawlMetrics {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            counters: Mutex::new(Counters::default()),
+        }
+    }
+}
+
+impl CrawlMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn counters(&self) -> std::sync::MutexGuard<'_, Counters> {
+        // A panic while counting doesn't make the counts any less useful
+        self.counters
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // A page fetched, or given up on, however many requests its redirects took
+    pub fn record_page(&self) {
+        self.counters().pages += 1;
+    }
+
+    // One HTTP request, timed until its body was read
+    pub fn record_response(&self, status: u16, bytes: usize, latency: Duration) {
+        let mut counters = self.counters();
+        counters.requests += 1;
+        counters.bytes += bytes as u64;
+        *counters.status_counts.entry(status).or_insert(0) += 1;
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|&bound| latency.as_secs_f64() <= bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        counters.latency_buckets[bucket] += 1;
+        counters.latency_total += latency;
+        counters.latency_max = counters.latency_max.max(latency);
+    }
+
+    pub fn record_error(&self, kind: &str) {
+        *self.counters().errors.entry(kind.to_string()).or_insert(0) += 1;
+    }
+
+    // Pages waiting to be crawled
+    pub fn set_queue_depth(&self, queued: usize) {
+        self.counters().queue_depth = queued;
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let counters = self.counters();
+        let max = counters.latency_max.as_secs_f64();
+        let percentile = |quantile| percentile(&counters.latency_buckets, max, quantile) * 1000.0;
+
+        MetricsSnapshot {
+            elapsed_seconds: elapsed,
+            pages: counters.pages,
+            pages_per_second: counters.pages as f64 / elapsed.max(f64::EPSILON),
+            requests: counters.requests,
+            bytes: counters.bytes,
+            status_counts: counters.status_counts.clone(),
+            latency_ms: LatencySummary {
+                p50: percentile(0.5),
+                p90: percentile(0.9),
+                p99: percentile(0.99),
+                max: max * 1000.0,
+                total: counters.latency_total.as_secs_f64() * 1000.0,
+                buckets: counters.latency_buckets,
+            },
+            queue_depth: counters.queue_depth,
+            errors: counters.errors.clone(),
+        }
+    }
+
+    // The metrics in the Prometheus text exposition format
+    pub fn prometheus(&self) -> String {
+        let snapshot = self.snapshot();
+        let latency = &snapshot.latency_ms;
+        let mut text = String::new();
+
+        write_metric(
+            &mut text,
+            ("crawler_pages_total", "counter"),
+            "Pages fetched or given up on.",
+            [(String::new(), snapshot.pages as f64)],
+        );
+        write_metric(
+            &mut text,
+            ("crawler_pages_per_second", "gauge"),
+            "Pages per second since the crawl started.",
+            [(String::new(), snapshot.pages_per_second)],
+        );
+        write_metric(
+            &mut text,
+            ("crawler_requests_total", "counter"),
+            "HTTP requests sent, including redirects.",
+            [(String::new(), snapshot.requests as f64)],
+        );
+        write_metric(
+            &mut text,
+            ("crawler_response_bytes_total", "counter"),
+            "Bytes of response bodies read.",
+            [(String::new(), snapshot.bytes as f64)],
+        );
+        write_metric(
+            &mut text,
+            ("crawler_responses_total", "counter"),
+            "HTTP responses by status code.",
+            snapshot
+                .status_counts
+                .iter()
+                .map(|(status, count)| (format!("{{status=\"{}\"}}", status), *count as f64)),
+        );
+        let bounds = LATENCY_BUCKETS
+            .iter()
+            .map(|bound| bound.to_string())
+            .chain(["+Inf".to_string()]);
+        let cumulative = latency.buckets.iter().scan(0, |count, bucket| {
+            *count += bucket;
+            Some(*count)
+        });
+        write_metric(
+            &mut text,
+            ("crawler_request_duration_seconds", "histogram"),
+            "Time from sending a request to reading its body.",
+            bounds
+                .zip(cumulative)
+                .map(|(bound, count)| (format!("_bucket{{le=\"{}\"}}", bound), count as f64))
+                .chain([
+                    ("_sum".to_string(), latency.total / 1000.0),
+                    ("_count".to_string(), snapshot.requests as f64),
+                ]),
+        );
+        write_metric(
+            &mut text,
+            ("crawler_queue_depth", "gauge"),
+            "Pages waiting to be crawled.",
+            [(String::new(), snapshot.queue_depth as f64)],
+        );
+        write_metric(
+            &mut text,
+            ("crawler_errors_total", "counter"),
+            "Failed requests and broken redirect chains by kind.",
+            snapshot
+                .errors
+                .iter()
+                .map(|(kind, count)| (format!("{{kind=\"{}\"}}", kind), *count as f64)),
+        );
+
+        text
+    }
+
+    pub fn write_summary(&self, path: &Path) -> Result<(), AppError> {
+        let mut file = io::BufWriter::new(std::fs::File::create(path)?);
+        serde_json::to_writer_pretty(&mut file, &self.snapshot())?;
+        writeln!(file)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LatencySummary {
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+    // Of all requests together
+    pub total: f64,
+    // Requests in each bucket of the histogram the percentiles are estimated from
+    #[serde(skip)]
+    pub buckets: [u64; LATENCY_BUCKETS.len() + 1],
+}
+
+// The metrics at one point of the crawl, and the JSON summary written at its end
+#[derive(Debug, Clone, Serialize)]
+pub struct MetricsSnapshot {
+    pub elapsed_seconds: f64,
+    pub pages: u64,
+    pub pages_per_second: f64,
+    pub requests: u64,
+    pub bytes: u64,
+    pub status_counts: BTreeMap<u16, u64>,
+    pub latency_ms: LatencySummary,
+    pub queue_depth: usize,
+    pub errors: BTreeMap<String, u64>,
+}
+
+impl MetricsSnapshot {
+    pub fn status_line(&self) -> String {
+        format!(
+            "{} pages ({:.1}/s), {} requests, {}, {} queued, {} errors, p50 {:.0} ms, p99 {:.0} ms",
+            self.pages,
+            self.pages_per_second,
+            self.requests,
+            format_bytes(self.bytes),
+            self.queue_depth,
+            self.errors.values().sum::<u64>(),
+            self.latency_ms.p50,
+            self.latency_ms.p99,
+        )
+    }
+}
+
+// Each sample is written as the metric's name, then the sample's suffix and labels
+fn write_metric(
+    text: &mut String,
+    (name, kind): (&str, &str),
+    help: &str,
+    samples: impl IntoIterator<Item = (String, f64)>,
+) {
+    let _ = writeln!(text, "# HELP {} {}", name, help);
+    let _ = writeln!(text, "# TYPE {} {}", name, kind);
+    for (suffix, value) in samples {
+        let _ = writeln!(text, "{}{} {}", name, suffix, value);
+    }
+}
+
+// The upper bound of the bucket holding the nearest-rank percentile, in seconds, but
+// never more than the slowest request
+fn percentile(buckets: &[u64], max: f64, quantile: f64) -> f64 {
+    let count = buckets.iter().sum::<u64>();
+    if count == 0 {
+        return 0.0;
+    }
+    let rank = ((quantile * count as f64).ceil() as u64).clamp(1, count);
+    let mut seen = 0;
+    let bucket = buckets
+        .iter()
+        .position(|bucket| {
+            seen += bucket;
+            seen >= rank
+        })
+        .unwrap_or(LATENCY_BUCKETS.len());
+    LATENCY_BUCKETS
+        .get(bucket)
+        .map_or(max, |bound| bound.min(max))
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = "B";
+    for next in UNITS {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next;
+    }
+    if unit == "B" {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, unit)
+    }
+}
+
+// What kind of failure a request error is, for counting errors by kind
+pub fn error_kind(err: &reqwest::Error) -> &'static str {
+    if err.is_timeout() {
+        "timeout"
+    } else if err.is_connect() {
+        "connect"
+    } else if err.is_body() || err.is_decode() {
+        "body"
+    } else if err.is_request() {
+        "request"
+    } else {
+        "http"
+    }
+}
+
+// Serves the metrics at http://127.0.0.1:{port}/metrics until the task is aborted
+pub async fn serve_metrics(
+    metrics: Arc<CrawlMetrics>,
+    port: u16,
+) -> Result<JoinHandle<()>, AppError> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port)).await?;
+    println!(
+        "Serving metrics at http://{}/metrics",
+        listener.local_addr()?
+    );
+
+    Ok(tokio::spawn(async move {
+        loop {
+            let Ok((stream, _)) = listener.accept().await else {
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
+                continue;
+            };
+            let metrics = metrics.clone();
+            tokio::spawn(async move {
+                let _ = answer(stream, &metrics).await;
+            });
+        }
+    }))
+}
+
+async fn answer(mut stream: TcpStream, metrics: &CrawlMetrics) -> io::Result<()> {
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n")
+        && request.len() < MAX_REQUEST_SIZE
+    {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.split_whitespace();
+    let (status, body) = match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", metrics.prometheus()),
+        _ => ("404 Not Found", "Not found\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+// A status line on stderr, redrawn every second while the crawl runs. Nothing is drawn
+// when stderr isn't a terminal.
+pub struct Progress {
+    task: Option<JoinHandle<()>>,
+}
+
+impl Progress {
+    pub fn start(metrics: Arc<CrawlMetrics>) -> Self {
+        if !io::stderr().is_terminal() {
+            return Self { task: None };
+        }
+
+        let task = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(PROGRESS_INTERVAL);
+            loop {
+                interval.tick().await;
+                let mut stderr = io::stderr().lock();
+                let _ = write!(stderr, "\r\x1b[2K{}", metrics.snapshot().status_line());
+                let _ = stderr.flush();
+            }
+        });
+        Self { task: Some(task) }
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+            eprint!("\r\x1b[2K");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latencies_are_counted_in_cumulative_buckets() {
+        let metrics = CrawlMetrics::new();
+        for millis in [3, 40, 40, 700, 12_000] {
+            metrics.record_response(200, 0, Duration::from_millis(millis));
+        }
+
+        let text = metrics.prometheus();
+        for line in [
+            "# TYPE crawler_request_duration_seconds histogram",
+            "crawler_request_duration_seconds_bucket{le=\"0.005\"} 1",
+            "crawler_request_duration_seconds_bucket{le=\"0.05\"} 3",
+            "crawler_request_duration_seconds_bucket{le=\"1\"} 4",
+            "crawler_request_duration_seconds_bucket{le=\"10\"} 4",
+            "crawler_request_duration_seconds_bucket{le=\"+Inf\"} 5",
+            "crawler_request_duration_seconds_count 5",
+        ] {
+            assert!(
+                text.lines().any(|sample| sample == line),
+                "{} in\n{}",
+                line,
+                text
+            );
+        }
+
+        let latency = metrics.snapshot().latency_ms;
+        assert_eq!(latency.p50, 50.0);
+        assert_eq!(latency.p90, 12_000.0);
+        assert_eq!(latency.max, 12_000.0);
+    }
+}
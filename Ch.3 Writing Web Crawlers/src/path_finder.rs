@@ -0,0 +1,544 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use reqwest::{Client, Method};
+use scraper::{Html, Selector};
+use tokio::task::JoinSet;
+use url::Url;
+
+use crate::crawler::USER_AGENT;
+use crate::error::AppError;
+use crate::fetch::PageFetcher;
+use crate::headers::{HeaderProfile, RequestHeaders};
+use crate::links::{find_links, normalize_url, resolve_href, Link};
+use crate::request_log::send_traced;
+use crate::robots::RobotsGate;
+use crate::scope::Scope;
+
+// Decides which pages a path may go through, e.g. only Wikipedia articles
+pub type LinkFilter = Arc<dyn Fn(&Url) -> bool + Send + Sync>;
+
+// Lists the pages that link to a page, which lets the search also grow backwards from
+// the target. Without one the search only runs forwards from the source.
+#[async_trait]
+pub trait BacklinkSource: Send + Sync {
+    async fn backlinks(&self, client: &Client, url: &Url) -> Result<Vec<Url>, AppError>;
+}
+
+// Reads backlinks from a MediaWiki site's Special:WhatLinksHere page
+pub struct MediaWikiBacklinks;
+
+#[async_trait]
+impl BacklinkSource for MediaWikiBacklinks {
+    async fn backlinks(&self, client: &Client, url: &Url) -> Result<Vec<Url>, AppError> {
+        let Some(title) = url.path().strip_prefix("/wiki/") else {
+            return Ok(Vec::new());
+        };
+
+        let mut list_url = url.join("/w/index.php")?;
+        list_url
+            .query_pairs_mut()
+            .append_pair("title", &format!("Special:WhatLinksHere/{}", title))
+            .append_pair("limit", "500")
+            .append_pair("hidetrans", "1")
+            .append_pair("hideredirs", "1");
+
+        let (status, body) = send_traced(client, Method::GET, &list_url, None).await?;
+        if !(200..300).contains(&status) {
+            return Err(AppError::UnexpectedStatus(status, list_url.to_string()));
+        }
+
+        let document = Html::parse_document(&String::from_utf8_lossy(&body));
+        let item_selector = Selector::parse("#mw-whatlinkshere-list > li > a").unwrap();
+        let backlinks = document
+            .select(&item_selector)
+            .filter_map(|element| element.value().attr("href"))
+            .filter_map(|href| resolve_href(url, href))
+            .collect();
+
+        Ok(backlinks)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PathStep {
+    pub url: Url,
+    // Anchor text of the link that led to this page, if it could be found
+    pub anchor_text: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct PathSearchResult {
+    pub paths: Vec<Vec<PathStep>>,
+    pub pages_fetched: usize,
+    pub budget_exhausted: bool,
+    pub errors: Vec<(String, String)>,
+}
+
+pub struct PathSearchConfig {
+    pub filter: LinkFilter,
+    pub backlinks: Option<Arc<dyn BacklinkSource>>,
+    // Longest path, in links, worth looking for
+    pub max_depth: usize,
+    // Pages fetched, counting both link lists and backlink lists
+    pub max_pages: usize,
+    pub max_paths: usize,
+    pub workers: usize,
+    pub respect_robots: bool,
+    // Paths only go through pages in scope
+    pub scope: Scope,
+    // For the pages themselves. Backlink lists come from an API and are fetched with
+    // the profile's first user agent only.
+    pub headers: HeaderProfile,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Forward,
+    Backward,
+}
+
+// One side of the bidirectional search: the distance of every page reached so far and,
+// for each page, its neighbours one step closer to where this side started.
+#[derive(Default)]
+struct SearchSide {
+    distance: HashMap<String, usize>,
+    previous: HashMap<String, Vec<String>>,
+    urls: HashMap<String, Url>,
+    layer: Vec<Url>,
+    depth: usize,
+}
+
+impl SearchSide {
+    fn new(start: &Url) -> Self {
+        let mut side = Self::default();
+        side.distance.insert(start.to_string(), 0);
+        side.urls.insert(start.to_string(), start.clone());
+        side.layer.push(start.clone());
+        side
+    }
+
+    fn reach(&mut self, url: Url, from: &Url, next_layer: &mut Vec<Url>) {
+        let key = url.to_string();
+        match self.distance.get(&key) {
+            None => {
+                self.distance.insert(key.clone(), self.depth + 1);
+                self.previous.insert(key.clone(), vec![from.to_string()]);
+                self.urls.insert(key, url.clone());
+                next_layer.push(url);
+            }
+            Some(&distance) if distance == self.depth + 1 => {
+                let previous = self.previous.entry(key).or_default();
+                if !previous.contains(&from.to_string()) {
+                    previous.push(from.to_string());
+                }
+            }
+            Some(_) => {}
+        }
+    }
+
+    // Every shortest chain of pages from this side's start to `page`, start first
+    fn chains_to(&self, page: &str, limit: usize) -> Vec<Vec<String>> {
+        let Some(previous) = self.previous.get(page) else {
+            return vec![vec![page.to_string()]];
+        };
+
+        let mut chains = Vec::new();
+        for parent in previous {
+            for mut chain in self.chains_to(parent, limit) {
+                chain.push(page.to_string());
+                chains.push(chain);
+                if chains.len() >= limit {
+                    return chains;
+                }
+            }
+        }
+        chains
+    }
+}
+
+pub struct PathFinder {
+    client: Client,
+    fetcher: PageFetcher,
+    robots: Option<Arc<RobotsGate>>,
+    config: PathSearchConfig,
+    scope: Arc<Scope>,
+    outlinks: HashMap<String, Vec<Link>>,
+    // Where pages that redirect ended up, so a link to an old URL of the target still
+    // reaches it
+    redirects: HashMap<String, String>,
+    result: PathSearchResult,
+}
+
+impl PathFinder {
+    pub fn new(config: PathSearchConfig) -> Result<Self, AppError> {
+        let user_agent = config.headers.user_agent(USER_AGENT);
+        let client = Client::builder().user_agent(user_agent).build()?;
+        let robots = config
+            .respect_robots
+            .then(|| Arc::new(RobotsGate::new(client.clone(), user_agent)));
+        let fetcher =
+            PageFetcher::new(user_agent)?.with_headers(RequestHeaders::new(config.headers.clone()));
+
+        let scope = Arc::new(config.scope.clone());
+
+        Ok(Self {
+            client,
+            fetcher,
+            robots,
+            config,
+            scope,
+            outlinks: HashMap::new(),
+            redirects: HashMap::new(),
+            result: PathSearchResult::default(),
+        })
+    }
+
+    pub async fn find(mut self, source: &str, target: &str) -> Result<PathSearchResult, AppError> {
+        let source = parse_page_url(source)?;
+        let target = parse_page_url(target)?;
+        self.scope = Arc::new(self.config.scope.clone().for_start(&source));
+
+        if source == target {
+            self.result.paths.push(vec![PathStep {
+                url: source,
+                anchor_text: None,
+            }]);
+            return Ok(self.result);
+        }
+
+        let mut forward = SearchSide::new(&source);
+        let mut backward = SearchSide::new(&target);
+
+        while forward.depth + backward.depth < self.config.max_depth {
+            // Grow whichever side has the smaller layer, as long as backlinks are
+            // available. A side that has run out of pages leaves the rest to the other.
+            let can_go_backward = self.config.backlinks.is_some() && !backward.layer.is_empty();
+            let direction = if can_go_backward
+                && (forward.layer.is_empty() || backward.layer.len() < forward.layer.len())
+            {
+                Direction::Backward
+            } else if !forward.layer.is_empty() {
+                Direction::Forward
+            } else {
+                break;
+            };
+
+            let side = match direction {
+                Direction::Forward => &mut forward,
+                Direction::Backward => &mut backward,
+            };
+
+            let layer = std::mem::take(&mut side.layer);
+            let neighbours = self.expand(&layer, direction).await;
+
+            let side = match direction {
+                Direction::Forward => &mut forward,
+                Direction::Backward => &mut backward,
+            };
+            let mut next_layer = Vec::new();
+            for (page, found) in neighbours {
+                for neighbour in found {
+                    side.reach(neighbour, &page, &mut next_layer);
+                }
+            }
+            side.layer = next_layer;
+            side.depth += 1;
+
+            if let Some(meeting) = meeting_pages(&forward, &backward, &self.redirects) {
+                self.collect_paths(&forward, &backward, &meeting).await;
+                break;
+            }
+            if self.result.budget_exhausted {
+                break;
+            }
+        }
+
+        Ok(self.result)
+    }
+
+    // Fetches the neighbours of every page in a layer, a few pages at a time
+    async fn expand(&mut self, layer: &[Url], direction: Direction) -> Vec<(Url, Vec<Url>)> {
+        let mut neighbours = Vec::new();
+        let mut pending = layer.iter().cloned();
+        let mut workers = JoinSet::new();
+
+        loop {
+            while workers.len() < self.config.workers.max(1) {
+                if self.result.pages_fetched >= self.config.max_pages {
+                    self.result.budget_exhausted = true;
+                    break;
+                }
+                let Some(page) = pending.next() else {
+                    break;
+                };
+
+                self.result.pages_fetched += 1;
+                let client = self.client.clone();
+                let fetcher = self.fetcher.clone();
+                let robots = self.robots.clone();
+                let backlinks = self.config.backlinks.clone();
+                let scope = self.scope.clone();
+                workers.spawn(async move {
+                    let result = match (direction, backlinks) {
+                        (Direction::Backward, Some(backlinks)) => backlinks
+                            .backlinks(&client, &page)
+                            .await
+                            .map(|urls| (page.clone(), urls.into_iter().map(backlink).collect())),
+                        _ => fetch_links(&fetcher, robots.as_deref(), &scope, &page).await,
+                    };
+                    (page, result)
+                });
+            }
+
+            let Some(joined) = workers.join_next().await else {
+                break;
+            };
+            let Ok((page, result)) = joined else {
+                continue;
+            };
+
+            match result {
+                Ok((final_url, links)) => {
+                    if direction == Direction::Forward {
+                        if final_url != page {
+                            self.redirects
+                                .insert(page.to_string(), final_url.to_string());
+                        }
+                        self.outlinks.insert(page.to_string(), links.clone());
+                    }
+                    let allowed = links
+                        .into_iter()
+                        .map(|link| link.url)
+                        .filter(|url| self.scope.contains(url) && *url != page)
+                        .filter(|url| (self.config.filter)(url))
+                        .collect();
+                    neighbours.push((page, allowed));
+                }
+                Err(err) => self.result.errors.push((page.to_string(), err.to_string())),
+            }
+        }
+
+        neighbours
+    }
+
+    async fn collect_paths(
+        &mut self,
+        forward: &SearchSide,
+        backward: &SearchSide,
+        meeting: &[(String, String)],
+    ) {
+        let limit = self.config.max_paths.max(1);
+        let mut chains = Vec::new();
+
+        'meeting: for (forward_page, backward_page) in meeting {
+            for head in forward.chains_to(forward_page, limit) {
+                for tail in backward.chains_to(backward_page, limit) {
+                    // The backward chain runs from the target to the meeting page, which
+                    // takes the place of the forward page that redirected to it
+                    let mut chain = head[..head.len() - 1].to_vec();
+                    chain.extend(tail.into_iter().rev());
+                    chains.push(chain);
+                    if chains.len() >= limit {
+                        break 'meeting;
+                    }
+                }
+            }
+        }
+
+        for chain in chains {
+            let mut steps = Vec::new();
+            for (index, page) in chain.iter().enumerate() {
+                let url = forward
+                    .urls
+                    .get(page)
+                    .or_else(|| backward.urls.get(page))
+                    .cloned()
+                    .unwrap_or_else(|| Url::parse(page).expect("search only stores valid URLs"));
+                let anchor_text = match index {
+                    0 => None,
+                    _ => self.anchor_text(&chain[index - 1], page).await,
+                };
+                steps.push(PathStep { url, anchor_text });
+            }
+            self.result.paths.push(steps);
+        }
+    }
+
+    // Links found walking backwards weren't read from the linking page, so their
+    // anchor text is looked up there. Those pages count toward the page budget like any
+    // other.
+    async fn anchor_text(&mut self, from: &str, to: &str) -> Option<String> {
+        if !self.outlinks.contains_key(from) {
+            if self.result.pages_fetched >= self.config.max_pages {
+                self.result.budget_exhausted = true;
+                return None;
+            }
+            self.result.pages_fetched += 1;
+            let url = Url::parse(from).ok()?;
+            let (_, links) = fetch_links(&self.fetcher, self.robots.as_deref(), &self.scope, &url)
+                .await
+                .ok()?;
+            self.outlinks.insert(from.to_string(), links);
+        }
+
+        self.outlinks
+            .get(from)?
+            .iter()
+            .find(|link| {
+                link.url.as_str() == to
+                    || self.redirects.get(link.url.as_str()).map(String::as_str) == Some(to)
+            })
+            .map(|link| link.anchor_text.clone())
+    }
+}
+
+// Pages both sides have reached that lie on a shortest path, as the forward side's page
+// and the backward side's. They differ when the forward page redirects to the other.
+fn meeting_pages(
+    forward: &SearchSide,
+    backward: &SearchSide,
+    redirects: &HashMap<String, String>,
+) -> Option<Vec<(String, String)>> {
+    let totals = forward
+        .distance
+        .iter()
+        .filter_map(|(page, distance)| {
+            let meeting = std::iter::once(page)
+                .chain(redirects.get(page))
+                .find(|meeting| backward.distance.contains_key(*meeting))?;
+            Some((page, meeting, distance + backward.distance[meeting]))
+        })
+        .collect::<Vec<_>>();
+
+    let shortest = totals.iter().map(|(_, _, total)| *total).min()?;
+    Some(
+        totals
+            .into_iter()
+            .filter(|(_, _, total)| *total == shortest)
+            .map(|(page, meeting, _)| (page.clone(), meeting.clone()))
+            .collect(),
+    )
+}
+
+// Backlink lists only give the linking page, not the link itself
+fn backlink(url: Url) -> Link {
+    Link {
+        url,
+        anchor_text: String::new(),
+        rel: Vec::new(),
+    }
+}
+
+fn parse_page_url(url: &str) -> Result<Url, AppError> {
+    normalize_url(Url::parse(url)?).ok_or_else(|| AppError::UnsupportedScheme(url.to_string()))
+}
+
+// The links of a page, and the URL it ended up at after redirects
+async fn fetch_links(
+    fetcher: &PageFetcher,
+    robots: Option<&RobotsGate>,
+    scope: &Scope,
+    page: &Url,
+) -> Result<(Url, Vec<Link>), AppError> {
+    let (final_url, html) = fetcher.fetch(robots, scope, page).await?.into_page()?;
+    let links = find_links(&html, &final_url);
+    Ok((final_url, links))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    const SITE: &[(&str, &[&str])] = &[
+        ("/s", &["/a1", "/a2"]),
+        ("/a1", &["/b"]),
+        ("/a2", &[]),
+        ("/b", &["/t"]),
+        ("/t", &[]),
+    ];
+
+    async fn serve_site() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = [0; 1024];
+                let read = stream.read(&mut request).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&request[..read]);
+                let path = request.split_whitespace().nth(1).unwrap_or("/");
+                let response = match SITE.iter().find(|(page, _)| *page == path) {
+                    Some((_, links)) => {
+                        let body = links
+                            .iter()
+                            .map(|link| format!("<a href=\"{}\">to {}</a>", link, link))
+                            .collect::<String>();
+                        format!(
+                            "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                            body.len(),
+                            body
+                        )
+                    }
+                    None => {
+                        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                            .to_string()
+                    }
+                };
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        address
+    }
+
+    // The target's only listed backlink is /b, and nothing is listed as linking to /b
+    struct ShortBacklinks;
+
+    #[async_trait]
+    impl BacklinkSource for ShortBacklinks {
+        async fn backlinks(&self, _client: &Client, url: &Url) -> Result<Vec<Url>, AppError> {
+            Ok(match url.path() {
+                "/t" => vec![url.join("/b")?],
+                _ => Vec::new(),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn forward_search_goes_on_when_backlinks_run_out() {
+        let address = serve_site().await;
+        let config = PathSearchConfig {
+            filter: Arc::new(|_| true),
+            backlinks: Some(Arc::new(ShortBacklinks)),
+            max_depth: 6,
+            max_pages: 100,
+            max_paths: 10,
+            workers: 1,
+            respect_robots: false,
+            scope: Scope::new(),
+            headers: HeaderProfile::identify(USER_AGENT, None),
+        };
+
+        let result = PathFinder::new(config)
+            .unwrap()
+            .find(
+                &format!("http://{}/s", address),
+                &format!("http://{}/t", address),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(result.paths.len(), 1, "{:?}", result);
+        let path = result.paths[0]
+            .iter()
+            .map(|step| step.url.path())
+            .collect::<Vec<_>>();
+        assert_eq!(path, ["/s", "/a1", "/b", "/t"]);
+        assert_eq!(result.paths[0][3].anchor_text.as_deref(), Some("to /t"));
+    }
+}
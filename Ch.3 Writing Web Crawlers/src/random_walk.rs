@@ -0,0 +1,259 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use fancy_regex::Regex;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use reqwest::Client;
+use url::Url;
+
+use crate::crawler::USER_AGENT;
+use crate::error::AppError;
+use crate::fetch::PageFetcher;
+use crate::headers::{HeaderProfile, RequestHeaders};
+use crate::links::{find_links, normalize_url, Link};
+use crate::robots::RobotsGate;
+use crate::scope::Scope;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeadEndPolicy {
+    // Jump back to the starting page
+    Restart,
+    // Return to the previous page on the path and pick another link there
+    Backtrack,
+    // End the walk
+    Stop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepKind {
+    Start,
+    Link,
+    Restart,
+    Backtrack,
+}
+
+impl fmt::Display for StepKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Start => f.pad("start"),
+            Self::Link => f.pad("link"),
+            Self::Restart => f.pad("restart"),
+            Self::Backtrack => f.pad("backtrack"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WalkStep {
+    pub kind: StepKind,
+    pub url: Url,
+    pub anchor_text: String,
+}
+
+#[derive(Debug)]
+pub struct WalkConfig {
+    pub max_steps: usize,
+    pub restart_probability: f64,
+    // The same seed replays the same walk, as long as the pages don't change
+    pub seed: Option<u64>,
+    pub dead_end: DeadEndPolicy,
+    // Only internal links whose path and query match are followed
+    pub link_pattern: Option<Regex>,
+    pub respect_robots: bool,
+    // Links leaving the scope are never followed
+    pub scope: Scope,
+    pub headers: HeaderProfile,
+}
+
+#[derive(Debug)]
+pub struct RandomWalk {
+    pub seed: u64,
+    pub steps: Vec<WalkStep>,
+    pub errors: Vec<(String, String)>,
+}
+
+impl RandomWalk {
+    pub fn distinct_pages(&self) -> usize {
+        self.steps
+            .iter()
+            .map(|step| step.url.as_str())
+            .collect::<HashSet<_>>()
+            .len()
+    }
+
+    pub fn write_csv<W: std::io::Write>(&self, writer: W) -> std::io::Result<()> {
+        let mut csv_writer = csv::Writer::from_writer(writer);
+        csv_writer.write_record(["step", "kind", "url", "anchor_text"])?;
+
+        for (index, step) in self.steps.iter().enumerate() {
+            csv_writer.write_record([
+                index.to_string().as_str(),
+                step.kind.to_string().as_str(),
+                step.url.as_str(),
+                step.anchor_text.as_str(),
+            ])?;
+        }
+
+        csv_writer.flush()
+    }
+}
+
+pub struct RandomWalker {
+    fetcher: PageFetcher,
+    robots: Option<RobotsGate>,
+    config: WalkConfig,
+    // Pages are fetched once per walk, however often the walk comes back to them
+    links: HashMap<String, Vec<Link>>,
+    errors: Vec<(String, String)>,
+}
+
+impl RandomWalker {
+    pub fn new(config: WalkConfig) -> Result<Self, AppError> {
+        if !(0.0..=1.0).contains(&config.restart_probability) {
+            return Err(AppError::InvalidProbability(config.restart_probability));
+        }
+
+        let user_agent = config.headers.user_agent(USER_AGENT);
+        let client = Client::builder().user_agent(user_agent).build()?;
+        let robots = config
+            .respect_robots
+            .then(|| RobotsGate::new(client.clone(), user_agent));
+        let fetcher =
+            PageFetcher::new(user_agent)?.with_headers(RequestHeaders::new(config.headers.clone()));
+
+        Ok(Self {
+            fetcher,
+            robots,
+            config,
+            links: HashMap::new(),
+            errors: Vec::new(),
+        })
+    }
+
+    pub async fn walk(mut self, starting_site: &str) -> Result<RandomWalk, AppError> {
+        let start_url = normalize_url(Url::parse(starting_site)?)
+            .ok_or_else(|| AppError::UnsupportedScheme(starting_site.to_string()))?;
+
+        let scope = self.config.scope.clone().for_start(&start_url);
+        let seed = self.config.seed.unwrap_or_else(|| rand::thread_rng().gen());
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        let mut steps = vec![WalkStep {
+            kind: StepKind::Start,
+            url: start_url.clone(),
+            anchor_text: String::new(),
+        }];
+        let mut path = vec![start_url.clone()];
+        let mut dead_ends = HashSet::new();
+
+        while steps.len() <= self.config.max_steps {
+            let current = path.last().cloned().unwrap_or_else(|| start_url.clone());
+
+            if current != start_url && rng.gen_bool(self.config.restart_probability) {
+                path = vec![start_url.clone()];
+                steps.push(restart_step(&start_url));
+                continue;
+            }
+
+            let candidates = self
+                .links_of(&current, &scope)
+                .await
+                .into_iter()
+                .filter(|link| !dead_ends.contains(link.url.as_str()))
+                .collect::<Vec<_>>();
+
+            if let Some(link) = candidates.choose(&mut rng) {
+                path.push(link.url.clone());
+                steps.push(WalkStep {
+                    kind: StepKind::Link,
+                    url: link.url.clone(),
+                    anchor_text: link.anchor_text.clone(),
+                });
+                continue;
+            }
+
+            dead_ends.insert(current.to_string());
+            match self.config.dead_end {
+                DeadEndPolicy::Stop => break,
+                DeadEndPolicy::Restart => {
+                    if dead_ends.contains(start_url.as_str()) {
+                        break;
+                    }
+                    path = vec![start_url.clone()];
+                    steps.push(restart_step(&start_url));
+                }
+                DeadEndPolicy::Backtrack => {
+                    path.pop();
+                    let Some(previous) = path.last() else {
+                        break;
+                    };
+                    steps.push(WalkStep {
+                        kind: StepKind::Backtrack,
+                        url: previous.clone(),
+                        anchor_text: String::new(),
+                    });
+                }
+            }
+        }
+
+        Ok(RandomWalk {
+            seed,
+            steps,
+            errors: self.errors,
+        })
+    }
+
+    // Links of a page that the walk may follow. A page that can't be fetched has none.
+    async fn links_of(&mut self, page_url: &Url, scope: &Scope) -> Vec<Link> {
+        if let Some(links) = self.links.get(page_url.as_str()) {
+            return links.clone();
+        }
+
+        let links = match self.fetch_links(page_url, scope).await {
+            Ok(links) => links,
+            Err(err) => {
+                self.errors.push((page_url.to_string(), err.to_string()));
+                Vec::new()
+            }
+        };
+
+        let mut seen = HashSet::new();
+        let links = links
+            .into_iter()
+            .filter(|link| scope.contains(&link.url))
+            .filter(|link| link.url != *page_url)
+            .filter(|link| self.matches_pattern(&link.url))
+            .filter(|link| seen.insert(link.url.to_string()))
+            .collect::<Vec<_>>();
+
+        self.links.insert(page_url.to_string(), links.clone());
+        links
+    }
+
+    // Links are read from wherever the page redirects to
+    async fn fetch_links(&self, page_url: &Url, scope: &Scope) -> Result<Vec<Link>, AppError> {
+        let (final_url, html) = self
+            .fetcher
+            .fetch(self.robots.as_ref(), scope, page_url)
+            .await?
+            .into_page()?;
+        Ok(find_links(&html, &final_url))
+    }
+
+    fn matches_pattern(&self, url: &Url) -> bool {
+        let Some(pattern) = &self.config.link_pattern else {
+            return true;
+        };
+        pattern.is_match(url.path()).unwrap_or(false)
+    }
+}
+
+fn restart_step(start_url: &Url) -> WalkStep {
+    WalkStep {
+        kind: StepKind::Restart,
+        url: start_url.clone(),
+        anchor_text: String::new(),
+    }
+}
@@ -0,0 +1,319 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use reqwest::redirect::Policy;
+use reqwest::{Client, Method};
+use serde::{Deserialize, Serialize};
+use tracing::field::{self, Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{info, info_span, warn, Metadata, Span, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+use url::Url;
+
+use crate::error::AppError;
+use crate::headers::RequestHeaders;
+
+// The name of the span every HTTP request gets
+const REQUEST_SPAN: &str = "request";
+
+// One HTTP request, as a line of the request log
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RequestRecord {
+    // When the request was sent, in seconds since the Unix epoch
+    pub timestamp: f64,
+    pub method: String,
+    pub url: String,
+    // Attempts at the same page that failed before this one
+    pub retry: u64,
+    pub status: Option<u16>,
+    // Size of the body read, which is none for redirects and unparsed content types
+    pub bytes: Option<u64>,
+    pub duration_ms: Option<f64>,
+    pub error: Option<String>,
+}
+
+impl Visit for RequestRecord {
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        match field.name() {
+            "retry" => self.retry = value,
+            "status" => self.status = u16::try_from(value).ok(),
+            "bytes" => self.bytes = Some(value),
+            _ => {}
+        }
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        if let Ok(value) = u64::try_from(value) {
+            self.record_u64(field, value);
+        }
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        if field.name() == "duration_ms" {
+            self.duration_ms = Some(value);
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "method" => self.method = value.to_string(),
+            "url" => self.url = value.to_string(),
+            "error" => self.error = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    // Fields recorded with `%` arrive here, written with their Display impl
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record_str(field, &format!("{:?}", value));
+    }
+}
+
+// The span of one HTTP request. The status, body size and duration are recorded on it
+// once the response has been read, and an event is logged in it.
+pub struct RequestSpan {
+    span: Span,
+    sent: Instant,
+}
+
+impl RequestSpan {
+    pub fn new(method: &Method, url: &Url, retry: usize) -> Self {
+        let span = info_span!(
+            "request",
+            method = %method,
+            url = %url,
+            retry,
+            status = field::Empty,
+            bytes = field::Empty,
+            duration_ms = field::Empty,
+            error = field::Empty,
+        );
+        Self {
+            span,
+            sent: Instant::now(),
+        }
+    }
+
+    // Returns how long the request took
+    pub fn finish(&self, status: u16, bytes: Option<usize>) -> Duration {
+        let duration = self.record_duration();
+        self.span.record("status", status);
+        if let Some(bytes) = bytes {
+            self.span.record("bytes", bytes);
+        }
+        info!(parent: &self.span, "response");
+        duration
+    }
+
+    pub fn failed(&self, err: &dyn fmt::Display) {
+        self.record_duration();
+        self.span.record("error", field::display(err));
+        warn!(parent: &self.span, "request failed: {}", err);
+    }
+
+    fn record_duration(&self) -> Duration {
+        let duration = self.sent.elapsed();
+        self.span
+            .record("duration_ms", duration.as_secs_f64() * 1000.0);
+        duration
+    }
+}
+
+// A tracing layer that writes each request span to a file as a line of JSON when the
+// span closes. Lines are written as they come, so a crawl that is killed still leaves
+// a log of every request it finished.
+pub struct RequestLog {
+    file: Mutex<File>,
+}
+
+impl RequestLog {
+    pub fn create(path: &Path) -> Result<Self, AppError> {
+        Ok(Self {
+            file: Mutex::new(File::create(path)?),
+        })
+    }
+
+    // For filtering the layer, which only handles request spans
+    pub fn is_request(metadata: &Metadata<'_>) -> bool {
+        metadata.is_span() && metadata.name() == REQUEST_SPAN && metadata.target() == module_path!()
+    }
+}
+
+impl<S> Layer<S> for RequestLog
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut record = RequestRecord {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64(),
+            ..RequestRecord::default()
+        };
+        attrs.record(&mut record);
+        span.extensions_mut().insert(record);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        if let Some(record) = extensions.get_mut::<RequestRecord>() {
+            values.record(record);
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(record) = span.extensions_mut().remove::<RequestRecord>() else {
+            return;
+        };
+        let Ok(mut line) = serde_json::to_string(&record) else {
+            return;
+        };
+        line.push('\n');
+
+        // A failed write loses one line of the log, which isn't worth failing the crawl
+        let mut file = self
+            .file
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let _ = file.write_all(line.as_bytes());
+    }
+}
+
+pub fn read_request_log(path: &Path) -> Result<Vec<RequestRecord>, AppError> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut records = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record =
+            serde_json::from_str(&line).map_err(|err| AppError::RequestLog(index + 1, err))?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+#[derive(Debug)]
+pub struct ReplayedRequest {
+    pub logged: RequestRecord,
+    pub status: Option<u16>,
+    pub error: Option<String>,
+}
+
+impl ReplayedRequest {
+    // Whether the request was answered differently from when it was logged
+    pub fn changed(&self) -> bool {
+        self.status != self.logged.status
+    }
+}
+
+// Sends the logged requests again, in the order they were sent. Every hop of a redirect
+// chain is a request of its own in the log, so redirects aren't followed. With
+// `keep_timing` the requests are spaced out the way they were in the logged run.
+pub async fn replay(
+    records: Vec<RequestRecord>,
+    headers: &RequestHeaders,
+    keep_timing: bool,
+) -> Result<Vec<ReplayedRequest>, AppError> {
+    let client = Client::builder().redirect(Policy::none()).build()?;
+    let started = Instant::now();
+    let first_timestamp = records.first().map_or(0.0, |record| record.timestamp);
+    let mut replayed = Vec::new();
+
+    for logged in records {
+        if keep_timing {
+            let offset = Duration::from_secs_f64((logged.timestamp - first_timestamp).max(0.0));
+            tokio::time::sleep_until((started + offset).into()).await;
+        }
+
+        let (status, error) = match (
+            Method::from_bytes(logged.method.as_bytes()),
+            Url::parse(&logged.url),
+        ) {
+            (Ok(method), Ok(url)) => {
+                match send_traced(&client, method, &url, Some(headers)).await {
+                    Ok((status, _)) => (Some(status), None),
+                    Err(err) => (
+                        err.status().map(|status| status.as_u16()),
+                        Some(err.to_string()),
+                    ),
+                }
+            }
+            (Err(err), _) => (None, Some(err.to_string())),
+            (_, Err(err)) => (None, Some(err.to_string())),
+        };
+        replayed.push(ReplayedRequest {
+            logged,
+            status,
+            error,
+        });
+    }
+
+    Ok(replayed)
+}
+
+// Sends a request and reads its whole body, in a request span. Whatever the status,
+// the response is returned. Without headers, the client's own user agent is sent.
+pub async fn send_traced(
+    client: &Client,
+    method: Method,
+    url: &Url,
+    headers: Option<&RequestHeaders>,
+) -> Result<(u16, Vec<u8>), reqwest::Error> {
+    send_traced_capped(client, method, url, headers, usize::MAX).await
+}
+
+// Like `send_traced`, but stops reading the body after `max_bytes`, so a huge response
+// is never held in memory whole
+pub async fn send_traced_capped(
+    client: &Client,
+    method: Method,
+    url: &Url,
+    headers: Option<&RequestHeaders>,
+    max_bytes: usize,
+) -> Result<(u16, Vec<u8>), reqwest::Error> {
+    let request = RequestSpan::new(&method, url, 0);
+    let result = async {
+        let mut builder = client.request(method, url.as_str());
+        if let Some(headers) = headers {
+            builder = headers.apply(builder, url);
+        }
+        let mut response = builder.send().await?;
+        let status = response.status().as_u16();
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            let room = max_bytes - body.len();
+            body.extend_from_slice(&chunk[..chunk.len().min(room)]);
+            if body.len() >= max_bytes {
+                break;
+            }
+        }
+        Ok((status, body))
+    }
+    .await;
+
+    match &result {
+        Ok((status, body)) => {
+            request.finish(*status, Some(body.len()));
+        }
+        Err(err) => request.failed(err),
+    }
+    result
+}
@@ -0,0 +1,494 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use reqwest::{Client, Method};
+use tokio::sync::OnceCell;
+use tokio::time::{self, Instant};
+use url::Url;
+
+use crate::request_log::send_traced;
+
+// How long an origin whose robots.txt couldn't be fetched stays closed before the file
+// is asked for again
+const UNAVAILABLE_RETRY: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    allow: bool,
+    pattern: String,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Group {
+    user_agents: Vec<String>,
+    rules: Vec<Rule>,
+    crawl_delay: Option<Duration>,
+}
+
+// A parsed robots.txt, following RFC 9309 plus the widely used Crawl-delay and Sitemap lines
+#[derive(Debug, Clone, Default)]
+pub struct RobotsTxt {
+    groups: Vec<Group>,
+    sitemaps: Vec<String>,
+}
+
+impl RobotsTxt {
+    pub fn allow_all() -> Self {
+        Self::default()
+    }
+
+    pub fn disallow_all() -> Self {
+        Self {
+            groups: vec![Group {
+                user_agents: vec!["*".to_string()],
+                rules: vec![Rule {
+                    allow: false,
+                    pattern: "/".to_string(),
+                }],
+                crawl_delay: None,
+            }],
+            sitemaps: Vec::new(),
+        }
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let mut robots = Self::default();
+        let mut current: Option<Group> = None;
+        // Consecutive User-agent lines share the group that follows them
+        let mut collecting_agents = false;
+
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let key = key.trim().to_lowercase();
+            let value = value.trim();
+
+            match key.as_str() {
+                "user-agent" => {
+                    if !collecting_agents {
+                        robots.groups.extend(current.take());
+                        current = Some(Group::default());
+                        collecting_agents = true;
+                    }
+                    if let Some(group) = current.as_mut() {
+                        group.user_agents.push(value.to_lowercase());
+                    }
+                }
+                "allow" | "disallow" => {
+                    collecting_agents = false;
+                    // An empty Disallow allows everything, which is the default anyway
+                    if let (Some(group), false) = (current.as_mut(), value.is_empty()) {
+                        group.rules.push(Rule {
+                            allow: key == "allow",
+                            pattern: normalize_pattern(value),
+                        });
+                    }
+                }
+                "crawl-delay" => {
+                    collecting_agents = false;
+                    if let (Some(group), Ok(seconds)) = (current.as_mut(), value.parse::<f64>()) {
+                        if seconds.is_finite() && seconds >= 0.0 {
+                            group.crawl_delay = Some(Duration::from_secs_f64(seconds));
+                        }
+                    }
+                }
+                // Sitemap lines are not tied to any group
+                "sitemap" if !value.is_empty() => robots.sitemaps.push(value.to_string()),
+                _ => {}
+            }
+        }
+
+        robots.groups.extend(current);
+        robots
+    }
+
+    pub fn sitemaps(&self) -> &[String] {
+        &self.sitemaps
+    }
+
+    // Groups naming the crawler's product token win over the `*` groups. Several
+    // groups for the same agent are merged into one rule set.
+    fn matching_groups(&self, user_agent: &str) -> Vec<&Group> {
+        let token = product_token(user_agent);
+        let named = self
+            .groups
+            .iter()
+            .filter(|group| group.user_agents.contains(&token))
+            .collect::<Vec<_>>();
+
+        if !named.is_empty() {
+            return named;
+        }
+
+        self.groups
+            .iter()
+            .filter(|group| group.user_agents.iter().any(|agent| agent == "*"))
+            .collect()
+    }
+
+    pub fn is_allowed(&self, user_agent: &str, url: &Url) -> bool {
+        let path = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+
+        if path == "/robots.txt" {
+            return true;
+        }
+
+        // The longest matching pattern decides, and Allow wins a tie
+        self.matching_groups(user_agent)
+            .into_iter()
+            .flat_map(|group| &group.rules)
+            .filter(|rule| pattern_matches(&rule.pattern, &path))
+            .max_by_key(|rule| (rule.pattern.len(), rule.allow))
+            .is_none_or(|rule| rule.allow)
+    }
+
+    pub fn crawl_delay(&self, user_agent: &str) -> Option<Duration> {
+        self.matching_groups(user_agent)
+            .into_iter()
+            .filter_map(|group| group.crawl_delay)
+            .max()
+    }
+}
+
+// "MyBot/1.2 (+https://example.com)" is matched against robots.txt as "mybot"
+fn product_token(user_agent: &str) -> String {
+    user_agent
+        .split(|c: char| c == '/' || c.is_whitespace())
+        .next()
+        .unwrap_or_default()
+        .to_lowercase()
+}
+
+// Patterns are compared with the percent-encoded path that `Url` produces
+fn normalize_pattern(pattern: &str) -> String {
+    let pattern = if pattern.starts_with('/') || pattern.starts_with('*') {
+        pattern.to_string()
+    } else {
+        format!("/{}", pattern)
+    };
+
+    pattern
+        .chars()
+        .map(|c| {
+            if c.is_ascii_graphic() {
+                c.to_string()
+            } else {
+                let mut buffer = [0; 4];
+                c.encode_utf8(&mut buffer)
+                    .bytes()
+                    .map(|byte| format!("%{:02X}", byte))
+                    .collect()
+            }
+        })
+        .collect()
+}
+
+// `*` matches any sequence of characters and a trailing `$` anchors the pattern at the
+// end of the path. Without `$` a pattern only has to match a prefix of the path.
+fn pattern_matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false),
+    };
+
+    let pattern = pattern.as_bytes();
+    let path = path.as_bytes();
+
+    // positions[i] is true when the pattern consumed so far can end at path[..i]
+    let mut positions = vec![false; path.len() + 1];
+    positions[0] = true;
+
+    for &symbol in pattern {
+        let mut next = vec![false; path.len() + 1];
+        if symbol == b'*' {
+            let mut reachable = false;
+            for i in 0..=path.len() {
+                reachable |= positions[i];
+                next[i] = reachable;
+            }
+        } else {
+            for i in 0..path.len() {
+                if positions[i] && path[i] == symbol {
+                    next[i + 1] = true;
+                }
+            }
+        }
+        positions = next;
+    }
+
+    if anchored {
+        positions[path.len()]
+    } else {
+        positions.iter().any(|&matched| matched)
+    }
+}
+
+// A robots.txt as fetched, which is kept until `expires` when it stands in for a file
+// that couldn't be read
+struct CachedRobots {
+    robots: Arc<RobotsTxt>,
+    expires: Option<Instant>,
+}
+
+// Fetches robots.txt once per origin and spaces out requests to honor Crawl-delay
+pub struct RobotsGate {
+    client: Client,
+    user_agent: String,
+    cache: Mutex<HashMap<String, Arc<OnceCell<CachedRobots>>>>,
+    next_slot: Mutex<HashMap<String, Instant>>,
+    unavailable_retry: Duration,
+}
+
+impl RobotsGate {
+    pub fn new(client: Client, user_agent: &str) -> Self {
+        Self {
+            client,
+            user_agent: user_agent.to_string(),
+            cache: Mutex::new(HashMap::new()),
+            next_slot: Mutex::new(HashMap::new()),
+            unavailable_retry: UNAVAILABLE_RETRY,
+        }
+    }
+
+    // How long to wait before asking again for a robots.txt that couldn't be fetched
+    pub fn with_unavailable_retry(mut self, retry: Duration) -> Self {
+        self.unavailable_retry = retry;
+        self
+    }
+
+    pub async fn robots_for(&self, url: &Url) -> Arc<RobotsTxt> {
+        let origin = url.origin().ascii_serialization();
+        let cell = {
+            let mut cache = self.cache.lock().unwrap();
+            let cell = cache.entry(origin.clone()).or_default();
+            let expired = cell
+                .get()
+                .and_then(|cached| cached.expires)
+                .is_some_and(|expires| expires <= Instant::now());
+            if expired {
+                *cell = Arc::default();
+            }
+            cell.clone()
+        };
+
+        cell.get_or_init(|| self.fetch(origin)).await.robots.clone()
+    }
+
+    // Missing robots.txt files allow everything. A server error means the site's
+    // rules are unknown, so nothing is crawled on that origin until the file is
+    // fetched again.
+    async fn fetch(&self, origin: String) -> CachedRobots {
+        let robots_url = format!("{}/robots.txt", origin);
+        let response = match Url::parse(&robots_url) {
+            Ok(url) => send_traced(&self.client, Method::GET, &url, None)
+                .await
+                .ok(),
+            Err(_) => None,
+        };
+        let (robots, expires) = match response {
+            Some((status, body)) if (200..300).contains(&status) => {
+                (RobotsTxt::parse(&String::from_utf8_lossy(&body)), None)
+            }
+            Some((status, _)) if (400..500).contains(&status) => (RobotsTxt::allow_all(), None),
+            _ => (
+                RobotsTxt::disallow_all(),
+                Some(Instant::now() + self.unavailable_retry),
+            ),
+        };
+
+        CachedRobots {
+            robots: Arc::new(robots),
+            expires,
+        }
+    }
+
+    // Returns whether the URL may be fetched. When it may, waits until the host's
+    // Crawl-delay has passed since the previous request to it.
+    pub async fn check(&self, url: &Url) -> bool {
+        let robots = self.robots_for(url).await;
+        if !robots.is_allowed(&self.user_agent, url) {
+            return false;
+        }
+
+        if let Some(delay) = robots.crawl_delay(&self.user_agent) {
+            let slot = {
+                let mut next_slot = self.next_slot.lock().unwrap();
+                let now = Instant::now();
+                let slot = next_slot
+                    .get(&url.origin().ascii_serialization())
+                    .map_or(now, |&next| next.max(now));
+                next_slot.insert(url.origin().ascii_serialization(), slot + delay);
+                slot
+            };
+            time::sleep_until(slot).await;
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    const PRECEDENCE: &str = include_str!("../tests/fixtures/robots_precedence.txt");
+    const GROUPS: &str = include_str!("../tests/fixtures/robots_groups.txt");
+
+    fn allowed(robots: &RobotsTxt, user_agent: &str, path: &str) -> bool {
+        let url = Url::parse("https://example.com")
+            .unwrap()
+            .join(path)
+            .unwrap();
+        robots.is_allowed(user_agent, &url)
+    }
+
+    #[test]
+    fn longest_match_wins() {
+        let robots = RobotsTxt::parse(PRECEDENCE);
+        assert!(!allowed(&robots, "bot", "/shop"));
+        assert!(!allowed(&robots, "bot", "/shop/cart"));
+        assert!(allowed(&robots, "bot", "/shop/public/item"));
+        assert!(allowed(&robots, "bot", "/docs/drafts/new"));
+        assert!(!allowed(&robots, "bot", "/docs/drafts/old/1"));
+        assert!(allowed(&robots, "bot", "/elsewhere"));
+    }
+
+    #[test]
+    fn allow_wins_a_tie() {
+        let robots = RobotsTxt::parse(PRECEDENCE);
+        assert!(allowed(&robots, "bot", "/page"));
+        assert!(allowed(&robots, "bot", "/page/2"));
+    }
+
+    #[test]
+    fn wildcards_and_end_anchors() {
+        let robots = RobotsTxt::parse(PRECEDENCE);
+        assert!(!allowed(&robots, "bot", "/files/report.pdf"));
+        assert!(allowed(&robots, "bot", "/files/report.pdf?download=1"));
+        assert!(allowed(&robots, "bot", "/files/report.pdfx"));
+        assert!(!allowed(&robots, "bot", "/private/data"));
+        assert!(!allowed(&robots, "bot", "/private-area/data/1"));
+        assert!(allowed(&robots, "bot", "/private-area/other"));
+        assert!(allowed(&robots, "bot", "/search"));
+        assert!(!allowed(&robots, "bot", "/search?q=rust"));
+        assert!(!allowed(&robots, "bot", "/searches"));
+    }
+
+    #[test]
+    fn patterns_match_percent_encoded_paths() {
+        let robots = RobotsTxt::parse(PRECEDENCE);
+        assert!(!allowed(&robots, "bot", "/café/menu"));
+        assert!(!allowed(&robots, "bot", "/ünïcode"));
+    }
+
+    #[test]
+    fn robots_txt_itself_is_always_allowed() {
+        let robots = RobotsTxt::disallow_all();
+        assert!(allowed(&robots, "bot", "/robots.txt"));
+        assert!(!allowed(&robots, "bot", "/"));
+    }
+
+    #[test]
+    fn groups_are_picked_by_product_token() {
+        let robots = RobotsTxt::parse(GROUPS);
+        // The MyBot groups replace the `*` group, which disallows everything
+        for user_agent in ["MyBot", "mybot/2.0", "MyBot/2.0 (+https://example.com/bot)"] {
+            assert!(allowed(&robots, user_agent, "/articles"), "{}", user_agent);
+            assert!(
+                !allowed(&robots, user_agent, "/admin/users"),
+                "{}",
+                user_agent
+            );
+            assert!(
+                allowed(&robots, user_agent, "/admin/help"),
+                "{}",
+                user_agent
+            );
+        }
+        // OtherBot shares the first MyBot group but not the second
+        assert!(allowed(&robots, "OtherBot/1.0", "/articles"));
+        assert!(!allowed(&robots, "OtherBot/1.0", "/admin/help"));
+        // An empty Disallow allows everything
+        assert!(allowed(&robots, "SlowBot", "/admin"));
+        assert!(!allowed(&robots, "UnknownBot/1.0", "/articles"));
+        assert_eq!(robots.sitemaps(), ["https://example.com/sitemap.xml"]);
+    }
+
+    #[test]
+    fn crawl_delay_comes_from_the_matching_groups() {
+        let robots = RobotsTxt::parse(GROUPS);
+        // Merged groups keep the longest delay
+        assert_eq!(
+            robots.crawl_delay("MyBot/1.0"),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(
+            robots.crawl_delay("OtherBot"),
+            Some(Duration::from_millis(1500))
+        );
+        assert_eq!(
+            robots.crawl_delay("UnknownBot"),
+            Some(Duration::from_secs(10))
+        );
+        // A delay that isn't a number is ignored
+        assert_eq!(robots.crawl_delay("SlowBot"), None);
+        assert_eq!(RobotsTxt::allow_all().crawl_delay("MyBot"), None);
+    }
+
+    // Answers robots.txt with a 503 the first time and disallows /private after that
+    async fn flaky_robots_server() -> (Url, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let origin = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buffer = [0; 1024];
+                let _ = stream.read(&mut buffer).await;
+                let (status, body) = match counter.fetch_add(1, Ordering::SeqCst) {
+                    0 => ("503 Service Unavailable", ""),
+                    _ => ("200 OK", "User-agent: *\nDisallow: /private\n"),
+                };
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+
+        (origin, requests)
+    }
+
+    #[tokio::test]
+    async fn unavailable_robots_txt_is_fetched_again() {
+        let (origin, requests) = flaky_robots_server().await;
+        let gate = RobotsGate::new(Client::new(), "bot")
+            .with_unavailable_retry(Duration::from_millis(100));
+        let page = origin.join("/page").unwrap();
+
+        assert!(!gate.check(&page).await);
+        assert!(!gate.check(&page).await);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        time::sleep(Duration::from_millis(150)).await;
+        assert!(gate.check(&page).await);
+        assert!(!gate.check(&origin.join("/private").unwrap()).await);
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,219 @@
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+
+use fancy_regex::Regex;
+use publicsuffix::{List, Psl};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Response;
+use url::Url;
+
+use crate::error::AppError;
+
+// Files that are never worth downloading to look for links
+pub const DEFAULT_BLOCKED_EXTENSIONS: [&str; 24] = [
+    "7z", "avi", "bmp", "css", "dmg", "doc", "docx", "exe", "gif", "gz", "ico", "iso", "jpeg",
+    "jpg", "js", "mov", "mp3", "mp4", "pdf", "png", "svg", "tar", "webp", "zip",
+];
+
+pub const DEFAULT_CONTENT_TYPES: [&str; 2] = ["text/html", "application/xhtml+xml"];
+
+static BUILT_IN_SUFFIXES: OnceLock<Arc<List>> = OnceLock::new();
+
+fn built_in_suffixes() -> Arc<List> {
+    BUILT_IN_SUFFIXES
+        .get_or_init(|| {
+            let list = include_str!("public_suffix_list.dat")
+                .parse()
+                .expect("the built-in public suffix list is valid");
+            Arc::new(list)
+        })
+        .clone()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DomainMatch {
+    // Only the listed host names
+    Exact,
+    // The listed hosts and any subdomain of them
+    Subdomains,
+    // Any host under the same registrable domain, so www.example.com, example.com and
+    // blog.example.com are one site while example.co.uk and other.co.uk are not
+    Registrable,
+}
+
+// Decides which URLs a crawler treats as part of the site. Everything else is an
+// external link: recorded, but never fetched.
+#[derive(Debug, Clone)]
+pub struct Scope {
+    // Hosts the crawl stays on. Empty means the host of the starting page.
+    pub allowed_domains: Vec<String>,
+    // Hosts, and their subdomains, that are never crawled
+    pub blocked_domains: Vec<String>,
+    pub domain_match: DomainMatch,
+    // When not empty, the URL path has to start with one of these
+    pub path_prefixes: Vec<String>,
+    // When not empty, the path and query have to match one of these
+    pub include_patterns: Vec<Regex>,
+    pub exclude_patterns: Vec<Regex>,
+    // Responses with another Content-Type are not parsed for links. Empty allows any.
+    pub content_types: Vec<String>,
+    pub blocked_extensions: Vec<String>,
+    public_suffixes: Arc<List>,
+}
+
+impl Default for Scope {
+    fn default() -> Self {
+        Self {
+            allowed_domains: Vec::new(),
+            blocked_domains: Vec::new(),
+            domain_match: DomainMatch::Registrable,
+            path_prefixes: Vec::new(),
+            include_patterns: Vec::new(),
+            exclude_patterns: Vec::new(),
+            content_types: DEFAULT_CONTENT_TYPES.map(String::from).to_vec(),
+            blocked_extensions: DEFAULT_BLOCKED_EXTENSIONS.map(String::from).to_vec(),
+            public_suffixes: built_in_suffixes(),
+        }
+    }
+}
+
+impl Scope {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Replaces the built-in excerpt with a full copy of the Public Suffix List
+    pub fn load_public_suffix_list(&mut self, path: &Path) -> Result<(), AppError> {
+        let list = std::fs::read_to_string(path)?.parse()?;
+        self.public_suffixes = Arc::new(list);
+        Ok(())
+    }
+
+    // Fills in the starting page's host when no allowed domains were given
+    pub fn for_start(mut self, start_url: &Url) -> Self {
+        if self.allowed_domains.is_empty() {
+            self.allowed_domains
+                .extend(start_url.host_str().map(str::to_string));
+        }
+        self
+    }
+
+    // Scheme and port don't matter, so http and https pages of a host are one site
+    pub fn contains(&self, url: &Url) -> bool {
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+
+        self.allows_host(host)
+            && !self
+                .blocked_domains
+                .iter()
+                .any(|blocked| is_same_or_subdomain(host, &normalize_domain(blocked)))
+            && self.allows_path(url)
+    }
+
+    pub fn accepts_content_type(&self, content_type: Option<&str>) -> bool {
+        // Servers that don't say what they sent get the benefit of the doubt
+        let Some(content_type) = content_type else {
+            return true;
+        };
+        let mime = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_lowercase();
+
+        self.content_types.is_empty()
+            || self
+                .content_types
+                .iter()
+                .any(|allowed| match allowed.strip_suffix("/*") {
+                    Some(family) => mime.split('/').next() == Some(family),
+                    None => *allowed == mime,
+                })
+    }
+
+    pub fn accepts_response(&self, response: &Response) -> bool {
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok());
+        self.accepts_content_type(content_type)
+    }
+
+    pub fn registrable_domain<'a>(&self, host: &'a str) -> Option<&'a str> {
+        if host.starts_with('[') || host.parse::<std::net::IpAddr>().is_ok() {
+            return None;
+        }
+        let domain = self.public_suffixes.domain(host.as_bytes())?;
+        let start = host.len() - domain.as_bytes().len();
+        Some(&host[start..])
+    }
+
+    fn allows_host(&self, host: &str) -> bool {
+        self.allowed_domains.iter().any(|domain| {
+            let domain = normalize_domain(domain);
+            match self.domain_match {
+                DomainMatch::Exact => host == domain,
+                DomainMatch::Subdomains => is_same_or_subdomain(host, &domain),
+                // Hosts without a registrable domain, like localhost or an IP address,
+                // only match themselves
+                DomainMatch::Registrable => {
+                    match (
+                        self.registrable_domain(host),
+                        self.registrable_domain(&domain),
+                    ) {
+                        (Some(host), Some(domain)) => host == domain,
+                        _ => host == domain,
+                    }
+                }
+            }
+        })
+    }
+
+    fn allows_path(&self, url: &Url) -> bool {
+        let path = url.path();
+        let target = match url.query() {
+            Some(query) => format!("{}?{}", path, query),
+            None => path.to_string(),
+        };
+
+        let extension = path
+            .rsplit('/')
+            .next()
+            .and_then(|file| file.rsplit_once('.'))
+            .map(|(_, extension)| extension.to_lowercase());
+        if let Some(extension) = extension {
+            if self.blocked_extensions.contains(&extension) {
+                return false;
+            }
+        }
+
+        (self.path_prefixes.is_empty()
+            || self
+                .path_prefixes
+                .iter()
+                .any(|prefix| path.starts_with(prefix.as_str())))
+            && (self.include_patterns.is_empty()
+                || self
+                    .include_patterns
+                    .iter()
+                    .any(|pattern| pattern.is_match(&target).unwrap_or(false)))
+            && !self
+                .exclude_patterns
+                .iter()
+                .any(|pattern| pattern.is_match(&target).unwrap_or(false))
+    }
+}
+
+fn normalize_domain(domain: &str) -> String {
+    domain.trim().trim_start_matches('.').to_lowercase()
+}
+
+fn is_same_or_subdomain(host: &str, domain: &str) -> bool {
+    host == domain
+        || host
+            .strip_suffix(domain)
+            .is_some_and(|prefix| prefix.ends_with('.'))
+}
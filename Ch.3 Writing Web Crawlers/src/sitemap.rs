@@ -0,0 +1,439 @@
+use std::collections::{HashSet, VecDeque};
+use std::io::Read;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use flate2::read::GzDecoder;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use reqwest::{Client, Method};
+use url::Url;
+
+use crate::error::AppError;
+use crate::links::normalize_url;
+use crate::request_log::send_traced_capped;
+use crate::robots::RobotsTxt;
+use crate::scope::Scope;
+
+// Sitemap indexes can nest; this bounds how many sitemap files one site may make us fetch
+const MAX_SITEMAP_FILES: usize = 50;
+
+// The protocol's limit for one sitemap file. Decompression stops here, so a small gzip
+// file can't expand to fill memory.
+const MAX_SITEMAP_BYTES: u64 = 50 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChangeFreq {
+    Always,
+    Hourly,
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+    Never,
+}
+
+impl ChangeFreq {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "always" => Some(Self::Always),
+            "hourly" => Some(Self::Hourly),
+            "daily" => Some(Self::Daily),
+            "weekly" => Some(Self::Weekly),
+            "monthly" => Some(Self::Monthly),
+            "yearly" => Some(Self::Yearly),
+            "never" => Some(Self::Never),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SitemapEntry {
+    pub url: Url,
+    pub lastmod: Option<DateTime<Utc>>,
+    pub changefreq: Option<ChangeFreq>,
+    pub priority: Option<f32>,
+}
+
+// Fields of a `<url>`, `<item>` or `<entry>` element, which may come in any order
+#[derive(Debug, Default)]
+struct PendingEntry {
+    url: Option<Url>,
+    lastmod: Option<DateTime<Utc>>,
+    changefreq: Option<ChangeFreq>,
+    priority: Option<f32>,
+}
+
+impl PendingEntry {
+    fn finish(self) -> Option<SitemapEntry> {
+        Some(SitemapEntry {
+            url: self.url?,
+            lastmod: self.lastmod,
+            changefreq: self.changefreq,
+            priority: self.priority,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ParsedSitemap {
+    pub entries: Vec<SitemapEntry>,
+    // Child sitemaps listed by a sitemap index
+    pub sitemaps: Vec<Url>,
+}
+
+#[derive(Debug, Default)]
+pub struct SitemapDiscovery {
+    pub entries: Vec<SitemapEntry>,
+    pub errors: Vec<(String, String)>,
+}
+
+// Parses a urlset, a sitemap index, an RSS feed or an Atom feed. Gzip-compressed
+// documents are recognized by their magic bytes, whatever the file is called.
+pub fn parse_sitemap(bytes: &[u8]) -> Result<ParsedSitemap, AppError> {
+    let xml = if bytes.starts_with(&[0x1f, 0x8b]) {
+        let mut decompressed = Vec::new();
+        GzDecoder::new(bytes)
+            .take(MAX_SITEMAP_BYTES + 1)
+            .read_to_end(&mut decompressed)?;
+        decompressed
+    } else {
+        bytes.to_vec()
+    };
+    if xml.len() as u64 > MAX_SITEMAP_BYTES {
+        return Err(AppError::SitemapTooLarge(MAX_SITEMAP_BYTES));
+    }
+
+    let mut reader = Reader::from_reader(xml.as_slice());
+    reader.trim_text(true);
+
+    let mut parsed = ParsedSitemap::default();
+    let mut path: Vec<String> = Vec::new();
+    let mut entry: Option<PendingEntry> = None;
+    let mut buf = Vec::new();
+
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(element) => {
+                let name = local_name(&element);
+                if matches!(name.as_str(), "url" | "item" | "entry") {
+                    entry = Some(PendingEntry::default());
+                }
+                if name == "link" && in_atom_entry(&path) {
+                    atom_link(&element, &mut entry)?;
+                }
+                path.push(name);
+            }
+            Event::Empty(element) if local_name(&element) == "link" && in_atom_entry(&path) => {
+                atom_link(&element, &mut entry)?;
+            }
+            Event::Text(text) => {
+                let text = text.unescape()?.into_owned();
+                record_text(&path, text.trim(), &mut entry, &mut parsed);
+            }
+            Event::CData(text) => {
+                let text = String::from_utf8_lossy(&text.into_inner()).into_owned();
+                record_text(&path, text.trim(), &mut entry, &mut parsed);
+            }
+            Event::End(_) => {
+                if let Some(name) = path.pop() {
+                    if matches!(name.as_str(), "url" | "item" | "entry") {
+                        parsed
+                            .entries
+                            .extend(entry.take().and_then(PendingEntry::finish));
+                    }
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    Ok(parsed)
+}
+
+fn local_name(element: &BytesStart) -> String {
+    String::from_utf8_lossy(element.local_name().as_ref()).to_lowercase()
+}
+
+fn in_atom_entry(path: &[String]) -> bool {
+    path.last().is_some_and(|parent| parent == "entry")
+}
+
+// Atom entries carry their URL in `<link href="..."/>` rather than as text
+fn atom_link(element: &BytesStart, entry: &mut Option<PendingEntry>) -> Result<(), AppError> {
+    let mut href = None;
+    let mut rel = None;
+
+    for attribute in element.attributes().flatten() {
+        match attribute.key.local_name().as_ref() {
+            b"href" => href = Some(attribute.unescape_value()?.into_owned()),
+            b"rel" => rel = Some(attribute.unescape_value()?.into_owned()),
+            _ => {}
+        }
+    }
+
+    let is_alternate = rel.is_none_or(|rel| rel == "alternate");
+    if let (Some(href), true, Some(entry)) = (href, is_alternate, entry.as_mut()) {
+        if entry.url.is_none() {
+            entry.url = Url::parse(&href).ok().and_then(normalize_url);
+        }
+    }
+
+    Ok(())
+}
+
+fn record_text(
+    path: &[String],
+    text: &str,
+    entry: &mut Option<PendingEntry>,
+    parsed: &mut ParsedSitemap,
+) {
+    let (Some(field), Some(parent)) = (path.last(), path.iter().rev().nth(1)) else {
+        return;
+    };
+
+    match (parent.as_str(), field.as_str()) {
+        ("sitemap", "loc") => {
+            if let Some(url) = Url::parse(text).ok().and_then(normalize_url) {
+                parsed.sitemaps.push(url);
+            }
+        }
+        ("url", "loc") | ("item", "link") => {
+            if let Some(entry) = entry.as_mut() {
+                entry.url = Url::parse(text).ok().and_then(normalize_url);
+            }
+        }
+        ("url", "lastmod") | ("item", "pubdate") | ("entry", "updated") => {
+            if let Some(entry) = entry.as_mut() {
+                entry.lastmod = parse_date(text);
+            }
+        }
+        ("url", "changefreq") => {
+            if let Some(entry) = entry.as_mut() {
+                entry.changefreq = ChangeFreq::parse(text);
+            }
+        }
+        ("url", "priority") => {
+            if let Some(entry) = entry.as_mut() {
+                entry.priority = text.parse::<f32>().ok().map(|p| p.clamp(0.0, 1.0));
+            }
+        }
+        _ => {}
+    }
+}
+
+// Finds the sitemaps a site advertises in robots.txt, falling back to /sitemap.xml,
+// and follows sitemap indexes down to the page entries. Only pages in the crawl's
+// scope are returned.
+pub async fn discover_sitemaps(
+    client: &Client,
+    site: &Url,
+    robots: &RobotsTxt,
+    scope: &Scope,
+) -> SitemapDiscovery {
+    let mut discovery = SitemapDiscovery::default();
+    let mut queue = robots
+        .sitemaps()
+        .iter()
+        .filter_map(|sitemap| Url::parse(sitemap).ok())
+        .collect::<VecDeque<_>>();
+
+    if queue.is_empty() {
+        if let Ok(default_sitemap) = site.join("/sitemap.xml") {
+            queue.push_back(default_sitemap);
+        }
+    }
+
+    let mut fetched = HashSet::new();
+    let mut seen_pages = HashSet::new();
+
+    while let Some(sitemap_url) = queue.pop_front() {
+        if fetched.len() >= MAX_SITEMAP_FILES {
+            break;
+        }
+        if !fetched.insert(sitemap_url.to_string()) {
+            continue;
+        }
+
+        match fetch_sitemap(client, &sitemap_url).await {
+            Ok(parsed) => {
+                queue.extend(parsed.sitemaps);
+                for entry in parsed.entries {
+                    if scope.contains(&entry.url) && seen_pages.insert(entry.url.to_string()) {
+                        discovery.entries.push(entry);
+                    }
+                }
+            }
+            Err(err) => discovery
+                .errors
+                .push((sitemap_url.to_string(), err.to_string())),
+        }
+    }
+
+    discovery
+}
+
+// Sitemaps use W3C datetimes, which may leave out the time or even the day, RSS feeds
+// use RFC 2822 dates and Atom feeds RFC 3339 ones. Dates without a time zone are UTC.
+fn parse_date(text: &str) -> Option<DateTime<Utc>> {
+    let text = text.trim();
+    if let Ok(date) = DateTime::parse_from_rfc3339(text) {
+        return Some(date.with_timezone(&Utc));
+    }
+    if let Ok(date) = DateTime::parse_from_rfc2822(text) {
+        return Some(date.with_timezone(&Utc));
+    }
+    // `2023-10-01T12:00Z`, a W3C datetime without seconds
+    if let Ok(date) = DateTime::parse_from_str(text, "%Y-%m-%dT%H:%M%#z") {
+        return Some(date.with_timezone(&Utc));
+    }
+
+    let date = match text.len() {
+        4 => format!("{}-01-01", text),
+        7 => format!("{}-01", text),
+        _ => text.to_string(),
+    };
+    NaiveDate::parse_from_str(&date, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|date| date.and_utc())
+}
+
+async fn fetch_sitemap(client: &Client, url: &Url) -> Result<ParsedSitemap, AppError> {
+    // One byte past the limit is enough to know the sitemap is too large
+    let (status, body) = send_traced_capped(
+        client,
+        Method::GET,
+        url,
+        None,
+        MAX_SITEMAP_BYTES as usize + 1,
+    )
+    .await?;
+    if !(200..300).contains(&status) {
+        return Err(AppError::UnexpectedStatus(status, url.to_string()));
+    }
+    if body.len() as u64 > MAX_SITEMAP_BYTES {
+        return Err(AppError::SitemapTooLarge(MAX_SITEMAP_BYTES));
+    }
+    parse_sitemap(&body)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    use super::*;
+
+    fn utc(text: &str) -> Option<DateTime<Utc>> {
+        Some(
+            DateTime::parse_from_rfc3339(text)
+                .unwrap()
+                .with_timezone(&Utc),
+        )
+    }
+
+    #[test]
+    fn dates_of_every_format_are_parsed() {
+        for (text, expected) in [
+            ("2023-10-01T12:30:15+02:00", "2023-10-01T10:30:15Z"),
+            ("2023-10-01T12:30:15.5Z", "2023-10-01T12:30:15.5Z"),
+            ("2023-10-01T12:30Z", "2023-10-01T12:30:00Z"),
+            ("2023-10-01T12:30-05:00", "2023-10-01T17:30:00Z"),
+            ("2023-10-01", "2023-10-01T00:00:00Z"),
+            ("2023-10", "2023-10-01T00:00:00Z"),
+            ("2023", "2023-01-01T00:00:00Z"),
+            ("Sun, 01 Oct 2023 12:30:15 GMT", "2023-10-01T12:30:15Z"),
+            ("Mon, 2 Oct 2023 08:00:00 +0200", "2023-10-02T06:00:00Z"),
+        ] {
+            assert_eq!(parse_date(text), utc(expected), "{}", text);
+        }
+        assert_eq!(parse_date("yesterday"), None);
+    }
+
+    #[test]
+    fn feed_entries_are_ordered_by_date_not_weekday() {
+        let feed = r#"<rss><channel>
+            <item><link>https://example.com/old</link><pubDate>Wed, 01 Jan 2020 00:00:00 GMT</pubDate></item>
+            <item><link>https://example.com/new</link><pubDate>Fri, 01 Sep 2023 00:00:00 GMT</pubDate></item>
+            <item><link>https://example.com/mid</link><pubDate>Thu, 01 Jun 2023 00:00:00 GMT</pubDate></item>
+        </channel></rss>"#;
+        let parsed = parse_sitemap(feed.as_bytes()).unwrap();
+
+        let mut frontier = crate::frontier::Frontier::new();
+        for entry in &parsed.entries {
+            frontier.push_with_hint(entry.url.clone(), 0, Some(entry));
+        }
+        let order = std::iter::from_fn(|| frontier.pop_where(|_| true))
+            .map(|(url, _)| url.path().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(order, ["/new", "/mid", "/old"]);
+    }
+
+    #[test]
+    fn gzip_bombs_are_cut_off() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        let chunk = vec![b' '; 1024 * 1024];
+        for _ in 0..=MAX_SITEMAP_BYTES / chunk.len() as u64 {
+            encoder.write_all(&chunk).unwrap();
+        }
+        let bomb = encoder.finish().unwrap();
+        assert!(bomb.len() < 1024 * 1024);
+
+        assert!(matches!(
+            parse_sitemap(&bomb),
+            Err(AppError::SitemapTooLarge(MAX_SITEMAP_BYTES))
+        ));
+    }
+
+    #[tokio::test]
+    async fn downloads_stop_at_the_size_limit() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+        use std::sync::Arc;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // A sitemap that never ends, without a Content-Length
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!(
+            "http://{}/sitemap.xml",
+            listener.local_addr().unwrap()
+        ))
+        .unwrap();
+        let sent = Arc::new(AtomicU64::new(0));
+        let server_sent = sent.clone();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).await;
+            let _ = stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Type: application/xml\r\nConnection: close\r\n\r\n<urlset>")
+                .await;
+            let chunk = vec![b' '; 1024 * 1024];
+            while stream.write_all(&chunk).await.is_ok() {
+                server_sent.fetch_add(chunk.len() as u64, Ordering::SeqCst);
+            }
+        });
+
+        assert!(matches!(
+            fetch_sitemap(&Client::new(), &url).await,
+            Err(AppError::SitemapTooLarge(MAX_SITEMAP_BYTES))
+        ));
+        let sent = sent.load(Ordering::SeqCst);
+        assert!(sent < 2 * MAX_SITEMAP_BYTES, "{} bytes sent", sent);
+    }
+
+    #[test]
+    fn gzip_sitemaps_within_the_limit_are_read() {
+        let xml = "<urlset><url><loc>https://example.com/a</loc><lastmod>2023-10-01</lastmod></url></urlset>";
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(xml.as_bytes()).unwrap();
+
+        let parsed = parse_sitemap(&encoder.finish().unwrap()).unwrap();
+        assert_eq!(parsed.entries.len(), 1);
+        assert_eq!(parsed.entries[0].lastmod, utc("2023-10-01T00:00:00Z"));
+    }
+}
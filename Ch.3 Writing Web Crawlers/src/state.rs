@@ -0,0 +1,522 @@
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+use rusqlite::{params, Connection, OptionalExtension};
+use url::Url;
+
+use crate::error::AppError;
+use crate::frontier::SitemapRank;
+use crate::graph::LinkGraph;
+use crate::links::Link;
+use crate::sitemap::SitemapEntry;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS crawl_info (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS pages (
+        url TEXT PRIMARY KEY,
+        depth INTEGER NOT NULL,
+        state TEXT NOT NULL,
+        status INTEGER,
+        attempts INTEGER NOT NULL DEFAULT 0,
+        error TEXT
+    );
+    CREATE TABLE IF NOT EXISTS links (
+        source TEXT NOT NULL,
+        target TEXT NOT NULL,
+        anchor_text TEXT NOT NULL,
+        rel TEXT NOT NULL,
+        internal INTEGER NOT NULL,
+        UNIQUE (source, target, anchor_text)
+    );
+";
+
+// The frontier is the queued pages in the same order as `Frontier`, so these columns are
+// added to state files written before the frontier was kept on disk
+const FRONTIER_COLUMNS: &[(&str, &str)] = &[
+    ("priority", "INTEGER NOT NULL DEFAULT 500"),
+    ("changefreq", "INTEGER NOT NULL DEFAULT 3"),
+    ("lastmod", "INTEGER"),
+    ("sequence", "INTEGER NOT NULL DEFAULT 0"),
+];
+
+const INDEXES: &str = "
+    CREATE INDEX IF NOT EXISTS frontier_order ON pages
+        (state, depth, priority DESC, changefreq, lastmod DESC, sequence);
+    CREATE INDEX IF NOT EXISTS links_by_target ON links (target, internal);
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageState {
+    // Seen but not fetched yet, including pages that were being fetched when the crawl
+    // stopped and failed pages waiting for another attempt
+    Queued,
+    Done,
+    Failed,
+    Disallowed,
+}
+
+impl PageState {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Queued => "queued",
+            Self::Done => "done",
+            Self::Failed => "failed",
+            Self::Disallowed => "disallowed",
+        }
+    }
+}
+
+impl fmt::Display for PageState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct QueuedPage {
+    pub url: Url,
+    pub depth: usize,
+    pub attempts: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct FailedPage {
+    pub url: String,
+    pub depth: usize,
+    pub attempts: usize,
+    pub error: String,
+}
+
+#[derive(Debug, Default)]
+pub struct StateSummary {
+    pub start_url: Option<String>,
+    pub pages_by_state: BTreeMap<String, usize>,
+    pub status_counts: BTreeMap<u16, usize>,
+    pub links: usize,
+}
+
+impl StateSummary {
+    pub fn print(&self) {
+        println!(
+            "Crawl of {}",
+            self.start_url.as_deref().unwrap_or("(not started)")
+        );
+        println!("  Pages by state:");
+        for (state, count) in &self.pages_by_state {
+            println!("    {}: {}", state, count);
+        }
+        println!("  Responses by status:");
+        for (status, count) in &self.status_counts {
+            println!("    {}: {}", status, count);
+        }
+        println!("  Links recorded: {}", self.links);
+    }
+}
+
+// Crawl progress kept in a SQLite database, so a crawl can be stopped and resumed and
+// its state inspected. Writes are grouped into transactions that are only committed at
+// checkpoints. A crash, or dropping the store without a checkpoint, loses the work since
+// the last checkpoint, and those pages are simply fetched again, but never leaves the
+// database inconsistent.
+pub struct CrawlStore {
+    connection: Connection,
+    next_sequence: Cell<i64>,
+}
+
+impl CrawlStore {
+    pub fn open(path: &Path) -> Result<Self, AppError> {
+        let connection = Connection::open(path)?;
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.pragma_update(None, "synchronous", "NORMAL")?;
+        connection.execute_batch(SCHEMA)?;
+        add_frontier_columns(&connection)?;
+        connection.execute_batch(INDEXES)?;
+        connection.execute_batch("BEGIN")?;
+
+        let next_sequence = connection.query_row(
+            "SELECT coalesce(max(sequence), 0) + 1 FROM pages",
+            [],
+            |row| row.get(0),
+        )?;
+
+        Ok(Self {
+            connection,
+            next_sequence: Cell::new(next_sequence),
+        })
+    }
+
+    // Pages queued later, or queued again after failing, go behind the others of their
+    // depth and rank
+    fn sequence(&self) -> i64 {
+        let sequence = self.next_sequence.get();
+        self.next_sequence.set(sequence + 1);
+        sequence
+    }
+
+    pub fn checkpoint(&mut self) -> Result<(), AppError> {
+        self.connection.execute_batch("COMMIT; BEGIN")?;
+        Ok(())
+    }
+
+    pub fn start_url(&self) -> Result<Option<String>, AppError> {
+        let start_url = self
+            .connection
+            .query_row(
+                "SELECT value FROM crawl_info WHERE key = 'start_url'",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(start_url)
+    }
+
+    pub fn set_start_url(&self, url: &Url) -> Result<(), AppError> {
+        self.connection.execute(
+            "INSERT OR REPLACE INTO crawl_info (key, value) VALUES ('start_url', ?1)",
+            params![url.as_str()],
+        )?;
+        Ok(())
+    }
+
+    // The seen set and the frontier: a new page is queued, ranked by its sitemap entry
+    // if it has one. Returns false when the page was already known.
+    pub fn add_page(
+        &self,
+        url: &Url,
+        depth: usize,
+        hint: Option<&SitemapEntry>,
+    ) -> Result<bool, AppError> {
+        let rank = SitemapRank::of(hint);
+        let inserted = self
+            .connection
+            .prepare_cached(
+                "INSERT OR IGNORE INTO pages
+                     (url, depth, state, priority, changefreq, lastmod, sequence)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            )?
+            .execute(params![
+                url.as_str(),
+                depth,
+                PageState::Queued.as_str(),
+                rank.priority,
+                rank.changefreq as i64,
+                rank.lastmod.map(|lastmod| lastmod.timestamp_millis()),
+                self.sequence()
+            ])?;
+        Ok(inserted == 1)
+    }
+
+    pub fn page_state(&self, url: &Url) -> Result<Option<PageState>, AppError> {
+        let state: Option<String> = self
+            .connection
+            .prepare_cached("SELECT state FROM pages WHERE url = ?1")?
+            .query_row(params![url.as_str()], |row| row.get(0))
+            .optional()?;
+
+        Ok(state.and_then(|state| match state.as_str() {
+            "queued" => Some(PageState::Queued),
+            "done" => Some(PageState::Done),
+            "failed" => Some(PageState::Failed),
+            "disallowed" => Some(PageState::Disallowed),
+            _ => None,
+        }))
+    }
+
+    pub fn record_page(
+        &self,
+        url: &Url,
+        depth: usize,
+        state: PageState,
+        status: Option<u16>,
+        attempts: usize,
+        error: Option<&str>,
+    ) -> Result<(), AppError> {
+        self.connection
+            .prepare_cached(
+                "INSERT INTO pages (url, depth, state, status, attempts, error, sequence)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                 ON CONFLICT (url) DO UPDATE SET
+                     state = excluded.state,
+                     status = excluded.status,
+                     attempts = excluded.attempts,
+                     error = excluded.error,
+                     sequence = excluded.sequence",
+            )?
+            .execute(params![
+                url.as_str(),
+                depth,
+                state.as_str(),
+                status,
+                attempts,
+                error,
+                self.sequence()
+            ])?;
+        Ok(())
+    }
+
+    pub fn record_link(&self, source: &Url, link: &Link, internal: bool) -> Result<(), AppError> {
+        self.connection
+            .prepare_cached(
+                "INSERT OR IGNORE INTO links (source, target, anchor_text, rel, internal)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?
+            .execute(params![
+                source.as_str(),
+                link.url.as_str(),
+                link.anchor_text,
+                link.rel.join(" "),
+                internal
+            ])?;
+        Ok(())
+    }
+
+    // Takes the first queued page, in frontier order, that `available` accepts. The page
+    // stays queued until it's recorded, so the caller skips the pages it's fetching.
+    pub fn next_queued<F>(&self, available: F) -> Result<Option<QueuedPage>, AppError>
+    where
+        F: Fn(&Url) -> bool,
+    {
+        let mut statement = self.connection.prepare_cached(
+            "SELECT url, depth, attempts FROM pages WHERE state = ?1
+             ORDER BY depth, priority DESC, changefreq, lastmod DESC, sequence",
+        )?;
+        let mut rows = statement.query(params![PageState::Queued.as_str()])?;
+        while let Some(row) = rows.next()? {
+            let url = Url::parse(&row.get::<_, String>(0)?)?;
+            if available(&url) {
+                return Ok(Some(QueuedPage {
+                    url,
+                    depth: row.get(1)?,
+                    attempts: row.get(2)?,
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn queued_count(&self) -> Result<usize, AppError> {
+        let count = self
+            .connection
+            .prepare_cached("SELECT count(*) FROM pages WHERE state = ?1")?
+            .query_row(params![PageState::Queued.as_str()], |row| row.get(0))?;
+        Ok(count)
+    }
+
+    // Whether a link to this out-of-scope page was recorded before
+    pub fn has_external(&self, url: &Url) -> Result<bool, AppError> {
+        let seen = self
+            .connection
+            .prepare_cached(
+                "SELECT EXISTS (SELECT 1 FROM links WHERE target = ?1 AND NOT internal)",
+            )?
+            .query_row(params![url.as_str()], |row| row.get(0))?;
+        Ok(seen)
+    }
+
+    pub fn external_count(&self) -> Result<usize, AppError> {
+        let count = self.connection.query_row(
+            "SELECT count(DISTINCT target) FROM links WHERE NOT internal",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(count)
+    }
+
+    pub fn failed_pages(&self) -> Result<Vec<FailedPage>, AppError> {
+        let mut statement = self.connection.prepare(
+            "SELECT url, depth, attempts, coalesce(error, '') FROM pages
+             WHERE state = ?1 ORDER BY rowid",
+        )?;
+        let rows = statement.query_map(params![PageState::Failed.as_str()], |row| {
+            Ok(FailedPage {
+                url: row.get(0)?,
+                depth: row.get(1)?,
+                attempts: row.get(2)?,
+                error: row.get(3)?,
+            })
+        })?;
+
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    pub fn disallowed_pages(&self) -> Result<Vec<String>, AppError> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT url FROM pages WHERE state = ?1 ORDER BY rowid")?;
+        let rows =
+            statement.query_map(params![PageState::Disallowed.as_str()], |row| row.get(0))?;
+
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    // Rebuilds the link graph of the pages crawled so far
+    pub fn load_graph(&self) -> Result<LinkGraph, AppError> {
+        let mut graph = LinkGraph::new();
+        if let Some(start_url) = self.start_url()? {
+            graph.add_root(&start_url);
+        }
+
+        let mut statement = self
+            .connection
+            .prepare("SELECT url, status FROM pages ORDER BY rowid")?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let url: String = row.get(0)?;
+            match row.get::<_, Option<u16>>(1)? {
+                Some(status) => graph.set_status(&url, true, status),
+                None => {
+                    graph.add_node(&url, true);
+                }
+            }
+        }
+
+        let mut statement = self.connection.prepare(
+            "SELECT source, target, anchor_text, rel, internal FROM links ORDER BY rowid",
+        )?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let source: String = row.get(0)?;
+            let target: String = row.get(1)?;
+            let rel: String = row.get(3)?;
+            let link = Link {
+                url: Url::parse(&target)?,
+                anchor_text: row.get(2)?,
+                rel: rel.split_whitespace().map(str::to_string).collect(),
+            };
+            graph.add_edge(&source, &link, row.get(4)?);
+        }
+
+        Ok(graph)
+    }
+
+    pub fn summary(&self) -> Result<StateSummary, AppError> {
+        let mut summary = StateSummary {
+            start_url: self.start_url()?,
+            ..StateSummary::default()
+        };
+
+        let mut statement = self
+            .connection
+            .prepare("SELECT state, count(*) FROM pages GROUP BY state")?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            summary.pages_by_state.insert(row.get(0)?, row.get(1)?);
+        }
+
+        let mut statement = self.connection.prepare(
+            "SELECT status, count(*) FROM pages WHERE status IS NOT NULL GROUP BY status",
+        )?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            summary.status_counts.insert(row.get(0)?, row.get(1)?);
+        }
+
+        summary.links = self
+            .connection
+            .query_row("SELECT count(*) FROM links", [], |row| row.get(0))?;
+
+        Ok(summary)
+    }
+}
+
+fn add_frontier_columns(connection: &Connection) -> Result<(), AppError> {
+    let mut statement = connection.prepare("SELECT name FROM pragma_table_info('pages')")?;
+    let columns = statement
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for (name, definition) in FRONTIER_COLUMNS {
+        if !columns.iter().any(|column| column == name) {
+            connection.execute_batch(&format!(
+                "ALTER TABLE pages ADD COLUMN {} {}",
+                name, definition
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+impl Drop for CrawlStore {
+    fn drop(&mut self) {
+        // Pages recorded since the last checkpoint may have output that was never
+        // written, so only a checkpoint keeps them
+        let _ = self.connection.execute_batch("ROLLBACK");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sitemap::ChangeFreq;
+
+    fn url(path: &str) -> Url {
+        Url::parse(&format!("http://example.com{}", path)).unwrap()
+    }
+
+    fn next_path(store: &CrawlStore, fetching: &[&str]) -> Option<String> {
+        store
+            .next_queued(|url| !fetching.contains(&url.path()))
+            .unwrap()
+            .map(|page| page.url.path().to_string())
+    }
+
+    #[test]
+    fn queued_pages_come_out_in_frontier_order() {
+        let path = std::env::temp_dir().join(format!("frontier-{}.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        {
+            let mut store = CrawlStore::open(&path).unwrap();
+            let important = SitemapEntry {
+                url: url("/important"),
+                lastmod: None,
+                changefreq: Some(ChangeFreq::Daily),
+                priority: Some(0.9),
+            };
+            assert!(store.add_page(&url("/deep"), 1, None).unwrap());
+            assert!(store.add_page(&url("/first"), 0, None).unwrap());
+            assert!(store.add_page(&url("/second"), 0, None).unwrap());
+            assert!(store
+                .add_page(&url("/important"), 0, Some(&important))
+                .unwrap());
+            assert!(!store.add_page(&url("/first"), 1, None).unwrap());
+
+            // A failed page goes to the back of its depth
+            store
+                .record_page(
+                    &url("/first"),
+                    0,
+                    PageState::Queued,
+                    None,
+                    1,
+                    Some("timeout"),
+                )
+                .unwrap();
+            store.checkpoint().unwrap();
+        }
+
+        let store = CrawlStore::open(&path).unwrap();
+        assert_eq!(store.queued_count().unwrap(), 4);
+        assert_eq!(next_path(&store, &[]).as_deref(), Some("/important"));
+        assert_eq!(
+            next_path(&store, &["/important"]).as_deref(),
+            Some("/second")
+        );
+        assert_eq!(
+            next_path(&store, &["/important", "/second"]).as_deref(),
+            Some("/first")
+        );
+        assert_eq!(
+            next_path(&store, &["/important", "/second", "/first"]).as_deref(),
+            Some("/deep")
+        );
+
+        drop(store);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,150 @@
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use writing_web_crawlers::crawler::{CrawlLimits, CrawlOptions, CrawlReport, Crawler, StopReason};
+
+// Page n links back to the home page and on to pages 8n+1 to 8n+8, a tree wide enough
+// to keep every worker busy
+const LINKS_PER_PAGE: usize = 8;
+
+// What every response waits before it is sent, like the network would
+const RESPONSE_DELAY: Duration = Duration::from_millis(2);
+
+#[derive(Default)]
+struct Load {
+    in_flight: AtomicUsize,
+    most_in_flight: AtomicUsize,
+    requests: AtomicUsize,
+}
+
+// A site of `pages` generated pages at /page/0 to /page/{pages - 1}
+async fn serve_site(pages: usize) -> (SocketAddr, Arc<Load>) {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let load = Arc::new(Load::default());
+
+    let server_load = load.clone();
+    tokio::spawn(async move {
+        loop {
+            let Ok((stream, _)) = listener.accept().await else {
+                continue;
+            };
+            let load = server_load.clone();
+            tokio::spawn(async move {
+                let _ = answer(stream, pages, &load).await;
+            });
+        }
+    });
+
+    (address, load)
+}
+
+async fn answer(mut stream: TcpStream, pages: usize, load: &Load) -> std::io::Result<()> {
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+
+    let in_flight = load.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+    load.most_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+    load.requests.fetch_add(1, Ordering::SeqCst);
+    tokio::time::sleep(RESPONSE_DELAY).await;
+
+    let request = String::from_utf8_lossy(&request);
+    let path = request.split_whitespace().nth(1).unwrap_or("/");
+    let page = path
+        .strip_prefix("/page/")
+        .and_then(|number| number.parse::<usize>().ok())
+        .filter(|page| *page < pages);
+    let response = match page {
+        Some(page) => {
+            let links = (page * LINKS_PER_PAGE + 1..=page * LINKS_PER_PAGE + LINKS_PER_PAGE)
+                .filter(|link| *link < pages)
+                .map(|link| format!("<a href=\"/page/{}\">Page {}</a>", link, link))
+                .collect::<String>();
+            let body = format!(
+                "<html><body><h1>Page {}</h1><a href=\"/page/0\">Home</a>{}</body></html>",
+                page, links
+            );
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        }
+        None => {
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+        }
+    };
+
+    load.in_flight.fetch_sub(1, Ordering::SeqCst);
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+async fn crawl(address: SocketAddr, workers: usize, max_per_host: usize) -> CrawlReport {
+    let limits = CrawlLimits {
+        max_depth: 100,
+        max_pages: 100_000,
+        max_duration: None,
+        workers,
+        max_per_host,
+        retries: 0,
+    };
+    let crawler = Crawler::new(limits, CrawlOptions::default()).unwrap();
+    let (_, report) = crawler
+        .crawl(&format!("http://{}/page/0", address))
+        .await
+        .unwrap();
+    report
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn workers_crawl_the_same_pages_faster() {
+    let pages = 2000;
+    let (address, load) = serve_site(pages).await;
+
+    let serial = crawl(address, 1, 1).await;
+    assert_eq!(load.most_in_flight.load(Ordering::SeqCst), 1);
+    let concurrent = crawl(address, 8, 8).await;
+
+    for report in [&serial, &concurrent] {
+        assert_eq!(report.stop_reason, StopReason::FrontierExhausted);
+        assert_eq!(report.pages_fetched, pages);
+        assert_eq!(report.status_counts.get(&200), Some(&pages));
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+    }
+    assert_eq!(load.requests.load(Ordering::SeqCst), 2 * pages);
+    assert!(
+        concurrent.elapsed * 2 < serial.elapsed,
+        "8 workers took {:?}, 1 worker took {:?}",
+        concurrent.elapsed,
+        serial.elapsed
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn requests_to_a_host_stay_within_its_limit() {
+    let pages = 300;
+    let (address, load) = serve_site(pages).await;
+
+    let report = crawl(address, 16, 3).await;
+
+    assert_eq!(report.pages_fetched, pages);
+    let most_in_flight = load.most_in_flight.load(Ordering::SeqCst);
+    assert!(
+        (2..=3).contains(&most_in_flight),
+        "{} requests at once",
+        most_in_flight
+    );
+}
@@ -0,0 +1,54 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::fields::FieldValue;
+use crate::history::ChangeKind;
+
+// What the crawler keeps of one page
+#[derive(Debug, Clone, Serialize)]
+pub struct Content {
+    pub site: String,
+    // The search that found the page, for pages found through a site's search
+    pub topic: Option<String>,
+    pub url: String,
+    // How the page changed since the last incremental crawl. Removed pages have no
+    // title, body or fields.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub change: Option<ChangeKind>,
+    pub title: String,
+    pub body: String,
+    // The site's own fields, by name
+    pub fields: BTreeMap<String, FieldValue>,
+}
+
+impl Content {
+    // The record of a page that has disappeared since an earlier crawl
+    pub fn removed(site: &str, url: &str) -> Self {
+        Self {
+            site: site.to_string(),
+            topic: None,
+            url: url.to_string(),
+            change: Some(ChangeKind::Removed),
+            title: String::new(),
+            body: String::new(),
+            fields: BTreeMap::new(),
+        }
+    }
+
+    pub fn print(&self) {
+        println!("SITE: {}", self.site);
+        if let Some(topic) = &self.topic {
+            println!("TOPIC: {}", topic);
+        }
+        println!("URL: {}", self.url);
+        if let Some(change) = self.change {
+            println!("CHANGE: {}", change);
+        }
+        println!("TITLE: {}", self.title);
+        for (name, value) in &self.fields {
+            println!("{}: {}", name.to_uppercase(), value);
+        }
+        println!("BODY:\n{}", self.body);
+    }
+}
@@ -0,0 +1,61 @@
+use std::path::PathBuf;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ConfigError {
+    #[error("Could not read {0}: {1}")]
+    Io(PathBuf, #[source] std::io::Error),
+
+    #[error("Unsupported site definition format: {0} (expected .toml, .yaml, .yml or .json)")]
+    UnsupportedFormat(PathBuf),
+
+    #[error("Invalid site definition in {0}: {1}")]
+    InvalidSite(PathBuf, String),
+
+    #[error("Site {0} in {1} is already defined in {2}")]
+    DuplicateSite(String, PathBuf, PathBuf),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SinkError {
+    #[error("Unsupported output format: {0} (expected .jsonl, .csv, .sqlite or a directory for Markdown files)")]
+    UnsupportedFormat(PathBuf),
+
+    #[error("Could not write {0}: {1}")]
+    Io(PathBuf, #[source] std::io::Error),
+
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("YAML error: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+
+    #[error("CSV error: {0}")]
+    Csv(#[from] csv::Error),
+
+    #[error("{0} has other columns than this crawl writes")]
+    CsvColumns(PathBuf),
+
+    #[error("SQLite error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum HistoryError {
+    #[error("Content history error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum RenderError {
+    #[error("Could not reach the WebDriver server: {0}")]
+    Http(#[from] reqwest::Error),
+
+    #[error("WebDriver error {0}: {1}")]
+    WebDriver(String, String),
+
+    #[error("The WebDriver server sent no {0}")]
+    MissingValue(&'static str),
+
+    #[error("Invalid screenshot: {0}")]
+    Screenshot(#[from] base64::DecodeError),
+}
@@ -0,0 +1,357 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime};
+use scraper::Html;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use url::Url;
+use writing_web_crawlers::links::{base_url, resolve_href};
+
+use crate::json::{value_text, DataSource, JsonQuery};
+use crate::website::CssSelector;
+
+// Languages that write 1.234,5 rather than 1,234.5
+const DECIMAL_COMMA_LANGUAGES: [&str; 24] = [
+    "bg", "ca", "cs", "da", "de", "el", "es", "et", "fi", "fr", "hr", "hu", "id", "it", "lt", "lv",
+    "nb", "nl", "pl", "pt", "ro", "ru", "sv", "tr",
+];
+
+// Regions of those languages that write 1'234.5 or 1,234.5 anyway
+const DECIMAL_POINT_REGIONS: [&str; 3] = ["ch", "li", "mx"];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Cardinality {
+    // The first match
+    #[default]
+    One,
+    // Every match, in page order
+    Many,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum FieldParser {
+    // The text with whitespace collapsed
+    #[default]
+    Text,
+    // A number written the way `locale` writes them, e.g. "de" for 1.234,5. Currency
+    // symbols and units around the number are ignored.
+    Number {
+        locale: Option<String>,
+    },
+    // A date or date and time in a chrono format like "%d %B %Y", or RFC 3339
+    // without one
+    Date {
+        format: Option<String>,
+    },
+    // A link, resolved against the page's URL
+    Url,
+}
+
+impl FieldParser {
+    pub fn parse(&self, text: &str, page_url: &Url) -> Result<FieldValue, String> {
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        match self {
+            Self::Text => Ok(FieldValue::Text(text)),
+            Self::Number { locale } => parse_number(&text, locale.as_deref())
+                .map(FieldValue::Number)
+                .ok_or_else(|| format!("`{}` is not a number", text)),
+            Self::Date { format } => parse_date(&text, format.as_deref()),
+            Self::Url => resolve_href(page_url, &text)
+                .map(|url| FieldValue::Url(url.to_string()))
+                .ok_or_else(|| format!("`{}` is not a link", text)),
+        }
+    }
+}
+
+// How to pull one named value out of a page
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldSpec {
+    // Elements of the page, or...
+    pub selector: Option<CssSelector>,
+    // ...values in the site's data
+    pub path: Option<JsonQuery>,
+    // Read this attribute of the matched elements instead of their text
+    #[serde(default)]
+    pub attribute: Option<String>,
+    #[serde(default)]
+    pub cardinality: Cardinality,
+    // Pages without a value for a required field aren't kept
+    #[serde(default)]
+    pub required: bool,
+    // Used when nothing matches. It goes through the parser like a matched value.
+    #[serde(default)]
+    pub default: Option<String>,
+    #[serde(default)]
+    pub parser: FieldParser,
+}
+
+impl FieldSpec {
+    // What the types don't already check
+    pub fn validate(&self, name: &str) -> Result<(), String> {
+        match (&self.selector, &self.path) {
+            (None, None) => return Err(format!("field {} needs a selector or a path", name)),
+            (Some(_), Some(_)) => {
+                return Err(format!(
+                    "field {} has a selector and a path, pick one",
+                    name
+                ))
+            }
+            (None, Some(_)) if self.attribute.is_some() => {
+                return Err(format!(
+                    "field {} reads a path, so it has no attribute",
+                    name
+                ))
+            }
+            _ => {}
+        }
+        if self.required && self.default.is_some() {
+            return Err(format!(
+                "field {} is required, so its default would never be used",
+                name
+            ));
+        }
+        if let Some(default) = &self.default {
+            let url = Url::parse("http://localhost/").unwrap();
+            self.parser
+                .parse(default, &url)
+                .map_err(|err| format!("default of field {}: {}", name, err))?;
+        }
+        Ok(())
+    }
+}
+
+// Where the title or the body of a page is: the elements a selector matches, or the
+// values at a path in the page's data
+#[derive(Debug, Clone, Copy)]
+pub struct TextLocation<'a> {
+    pub tag: Option<&'a CssSelector>,
+    pub path: Option<&'a JsonQuery>,
+}
+
+impl TextLocation<'_> {
+    fn validate(&self, name: &str) -> Result<(), String> {
+        match (self.tag, self.path) {
+            (None, None) => Err(format!("set {}_tag or {}_path", name, name)),
+            (Some(_), Some(_)) => Err(format!("set {}_tag or {}_path, not both", name, name)),
+            _ => Ok(()),
+        }
+    }
+}
+
+// What a site reads from its pages. Paths only work on sites with data.
+pub fn validate_layout(
+    title: TextLocation,
+    body: TextLocation,
+    fields: &BTreeMap<String, FieldSpec>,
+    data: Option<&DataSource>,
+) -> Result<(), String> {
+    title.validate("title")?;
+    body.validate("body")?;
+    for (name, spec) in fields {
+        spec.validate(name)?;
+    }
+
+    let uses_data = title.path.is_some()
+        || body.path.is_some()
+        || fields.values().any(|spec| spec.path.is_some());
+    if uses_data && data.is_none() {
+        return Err("paths read the site's data, but the site has no data source".to_string());
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum FieldValue {
+    Text(String),
+    Number(f64),
+    Date(NaiveDate),
+    DateTime(NaiveDateTime),
+    // A date and time with its UTC offset
+    ZonedDateTime(DateTime<FixedOffset>),
+    Url(String),
+    List(Vec<FieldValue>),
+}
+
+impl fmt::Display for FieldValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Text(text) | Self::Url(text) => f.write_str(text),
+            Self::Number(number) => write!(f, "{}", number),
+            Self::Date(date) => write!(f, "{}", date),
+            Self::DateTime(date_time) => write!(f, "{}", date_time.format("%Y-%m-%dT%H:%M:%S")),
+            Self::ZonedDateTime(date_time) => f.write_str(&date_time.to_rfc3339()),
+            Self::List(values) => {
+                let values = values.iter().map(ToString::to_string).collect::<Vec<_>>();
+                f.write_str(&values.join(", "))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FieldIssue {
+    Missing,
+    // A matched value the field's parser rejected
+    Invalid(String),
+}
+
+// Something wrong with one field of one page
+#[derive(Debug, Clone)]
+pub struct FieldProblem {
+    pub field: String,
+    pub required: bool,
+    pub issue: FieldIssue,
+}
+
+impl fmt::Display for FieldProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = if self.required {
+            "required field"
+        } else {
+            "field"
+        };
+        match &self.issue {
+            FieldIssue::Missing => write!(f, "missing {} {}", kind, self.field),
+            FieldIssue::Invalid(reason) => write!(f, "{} {}: {}", kind, self.field, reason),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Extraction {
+    pub values: BTreeMap<String, FieldValue>,
+    pub problems: Vec<FieldProblem>,
+}
+
+impl Extraction {
+    // Whether every required field has a value
+    pub fn is_complete(&self) -> bool {
+        !self.problems.iter().any(|problem| problem.required)
+    }
+}
+
+// Fields with a path are read from `data`, and are missing when there is none
+pub fn extract_fields(
+    page: &Html,
+    data: Option<&Value>,
+    page_url: &Url,
+    specs: &BTreeMap<String, FieldSpec>,
+) -> Extraction {
+    let base_url = base_url(page, page_url);
+    let mut extraction = Extraction::default();
+
+    for (name, spec) in specs {
+        let problem = |issue| FieldProblem {
+            field: name.clone(),
+            required: spec.required,
+            issue,
+        };
+
+        let matches: Vec<String> = match (&spec.selector, &spec.path, data) {
+            (Some(selector), _, _) => page
+                .select(selector.selector())
+                .filter_map(|element| match &spec.attribute {
+                    Some(attribute) => element.value().attr(attribute).map(str::to_string),
+                    None => Some(element.text().collect::<String>()),
+                })
+                .collect(),
+            (None, Some(path), Some(data)) => path
+                .query(data)
+                .into_iter()
+                .filter_map(value_text)
+                .collect(),
+            _ => Vec::new(),
+        };
+        let matches = matches
+            .into_iter()
+            .filter(|text| !text.trim().is_empty())
+            .take(match spec.cardinality {
+                Cardinality::One => 1,
+                Cardinality::Many => usize::MAX,
+            })
+            .collect::<Vec<_>>();
+        let matches = match (&spec.default, matches.is_empty()) {
+            (Some(default), true) => vec![default.clone()],
+            _ => matches,
+        };
+
+        let mut values = Vec::new();
+        for text in matches {
+            match spec.parser.parse(&text, &base_url) {
+                Ok(value) => values.push(value),
+                Err(reason) => extraction
+                    .problems
+                    .push(problem(FieldIssue::Invalid(reason))),
+            }
+        }
+
+        let value = match spec.cardinality {
+            Cardinality::One => values.pop(),
+            Cardinality::Many => (!values.is_empty()).then_some(FieldValue::List(values)),
+        };
+        match value {
+            Some(value) => {
+                extraction.values.insert(name.clone(), value);
+            }
+            None if extraction.problems.iter().any(|p| p.field == *name) => {}
+            None => extraction.problems.push(problem(FieldIssue::Missing)),
+        }
+    }
+
+    extraction
+}
+
+// The first number in the text, e.g. 1234.5 in "€1.234,50 incl. VAT" with locale "de"
+fn parse_number(text: &str, locale: Option<&str>) -> Option<f64> {
+    let (decimal, grouping) = match locale.map(str::to_lowercase) {
+        Some(locale) if uses_decimal_comma(&locale) => (',', '.'),
+        _ => ('.', ','),
+    };
+
+    let start = text.find(|c: char| c.is_ascii_digit())?;
+    let negative = text[..start].trim_end().ends_with(['-', '\u{2212}']);
+    let mut number = String::from(if negative { "-" } else { "" });
+    for c in text[start..].chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            c if c == decimal => number.push('.'),
+            // Grouping separators, including the spaces and apostrophes some locales use
+            c if c == grouping || matches!(c, ' ' | '\u{a0}' | '\u{202f}' | '\'' | '’') => {}
+            _ => break,
+        }
+    }
+    number.trim_end_matches('.').parse().ok()
+}
+
+fn uses_decimal_comma(locale: &str) -> bool {
+    let mut parts = locale.split(['-', '_']);
+    let language = parts.next().unwrap_or_default();
+    let region = parts.next();
+    DECIMAL_COMMA_LANGUAGES.contains(&language)
+        && !region.is_some_and(|region| DECIMAL_POINT_REGIONS.contains(&region))
+}
+
+// The most precise value the format allows: a zoned date and time, a date and time,
+// or a date
+fn parse_date(text: &str, format: Option<&str>) -> Result<FieldValue, String> {
+    let invalid = |err: chrono::ParseError| format!("`{}` is not a date: {}", text, err);
+    let Some(format) = format else {
+        return match DateTime::parse_from_rfc3339(text) {
+            Ok(date_time) => Ok(FieldValue::ZonedDateTime(date_time)),
+            Err(_) => NaiveDate::parse_from_str(text, "%Y-%m-%d")
+                .map(FieldValue::Date)
+                .map_err(invalid),
+        };
+    };
+
+    DateTime::parse_from_str(text, format)
+        .map(FieldValue::ZonedDateTime)
+        .or_else(|_| NaiveDateTime::parse_from_str(text, format).map(FieldValue::DateTime))
+        .or_else(|_| NaiveDate::parse_from_str(text, format).map(FieldValue::Date))
+        .map_err(invalid)
+}
@@ -0,0 +1,246 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use writing_web_crawlers::dedup::fnv1a;
+
+use crate::content::Content;
+use crate::error::HistoryError;
+
+const HOUR: i64 = 60 * 60;
+
+// How long a page that just turned up waits before it is checked again
+pub const INITIAL_INTERVAL: i64 = 24 * HOUR;
+// Pages that change all the time are still checked at most this often
+pub const MIN_INTERVAL: i64 = 6 * HOUR;
+// Pages that never change are still checked at least this often
+pub const MAX_INTERVAL: i64 = 30 * 24 * HOUR;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS history (
+        url TEXT PRIMARY KEY,
+        content_hash INTEGER NOT NULL,
+        first_seen INTEGER NOT NULL,
+        last_seen INTEGER NOT NULL,
+        last_changed INTEGER NOT NULL,
+        next_check INTEGER NOT NULL,
+        interval INTEGER NOT NULL,
+        changes INTEGER NOT NULL DEFAULT 0,
+        removed INTEGER
+    );
+    CREATE TABLE IF NOT EXISTS aliases (
+        alias TEXT PRIMARY KEY,
+        url TEXT NOT NULL
+    );
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    New,
+    Updated,
+    // A page seen before that now answers 404 or 410
+    Removed,
+}
+
+impl ChangeKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::New => "new",
+            Self::Updated => "updated",
+            Self::Removed => "removed",
+        }
+    }
+}
+
+impl fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+// A fixed hash of everything extracted from the page, so any change to the title, body
+// or a field counts and nothing else does
+pub fn content_hash(content: &Content) -> u64 {
+    let mut text = format!("{}\0{}", content.title, content.body);
+    for (name, value) in &content.fields {
+        text.push_str(&format!("\0{}={}", name, value));
+    }
+    fnv1a(text.as_bytes())
+}
+
+// What incremental crawls know about the pages of one site, kept in SQLite between
+// runs. Each page is checked again after an interval that doubles every time it is
+// found unchanged and halves every time it has changed, within `MIN_INTERVAL` and
+// `MAX_INTERVAL`. Times are Unix timestamps in seconds.
+pub struct ContentHistory {
+    connection: Connection,
+}
+
+impl ContentHistory {
+    pub fn open(path: &Path) -> Result<Self, HistoryError> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(Self { connection })
+    }
+
+    // Records that `alias` redirects to `url`, so the page is known by either
+    pub fn add_alias(&self, alias: &str, url: &str) -> Result<(), HistoryError> {
+        self.connection
+            .prepare_cached("INSERT OR REPLACE INTO aliases (alias, url) VALUES (?1, ?2)")?
+            .execute(params![alias, url])?;
+        Ok(())
+    }
+
+    // The URL a page's history is kept under, which differs from `url` when that
+    // redirects
+    pub fn page_url(&self, url: &str) -> Result<String, HistoryError> {
+        let page_url = self
+            .connection
+            .prepare_cached("SELECT url FROM aliases WHERE alias = ?1")?
+            .query_row(params![url], |row| row.get(0))
+            .optional()?;
+        Ok(page_url.unwrap_or_else(|| url.to_string()))
+    }
+
+    // Whether the page should be fetched. Pages never seen are always due.
+    pub fn is_due(&self, url: &str, now: i64) -> Result<bool, HistoryError> {
+        Ok(self
+            .next_check(url)?
+            .is_none_or(|next_check| next_check <= now))
+    }
+
+    // Records a successful fetch and says how the content changed, or None when it
+    // didn't
+    pub fn observe(
+        &self,
+        url: &str,
+        hash: u64,
+        now: i64,
+    ) -> Result<Option<ChangeKind>, HistoryError> {
+        // SQLite integers are signed, so the hash is stored by its bits
+        let hash = hash as i64;
+        let known: Option<(i64, i64, Option<i64>)> = self
+            .connection
+            .prepare_cached("SELECT content_hash, interval, removed FROM history WHERE url = ?1")?
+            .query_row(params![url], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .optional()?;
+
+        let (change, interval) = match known {
+            None => (Some(ChangeKind::New), INITIAL_INTERVAL),
+            // A page that comes back after being removed is new again
+            Some((_, _, Some(_))) => (Some(ChangeKind::New), INITIAL_INTERVAL),
+            Some((old_hash, interval, None)) if old_hash == hash => {
+                (None, (interval * 2).min(MAX_INTERVAL))
+            }
+            Some((_, interval, None)) => {
+                (Some(ChangeKind::Updated), (interval / 2).max(MIN_INTERVAL))
+            }
+        };
+
+        self.connection
+            .prepare_cached(
+                "INSERT INTO history
+                     (url, content_hash, first_seen, last_seen, last_changed, next_check, interval)
+                 VALUES (?1, ?2, ?3, ?3, ?3, ?4, ?5)
+                 ON CONFLICT (url) DO UPDATE SET
+                     content_hash = excluded.content_hash,
+                     last_seen = excluded.last_seen,
+                     last_changed = CASE WHEN ?6 THEN excluded.last_changed ELSE last_changed END,
+                     next_check = excluded.next_check,
+                     interval = excluded.interval,
+                     changes = changes + ?6,
+                     removed = NULL",
+            )?
+            .execute(params![
+                url,
+                hash,
+                now,
+                now + interval,
+                interval,
+                change.is_some()
+            ])?;
+        Ok(change)
+    }
+
+    // Records that a page is gone. Returns false for pages that were never seen or were
+    // already known to be gone.
+    pub fn mark_removed(&self, url: &str, now: i64) -> Result<bool, HistoryError> {
+        let updated = self
+            .connection
+            .prepare_cached(
+                "UPDATE history SET removed = ?2, last_changed = ?2, next_check = ?3,
+                     interval = ?4, changes = changes + 1
+                 WHERE url = ?1 AND removed IS NULL",
+            )?
+            .execute(params![url, now, now + MAX_INTERVAL, MAX_INTERVAL])?;
+        Ok(updated == 1)
+    }
+
+    // Pages due for a check that a full crawl didn't reach, which are no longer linked
+    // from the site. Pages that aren't due may only be linked from pages the crawl
+    // skipped, so they wait until they are.
+    pub fn unreached(
+        &self,
+        reached: &HashSet<String>,
+        now: i64,
+    ) -> Result<Vec<String>, HistoryError> {
+        let urls = self
+            .connection
+            .prepare_cached(
+                "SELECT url FROM history WHERE removed IS NULL AND next_check <= ?1 ORDER BY url",
+            )?
+            .query_map(params![now], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(urls
+            .into_iter()
+            .filter(|url| !reached.contains(url))
+            .collect())
+    }
+
+    // When the page will be checked again
+    pub fn next_check(&self, url: &str) -> Result<Option<i64>, HistoryError> {
+        let next_check = self
+            .connection
+            .prepare_cached(
+                "SELECT next_check FROM history
+                 WHERE url = coalesce((SELECT url FROM aliases WHERE alias = ?1), ?1)",
+            )?
+            .query_row(params![url], |row| row.get(0))
+            .optional()?;
+        Ok(next_check)
+    }
+}
+
+// What an incremental crawl found
+#[derive(Debug, Default)]
+pub struct ChangeCounts {
+    pub new: usize,
+    pub updated: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+    // Pages skipped because they aren't due for another check yet
+    pub not_due: usize,
+}
+
+impl ChangeCounts {
+    pub fn add(&mut self, change: Option<ChangeKind>) {
+        match change {
+            Some(ChangeKind::New) => self.new += 1,
+            Some(ChangeKind::Updated) => self.updated += 1,
+            Some(ChangeKind::Removed) => self.removed += 1,
+            None => self.unchanged += 1,
+        }
+    }
+
+    pub fn print(&self) {
+        println!(
+            "Changes: {} new, {} updated, {} removed, {} unchanged, {} not due for a check",
+            self.new, self.updated, self.removed, self.unchanged, self.not_due
+        );
+    }
+}
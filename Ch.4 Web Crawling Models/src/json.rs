@@ -0,0 +1,97 @@
+use std::fmt;
+
+use scraper::{Html, Selector};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_json_path::JsonPath;
+
+use crate::website::{CssSelector, Pattern};
+
+// A JSONPath query like `$.props.pageProps.article.headline`, parsed when the site
+// definition is loaded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct JsonQuery {
+    source: String,
+    path: JsonPath,
+}
+
+impl JsonQuery {
+    // Every value the query matches, in document order
+    pub fn query<'a>(&self, value: &'a Value) -> Vec<&'a Value> {
+        self.path.query(value).all()
+    }
+}
+
+impl TryFrom<String> for JsonQuery {
+    type Error = String;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        let path = JsonPath::parse(&source)
+            .map_err(|err| format!("invalid JSONPath `{}`: {}", source, err))?;
+        Ok(Self { source, path })
+    }
+}
+
+impl From<JsonQuery> for String {
+    fn from(query: JsonQuery) -> Self {
+        query.source
+    }
+}
+
+impl fmt::Display for JsonQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+// Where the JSON data behind a site's pages is. Title, body and fields with a `path`
+// are read from it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum DataSource {
+    // The text of the first script element the selector matches, like
+    // `script#__NEXT_DATA__`
+    Script { selector: CssSelector },
+    // A regex run on every script element, like `window\.__INITIAL_STATE__\s*=\s*(\{.*\})`.
+    // The JSON is its first group, or the whole match without one.
+    Pattern { pattern: Pattern },
+    // An endpoint fetched for every target page, like
+    // `https://example.com/api/articles/{slug}`. `{name}` is replaced by the group of
+    // `target_pattern` with that name.
+    Api { url: String },
+}
+
+// The JSON embedded in the page. Returns `None` when the source is an API or the page
+// has no script it matches.
+pub fn embedded_json(page: &Html, source: &DataSource) -> Result<Option<Value>, String> {
+    let text = match source {
+        DataSource::Script { selector } => page
+            .select(selector.selector())
+            .next()
+            .map(|script| script.text().collect::<String>()),
+        DataSource::Pattern { pattern } => {
+            let script_selector = Selector::parse("script").unwrap();
+            page.select(&script_selector).find_map(|script| {
+                pattern
+                    .find(&script.text().collect::<String>())
+                    .map(str::to_string)
+            })
+        }
+        DataSource::Api { .. } => None,
+    };
+
+    text.map(|text| {
+        serde_json::from_str(text.trim()).map_err(|err| format!("invalid JSON: {}", err))
+    })
+    .transpose()
+}
+
+// A value as the text a field parser reads. Arrays and objects are written as JSON.
+pub fn value_text(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::String(text) => Some(text.clone()),
+        value => Some(value.to_string()),
+    }
+}
@@ -0,0 +1,12 @@
+pub mod content;
+pub mod error;
+pub mod fields;
+pub mod history;
+pub mod json;
+pub mod registry;
+pub mod render;
+pub mod search;
+pub mod sink;
+pub mod strategy;
+pub mod traversal;
+pub mod website;
@@ -1,119 +1,1353 @@
-use fancy_regex::Regex;
+use async_trait::async_trait;
+use clap::{Args, Parser, Subcommand};
 use scraper::{Html, Selector};
-use std::collections::HashSet;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::signal;
+use tokio::task::JoinHandle;
+use tracing::{info_span, warn, Instrument};
+use url::Url;
+use web_crawling_models::content::Content;
+use web_crawling_models::error::{ConfigError, SinkError};
+use web_crawling_models::fields::{
+    extract_fields, FieldIssue, FieldProblem, FieldSpec, TextLocation,
+};
+use web_crawling_models::history::{content_hash, ChangeCounts, ChangeKind, ContentHistory};
+use web_crawling_models::json::{embedded_json, value_text, DataSource};
+use web_crawling_models::registry::{SiteDefinition, SiteRegistry};
+use web_crawling_models::render::{PageRenderer, WebDriverRenderer};
+use web_crawling_models::search::SearchSite;
+use web_crawling_models::sink::{
+    open_sink, slug, ContentSink, FanOut, PrintSink, DEFAULT_BATCH_SIZE,
+};
+use web_crawling_models::strategy::{
+    BestFirst, BreadthFirst, Candidate, CrawlStrategy, DepthFirst, IterativeDeepening, Scorer,
+};
+use web_crawling_models::traversal::{traverse, Visitor};
+use web_crawling_models::website::{CssSelector, RenderSettings, StrategyKind, Website};
+use writing_web_crawlers::dedup::{canonical_url, simhash, DuplicateDetector};
+use writing_web_crawlers::error::AppError;
+use writing_web_crawlers::fetch::{Fetch, PageFetcher, RedirectFinding};
+use writing_web_crawlers::headers::{HeaderProfile, HeaderProfiles, RequestHeaders};
+use writing_web_crawlers::links::{base_url, find_document_links, resolve_href, Link};
+use writing_web_crawlers::logging::{init_logging, LogArgs};
+use writing_web_crawlers::metrics::{serve_metrics, CrawlMetrics, Progress};
+use writing_web_crawlers::robots::RobotsGate;
+use writing_web_crawlers::scope::Scope;
+use writing_web_crawlers::state::{CrawlStore, PageState};
 
-#[derive(Debug)]
-struct Website<'a> {
-    name: &'a str,
-    url: &'a str,
-    target_pattern: &'a str,
-    absolute_url: bool,
-    title_tag: &'a str,
-    body_tag: &'a str,
+const USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+// Articles whose body fingerprints differ in at most this many bits are the same article
+const NEAR_DUPLICATE_DISTANCE: u32 = 3;
+
+fn safe_get(page_obj: &scraper::Html, selector: &CssSelector) -> String {
+    let selected_elems = page_obj.select(selector.selector());
+    selected_elems
+        .map(|elem| elem.text().collect::<String>())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+// A page with the site's data for it, if the site has any
+struct Page<'a> {
+    html: &'a Html,
+    data: Option<&'a Value>,
+    url: &'a Url,
+}
+
+fn read_text(page: &Page, location: TextLocation) -> String {
+    match (location.tag, location.path, page.data) {
+        (Some(tag), _, _) => safe_get(page.html, tag),
+        (None, Some(path), Some(data)) => path
+            .query(data)
+            .into_iter()
+            .filter_map(value_text)
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
 }
 
-#[derive(Debug)]
-struct Content {
-    url: String,
-    title: String,
-    body: String,
+// Pages whose data can't be read are still read, and whatever is in the data comes up
+// missing
+fn page_data(url: &Url, data: Result<Option<Value>, Box<dyn Error>>) -> Option<Value> {
+    data.unwrap_or_else(|err| {
+        println!("No data for {}: {}", url, err);
+        None
+    })
 }
 
-impl Content {
-    fn print(&self) {
-        println!("URL: {}", self.url);
-        println!("TITLE: {}", self.title);
-        println!("BODY:\n{}", self.body);
+// Reports anything wrong with the page's fields. Pages without a title, a body or a
+// required field are skipped.
+fn extract_content(
+    page: &Page,
+    site: &str,
+    topic: Option<&str>,
+    title: TextLocation,
+    body: TextLocation,
+    fields: &BTreeMap<String, FieldSpec>,
+) -> Option<Content> {
+    let page_url = page.url;
+    let title = read_text(page, title);
+    let body = read_text(page, body);
+    let mut extraction = extract_fields(page.html, page.data, page_url, fields);
+    for (field, value) in [("title", &title), ("body", &body)] {
+        if value.trim().is_empty() {
+            extraction.problems.push(FieldProblem {
+                field: field.to_string(),
+                required: true,
+                issue: FieldIssue::Missing,
+            });
+        }
+    }
+
+    if !extraction.problems.is_empty() {
+        let problems = extraction
+            .problems
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        if !extraction.is_complete() {
+            println!("Skipping {}: {}", page_url, problems);
+            return None;
+        }
+        println!("Incomplete {}: {}", page_url, problems);
+    }
+
+    Some(Content {
+        site: site.to_string(),
+        topic: topic.map(str::to_string),
+        url: page_url.to_string(),
+        change: None,
+        title,
+        body,
+        fields: extraction.values,
+    })
+}
+
+// Fetches the pages of one site
+struct PageSource {
+    fetcher: PageFetcher,
+    robots: Option<RobotsGate>,
+    scope: Scope,
+    // The scope, for API responses instead of pages
+    data_scope: Scope,
+    redirect_findings: Vec<RedirectFinding>,
+}
+
+impl PageSource {
+    fn new(
+        respect_robots: bool,
+        scope: Scope,
+        headers: &HeaderProfile,
+    ) -> Result<Self, Box<dyn Error>> {
+        let user_agent = headers.user_agent(USER_AGENT);
+        let client = reqwest::Client::builder().user_agent(user_agent).build()?;
+        let robots = respect_robots.then(|| RobotsGate::new(client, user_agent));
+        let mut data_scope = scope.clone();
+        data_scope.content_types = vec!["application/json".to_string()];
+
+        Ok(Self {
+            fetcher: PageFetcher::new(user_agent)?
+                .with_headers(RequestHeaders::new(headers.clone())),
+            robots,
+            scope,
+            data_scope,
+            redirect_findings: Vec::new(),
+        })
+    }
+
+    fn with_metrics(mut self, metrics: Option<Arc<CrawlMetrics>>) -> Self {
+        if let Some(metrics) = metrics {
+            self.fetcher = self.fetcher.with_metrics(metrics);
+        }
+        self
+    }
+
+    // Follows redirects, checking robots.txt and waiting out its Crawl-delay before
+    // every request. Returns `None` when the chain doesn't end on a page of one of the
+    // scope's content types.
+    async fn get_page(&mut self, url: &str) -> Result<Option<(Fetch, Html)>, Box<dyn Error>> {
+        let mut fetch = self
+            .fetcher
+            .fetch(self.robots.as_ref(), &self.scope, &Url::parse(url)?)
+            .await?;
+        if let Some(finding) = fetch.audit() {
+            self.redirect_findings.push(finding);
+        }
+        if let Some(stop) = fetch.stop {
+            println!("Skipping {}: {}", url, stop);
+            return Ok(None);
+        }
+
+        let Some(body) = fetch.body.take() else {
+            return Ok(None);
+        };
+        Ok(Some((fetch, Html::parse_document(&body))))
+    }
+
+    // Fetches JSON from the site's API, with the same checks as its pages
+    async fn get_json(&mut self, url: &Url) -> Result<Option<Value>, Box<dyn Error>> {
+        let mut fetch = self
+            .fetcher
+            .fetch(self.robots.as_ref(), &self.data_scope, url)
+            .await?;
+        if let Some(stop) = fetch.stop {
+            println!("Skipping {}: {}", url, stop);
+            return Ok(None);
+        }
+        if let Some(status) = fetch.status.filter(|status| !(200..300).contains(status)) {
+            return Err(format!("{} answered {}", fetch.url, status).into());
+        }
+
+        let Some(body) = fetch.body.take() else {
+            return Err(format!("{} didn't answer with JSON", fetch.url).into());
+        };
+        Ok(Some(serde_json::from_str(&body)?))
+    }
+
+    fn print_findings(&self) {
+        for finding in &self.redirect_findings {
+            println!("Redirect {}: {}", finding.issue, finding.chain.join(" -> "));
+        }
     }
 }
 
 struct Crawler<'a> {
-    site: Website<'a>,
+    site: Website,
     visited: HashSet<String>,
+    source: PageSource,
+    // Pages finished by earlier runs are skipped
+    state: Option<CrawlStore>,
+    duplicates: DuplicateDetector,
+    // Incremental crawls only emit pages that are new, changed or removed, and only
+    // fetch target pages that are due for a check
+    history: Option<ContentHistory>,
+    changes: ChangeCounts,
+    // The target pages this crawl went through, by the URL their history is kept under,
+    // and whether it went through all it could. Pages a full crawl didn't reach are gone.
+    reached: HashSet<String>,
+    failed_visits: usize,
+    sink: &'a mut dyn ContentSink,
+    // The state is only checkpointed after the sink is flushed, so a crash never
+    // marks pages done whose content was still held in the sink
+    checkpoint_every: usize,
+    unsaved_pages: usize,
+    // Pages of sites with render settings are loaded in a browser after they're fetched
+    renderer: Option<&'a mut dyn PageRenderer>,
+    metrics: Option<Arc<CrawlMetrics>>,
 }
 
 impl<'a> Crawler<'a> {
-    fn new(site: Website<'a>) -> Self {
-        Self {
+    fn new(
+        site: Website,
+        source: PageSource,
+        state_file: Option<&Path>,
+        history_file: Option<&Path>,
+        sink: &'a mut dyn ContentSink,
+        checkpoint_every: usize,
+    ) -> Result<Self, Box<dyn Error>> {
+        let site_url = site.url.clone();
+        let state = match state_file {
+            Some(path) => {
+                let store = CrawlStore::open(path)?;
+                match store.start_url()? {
+                    Some(start_url) if start_url != site_url.as_str() => {
+                        return Err(
+                            format!("{} holds a crawl of {}", path.display(), start_url).into()
+                        );
+                    }
+                    Some(_) => {}
+                    None => store.set_start_url(&site_url)?,
+                }
+                Some(store)
+            }
+            None => None,
+        };
+
+        Ok(Self {
             site,
             visited: HashSet::new(),
+            source,
+            state,
+            duplicates: DuplicateDetector::new(NEAR_DUPLICATE_DISTANCE),
+            history: history_file.map(ContentHistory::open).transpose()?,
+            changes: ChangeCounts::default(),
+            reached: HashSet::new(),
+            failed_visits: 0,
+            sink,
+            checkpoint_every: checkpoint_every.max(1),
+            unsaved_pages: 0,
+            renderer: None,
+            metrics: None,
+        })
+    }
+
+    fn with_metrics(mut self, metrics: Option<Arc<CrawlMetrics>>) -> Self {
+        self.source = self.source.with_metrics(metrics.clone());
+        self.metrics = metrics;
+        self
+    }
+
+    fn with_renderer(mut self, renderer: Option<&'a mut dyn PageRenderer>) -> Self {
+        self.renderer = renderer;
+        self
+    }
+
+    // The page as the browser shows it. Pages that fail to render are read as served.
+    async fn render(&mut self, url: &Url, settings: &RenderSettings) -> Option<Html> {
+        let renderer = self.renderer.as_mut()?;
+        let page = match renderer.render(url, settings).await {
+            Ok(page) => page,
+            Err(err) => {
+                println!("Could not render {}, reading it as served: {}", url, err);
+                return None;
+            }
+        };
+        if let (false, Some(selector)) = (page.ready, &settings.wait_for) {
+            println!("Gave up waiting for {} on {}", selector, url);
         }
+
+        if let (Some(screenshot), Some(dir)) = (&page.screenshot, &settings.screenshot_dir) {
+            let path = dir.join(format!("{}.png", slug(url.as_str())));
+            let saved =
+                std::fs::create_dir_all(dir).and_then(|_| std::fs::write(&path, screenshot));
+            if let Err(err) = saved {
+                println!("Could not save {}: {}", path.display(), err);
+            }
+        }
+
+        Some(Html::parse_document(&page.html))
     }
 
-    async fn get_page(&self, url: &str) -> Result<scraper::Html, reqwest::Error> {
-        let response = reqwest::get(url).await?;
-        let body = response.text().await?;
-        Ok(Html::parse_document(&body))
+    fn checkpoint(&mut self) -> Result<(), Box<dyn Error>> {
+        self.sink.flush()?;
+        if let Some(state) = &mut self.state {
+            state.checkpoint()?;
+        }
+        self.unsaved_pages = 0;
+        Ok(())
     }
 
-    fn safe_get(&self, page_obj: &scraper::Html, selector: &str) -> String {
-        let select = Selector::parse(selector).unwrap();
-        let selected_elems = page_obj.select(&select);
-        selected_elems
-            .map(|elem| elem.text().collect::<String>())
-            .collect::<Vec<_>>()
-            .join("\n")
+    // The site's data for a target page
+    async fn data(&mut self, url: &Url, page: &Html) -> Option<Value> {
+        let data = match self.site.data.as_ref()? {
+            DataSource::Api { url: template } => match self.site.api_url(template, url) {
+                Ok(api_url) => self.source.get_json(&api_url).await,
+                Err(err) => Err(err.into()),
+            },
+            source => embedded_json(page, source).map_err(Into::into),
+        };
+        page_data(url, data)
     }
 
-    async fn parse(&mut self, url: &str) -> Result<(), Box<dyn Error>> {
-        let bs = self.get_page(url).await?;
-        let title = self.safe_get(&bs, self.site.title_tag);
-        let body = self.safe_get(&bs, self.site.body_tag);
+    fn parse(
+        &mut self,
+        fetch: &Fetch,
+        bs: &Html,
+        data: Option<&Value>,
+    ) -> Result<(), Box<dyn Error>> {
+        // Several links can redirect to the same article, and an article parsed before
+        // may have named this page as its canonical
+        let page_url = &fetch.url;
+        if !self.visited.insert(page_url.to_string()) {
+            if fetch.redirects.is_empty() {
+                println!("Skipping {}: same article as one already parsed", page_url);
+            } else {
+                println!("Skipping {}: redirects to {}", fetch.requested, page_url);
+            }
+            return Ok(());
+        }
 
-        if !title.is_empty() && !body.is_empty() {
-            let content = Content {
-                url: url.to_string(),
-                title,
-                body,
-            };
-            content.print();
+        // A page naming another URL as canonical is the same article as that page, and
+        // its content is written under the canonical URL
+        let canonical = canonical_url(bs, page_url)
+            .or(fetch.canonical.clone())
+            .filter(|c| c != page_url);
+        if let Some(canonical) = &canonical {
+            self.duplicates
+                .add_alias(canonical.as_str(), page_url.as_str());
+            if !self.visited.insert(canonical.to_string()) {
+                println!("Skipping {}: same article as {}", page_url, canonical);
+                return Ok(());
+            }
+        }
+
+        let page = Page {
+            html: bs,
+            data,
+            url: page_url,
+        };
+        let Some(mut content) = extract_content(
+            &page,
+            &self.site.name,
+            None,
+            self.site.title(),
+            self.site.body(),
+            &self.site.fields,
+        ) else {
+            return Ok(());
+        };
+        let article_url = canonical.as_ref().unwrap_or(page_url);
+        content.url = article_url.to_string();
+
+        if let Some(fingerprint) = simhash(&content.body) {
+            if let Some(original) = self.duplicates.check(article_url.as_str(), fingerprint) {
+                println!("Skipping {}: near-duplicate of {}", page_url, original);
+                return Ok(());
+            }
         }
 
+        if let Some(history) = &self.history {
+            let change = history.observe(article_url.as_str(), content_hash(&content), now())?;
+            self.changes.add(change);
+            if change.is_none() {
+                return Ok(());
+            }
+            content.change = change;
+        }
+
+        self.sink.write(content)?;
+        Ok(())
+    }
+
+    // Parses the page if it is a target page and returns the links worth following
+    async fn visit_page(&mut self, url: &Url, depth: usize) -> Result<Vec<Link>, Box<dyn Error>> {
+        if self.site.is_target(url) {
+            if let Some(history) = &self.history {
+                self.reached.insert(history.page_url(url.as_str())?);
+                if !history.is_due(url.as_str(), now())? {
+                    self.changes.not_due += 1;
+                    return Ok(Vec::new());
+                }
+            } else if let Some(state) = &self.state {
+                if state.page_state(url)? == Some(PageState::Done) {
+                    println!("Skipping {}: crawled in an earlier run", url);
+                    return Ok(Vec::new());
+                }
+            }
+        }
+
+        // The page is still fetched over HTTP first, for robots.txt, redirects and
+        // the status code
+        let Some((fetch, mut html)) = self.source.get_page(url.as_str()).await? else {
+            return Ok(Vec::new());
+        };
+        // The history is kept under the URL a page redirects to, so whether it is due is
+        // looked up through the URL it was linked as
+        if let Some(history) = &self.history {
+            if fetch.url != *url && self.site.is_target(&fetch.url) {
+                history.add_alias(url.as_str(), fetch.url.as_str())?;
+                self.reached.insert(fetch.url.to_string());
+            }
+        }
+        if matches!(fetch.status, Some(404 | 410)) {
+            if let Some(history) = &self.history {
+                if history.mark_removed(fetch.url.as_str(), now())? {
+                    self.changes.add(Some(ChangeKind::Removed));
+                    self.sink
+                        .write(Content::removed(&self.site.name, fetch.url.as_str()))?;
+                }
+            }
+            return Ok(Vec::new());
+        }
+        if let Some(settings) = self.site.render.clone() {
+            if let Some(rendered) = self.render(&fetch.url, &settings).await {
+                html = rendered;
+            }
+        }
+        if self.site.is_target(&fetch.url) {
+            let data = self.data(&fetch.url, &html).await;
+            self.parse(&fetch, &html, data.as_ref())?;
+        }
+
+        if let Some(state) = &self.state {
+            state.record_page(url, depth, PageState::Done, fetch.status, 1, None)?;
+            self.unsaved_pages += 1;
+            if self.unsaved_pages >= self.checkpoint_every {
+                self.checkpoint()?;
+            }
+        }
+
+        let follow_pattern = self.site.crawl.follow_pattern.as_ref();
+        let links = find_document_links(&html, &fetch.url)
+            .into_iter()
+            .filter(|link| self.source.scope.contains(&link.url))
+            .filter(|link| {
+                self.site.is_target(&link.url)
+                    || follow_pattern.is_some_and(|pattern| self.site.matches(pattern, &link.url))
+            })
+            .collect();
+        Ok(links)
+    }
+
+    fn strategy(&self) -> Box<dyn CrawlStrategy> {
+        let max_depth = self.site.crawl.max_depth;
+        match self.site.crawl.strategy {
+            StrategyKind::BreadthFirst => Box::new(BreadthFirst::new(max_depth)),
+            StrategyKind::DepthFirst => Box::new(DepthFirst::new(max_depth)),
+            StrategyKind::IterativeDeepening => Box::new(IterativeDeepening::new(max_depth)),
+            StrategyKind::BestFirst => {
+                // Target pages first, then the shallowest pages
+                let site = self.site.clone();
+                let scorer: Scorer = Arc::new(move |candidate: &Candidate| {
+                    let bonus = if site.is_target(&candidate.url) {
+                        100.0
+                    } else {
+                        0.0
+                    };
+                    bonus - candidate.depth as f64
+                });
+                Box::new(BestFirst::new(max_depth, scorer))
+            }
+        }
+    }
+
+    fn remove_unreached(&mut self) -> Result<(), Box<dyn Error>> {
+        let Some(history) = &self.history else {
+            return Ok(());
+        };
+        let now = now();
+        for url in history.unreached(&self.reached, now)? {
+            if history.mark_removed(&url, now)? {
+                self.changes.add(Some(ChangeKind::Removed));
+                self.sink.write(Content::removed(&self.site.name, &url))?;
+            }
+        }
         Ok(())
     }
 
     async fn crawl(&mut self) -> Result<(), Box<dyn Error>> {
-        println!("Crawl website: {}", self.site.name);
-
-        let html = self.get_page(self.site.url).await?;
-        let target_selector = Selector::parse("a[href]")?;
-        let regex = Regex::new(self.site.target_pattern)?;
-        let target_pages = html
-            .select(&target_selector)
-            .filter_map(|element| element.value().attr("href"))
-            .filter(|href| regex.is_match(href).unwrap_or(false))
-            .collect::<Vec<_>>();
-
-        for target_page in target_pages {
-            if !self.visited.contains(target_page) {
-                self.visited.insert(target_page.to_string());
-                let target_url = if !self.site.absolute_url {
-                    format!("{}{}", self.site.url, target_page)
-                } else {
-                    target_page.to_string()
+        let mut strategy = self.strategy();
+        println!(
+            "Crawl website: {} ({}, max depth {})",
+            self.site.name,
+            strategy.name(),
+            self.site.crawl.max_depth
+        );
+
+        let seed = self.site.url.clone();
+        let max_pages = self.site.crawl.max_pages;
+        let visits = traverse(strategy.as_mut(), self, &seed, max_pages).await;
+        if visits.len() < max_pages && self.failed_visits == 0 {
+            self.remove_unreached()?;
+        }
+        self.checkpoint()?;
+        println!("Visited {} pages of {}", visits.len(), self.site.name);
+        if self.history.is_some() {
+            self.changes.print();
+        }
+
+        for cluster in self.duplicates.clusters() {
+            println!("Duplicates of {}:", cluster.original);
+            for duplicate in &cluster.duplicates {
+                println!("  {}", duplicate);
+            }
+        }
+
+        self.source.print_findings();
+
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl Visitor for Crawler<'_> {
+    async fn visit(&mut self, url: &Url, depth: usize) -> Vec<Link> {
+        let span = info_span!("page", site = %self.site.name, url = %url, depth);
+        match self.visit_page(url, depth).instrument(span.clone()).await {
+            Ok(links) => links,
+            Err(err) => {
+                warn!(parent: &span, "Error crawling {}: {}", url, err);
+                self.failed_visits += 1;
+                Vec::new()
+            }
+        }
+    }
+
+    fn queued(&mut self, links: usize) {
+        if let Some(metrics) = &self.metrics {
+            metrics.set_queue_depth(links);
+        }
+    }
+}
+
+// Runs topics through the search of every site and reads the results
+struct SearchCrawler<'a> {
+    sites: Vec<(SearchSite, PageSource)>,
+    // Pages already read for a topic
+    seen: HashSet<(String, String)>,
+    sink: &'a mut dyn ContentSink,
+    metrics: Option<Arc<CrawlMetrics>>,
+    // Pages of results and result pages that couldn't be fetched
+    failed_pages: usize,
+}
+
+impl<'a> SearchCrawler<'a> {
+    fn new(
+        sites: Vec<SearchSite>,
+        respect_robots: bool,
+        headers: &SiteHeaders,
+        sink: &'a mut dyn ContentSink,
+        metrics: Option<Arc<CrawlMetrics>>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let sites = sites
+            .into_iter()
+            .map(|site| {
+                let scope = Scope::new().for_start(&site.search_url("")?);
+                let headers = headers.for_site(site.headers.as_deref())?;
+                let source =
+                    PageSource::new(respect_robots, scope, headers)?.with_metrics(metrics.clone());
+                Ok((site, source))
+            })
+            .collect::<Result<_, Box<dyn Error>>>()?;
+
+        Ok(Self {
+            sites,
+            seen: HashSet::new(),
+            sink,
+            metrics,
+            failed_pages: 0,
+        })
+    }
+
+    async fn search(&mut self, topic: &str) -> Result<(), Box<dyn Error>> {
+        for (site, source) in &mut self.sites {
+            println!("Search {} for {}", site.name, topic);
+
+            let search_url = site.search_url(topic)?;
+            let mut results_pages = HashSet::new();
+            let mut next = Some(search_url.clone());
+            for index in 0..site.max_pages {
+                let Some(url) = next.take() else {
+                    break;
                 };
-                self.parse(&target_url).await?;
+                if !results_pages.insert(url.clone()) {
+                    break;
+                }
+                // Without the results, there's nothing more to read from this site
+                let span = info_span!("results", site = %site.name, topic, url = %url);
+                let (fetch, html) =
+                    match source.get_page(url.as_str()).instrument(span.clone()).await {
+                        Ok(Some(page)) => page,
+                        Ok(None) => break,
+                        Err(err) => {
+                            warn!(parent: &span, "Error fetching {}: {}", url, err);
+                            self.failed_pages += 1;
+                            break;
+                        }
+                    };
+
+                let results = result_links(&html, &fetch.url, &site.result_selector);
+                if results.is_empty() {
+                    break;
+                }
+                next = match &site.next_page_selector {
+                    Some(selector) => result_links(&html, &fetch.url, selector).into_iter().next(),
+                    None => site.results_page(&search_url, index + 1),
+                };
+
+                for (index, result) in results.iter().enumerate() {
+                    // The results of this page still to be read
+                    if let Some(metrics) = &self.metrics {
+                        metrics.set_queue_depth(results.len() - index - 1);
+                    }
+                    if !source.scope.contains(result) {
+                        println!("Skipping {}: out of scope", result);
+                        continue;
+                    }
+                    if !self.seen.insert((topic.to_string(), result.to_string())) {
+                        continue;
+                    }
+                    let span = info_span!("result", site = %site.name, topic, url = %result);
+                    let page = match source
+                        .get_page(result.as_str())
+                        .instrument(span.clone())
+                        .await
+                    {
+                        Ok(Some(page)) => page,
+                        Ok(None) => continue,
+                        Err(err) => {
+                            warn!(parent: &span, "Error fetching {}: {}", result, err);
+                            self.failed_pages += 1;
+                            continue;
+                        }
+                    };
+                    let (fetch, html) = page;
+                    let data = site.data.as_ref().and_then(|source| {
+                        page_data(&fetch.url, embedded_json(&html, source).map_err(Into::into))
+                    });
+
+                    let page = Page {
+                        html: &html,
+                        data: data.as_ref(),
+                        url: &fetch.url,
+                    };
+                    let content = extract_content(
+                        &page,
+                        &site.name,
+                        Some(topic),
+                        site.title(),
+                        site.body(),
+                        &site.fields,
+                    );
+                    if let Some(content) = content {
+                        self.sink.write(content)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn print_findings(&self) {
+        if self.failed_pages > 0 {
+            println!("{} pages could not be fetched", self.failed_pages);
+        }
+        for (_, source) in &self.sites {
+            source.print_findings();
+        }
+    }
+}
+
+// The link of every element the selector matches, or of the first link inside it
+fn result_links(page: &Html, page_url: &Url, selector: &CssSelector) -> Vec<Url> {
+    let link_selector = Selector::parse("a[href]").unwrap();
+    let base_url = base_url(page, page_url);
+    let mut links = Vec::new();
+
+    for element in page.select(selector.selector()) {
+        let href = element.value().attr("href").or_else(|| {
+            element
+                .select(&link_selector)
+                .next()
+                .and_then(|link| link.value().attr("href"))
+        });
+        if let Some(url) = href.and_then(|href| resolve_href(&base_url, href)) {
+            if !links.contains(&url) {
+                links.push(url);
             }
         }
+    }
+
+    links
+}
+
+#[derive(Parser)]
+#[command(about = "Crawl the sites described in site definition files")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+
+    #[command(flatten)]
+    logging: LogArgs,
+
+    #[command(flatten)]
+    headers: HeaderArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Crawl every site in a directory of definitions, starting from its home page
+    Crawl(CrawlArgs),
+    /// Look up topics with the search of every site in a directory of definitions
+    Search(SearchArgs),
+}
+
+#[derive(Args)]
+struct CrawlArgs {
+    /// Directory of site definitions [default: sites/ in the crate]
+    sites_dir: Option<PathBuf>,
+
+    /// Only output pages that are new, changed or gone since the last incremental
+    /// crawl, and check pages that rarely change less often
+    #[arg(long)]
+    incremental: bool,
+
+    /// WebDriver server, e.g. http://localhost:9515 for chromedriver, that renders the
+    /// pages of sites with render settings. Without one they are read as served.
+    #[arg(long, value_name = "URL")]
+    webdriver: Option<Url>,
+
+    /// Fetch pages even when the site's robots.txt disallows them
+    #[arg(long)]
+    ignore_robots: bool,
+
+    /// Keep the state of each site's crawl in this directory, so that running the crawl
+    /// again resumes it. Incremental crawls keep their history here too.
+    #[arg(long, value_name = "DIR")]
+    state: Option<PathBuf>,
+
+    #[command(flatten)]
+    output: OutputArgs,
+
+    #[command(flatten)]
+    metrics: MetricsArgs,
+}
+
+#[derive(Args)]
+struct SearchArgs {
+    /// Topics to search for
+    #[arg(required = true)]
+    topics: Vec<String>,
+
+    /// Directory of search site definitions [default: search_sites/ in the crate]
+    #[arg(long, value_name = "DIR")]
+    sites: Option<PathBuf>,
+
+    /// Fetch pages even when the site's robots.txt disallows them
+    #[arg(long)]
+    ignore_robots: bool,
+
+    #[command(flatten)]
+    output: OutputArgs,
+
+    #[command(flatten)]
+    metrics: MetricsArgs,
+}
+
+#[derive(Args)]
+struct OutputArgs {
+    /// Write pages to a file, in the format given by its extension (.jsonl, .csv or
+    /// .sqlite), or to a directory of Markdown files for a path without one. May be
+    /// repeated. Pages are printed when there is none.
+    #[arg(long = "output", value_name = "PATH")]
+    outputs: Vec<PathBuf>,
+
+    /// Pages each output holds before writing them out
+    #[arg(long, default_value_t = DEFAULT_BATCH_SIZE)]
+    batch_size: usize,
+}
+
+impl OutputArgs {
+    fn open(&self, field_names: &[String]) -> Result<Box<dyn ContentSink>, SinkError> {
+        let mut sinks = self
+            .outputs
+            .iter()
+            .map(|path| open_sink(path, field_names, self.batch_size))
+            .collect::<Result<Vec<_>, SinkError>>()?;
+
+        Ok(match sinks.len() {
+            0 => Box::new(PrintSink),
+            1 => sinks.remove(0),
+            _ => Box::new(FanOut::new(sinks)),
+        })
+    }
+}
+
+#[derive(Args)]
+struct HeaderArgs {
+    /// Header profile for the sites that don't name one
+    #[arg(
+        long = "headers",
+        global = true,
+        default_value = "default",
+        value_name = "NAME"
+    )]
+    profile: String,
+
+    /// TOML file of named header profiles [default: header_profiles.toml in the crate]
+    #[arg(long, global = true, value_name = "PATH")]
+    header_profiles: Option<PathBuf>,
+
+    /// URL where site owners can find out about the crawl, sent in the default user
+    /// agent. Give one whenever you crawl sites you don't own.
+    #[arg(long, global = true, value_name = "URL")]
+    contact: Option<String>,
+}
+
+impl HeaderArgs {
+    // Fails when the profiles can't be read or a site names one that doesn't exist
+    fn load<'a>(
+        &self,
+        site_profiles: impl Iterator<Item = Option<&'a str>>,
+    ) -> Result<SiteHeaders, AppError> {
+        let path = self
+            .header_profiles
+            .clone()
+            .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("header_profiles.toml"));
+        let mut profiles =
+            HeaderProfiles::new(HeaderProfile::identify(USER_AGENT, self.contact.as_deref()));
+        profiles.load(&path)?;
+
+        let headers = SiteHeaders {
+            profiles,
+            default: self.profile.clone(),
+        };
+        for profile in std::iter::once(None).chain(site_profiles) {
+            headers.for_site(profile)?;
+        }
+        Ok(headers)
+    }
+}
 
+// The header profiles, and which one sites without their own get
+struct SiteHeaders {
+    profiles: HeaderProfiles,
+    default: String,
+}
+
+impl SiteHeaders {
+    fn for_site(&self, profile: Option<&str>) -> Result<&HeaderProfile, AppError> {
+        self.profiles.get(profile.unwrap_or(&self.default))
+    }
+}
+
+#[derive(Args)]
+struct MetricsArgs {
+    /// Show a live status line with pages per second, bytes, queue depth and errors
+    #[arg(long)]
+    progress: bool,
+
+    /// Serve Prometheus metrics at http://127.0.0.1:PORT/metrics while running
+    #[arg(long, value_name = "PORT")]
+    metrics_port: Option<u16>,
+
+    /// Write a JSON summary of the metrics to this file at the end
+    #[arg(long, value_name = "PATH")]
+    metrics_json: Option<PathBuf>,
+}
+
+impl MetricsArgs {
+    // Nothing is counted when no report was asked for
+    async fn start(&self) -> Result<Option<MetricsRun>, Box<dyn Error>> {
+        if !self.progress && self.metrics_port.is_none() && self.metrics_json.is_none() {
+            return Ok(None);
+        }
+
+        let metrics = Arc::new(CrawlMetrics::new());
+        let server = match self.metrics_port {
+            Some(port) => Some(serve_metrics(metrics.clone(), port).await?),
+            None => None,
+        };
+        Ok(Some(MetricsRun {
+            progress: self.progress.then(|| Progress::start(metrics.clone())),
+            server,
+            summary: self.metrics_json.clone(),
+            metrics,
+        }))
+    }
+}
+
+// The metrics of a run, and whatever reports on them while it lasts
+struct MetricsRun {
+    metrics: Arc<CrawlMetrics>,
+    progress: Option<Progress>,
+    server: Option<JoinHandle<()>>,
+    summary: Option<PathBuf>,
+}
+
+impl MetricsRun {
+    fn finish(self) -> Result<(), Box<dyn Error>> {
+        drop(self.progress);
+        if let Some(server) = self.server {
+            server.abort();
+        }
+        if let Some(path) = &self.summary {
+            self.metrics.write_summary(path)?;
+            println!("Metrics summary written to {}", path.display());
+        }
         Ok(())
     }
 }
 
+fn load_registry<S: SiteDefinition>(
+    dir: Option<PathBuf>,
+    default: &str,
+) -> Result<SiteRegistry<S>, ConfigError> {
+    let dir = dir.unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join(default));
+    let registry = SiteRegistry::load_dir(&dir)?;
+    println!("Loaded {} sites from {}", registry.len(), dir.display());
+    Ok(registry)
+}
+
+// What applies to every site of a crawl
+struct CrawlOptions {
+    respect_robots: bool,
+    incremental: bool,
+    // Without one a crawl starts over every time, and incremental crawls keep their
+    // history in the current directory
+    state_dir: Option<PathBuf>,
+    // Pages the sinks hold before the crawl state is checkpointed
+    checkpoint_every: usize,
+}
+
+// Incremental crawls go through every page each time, so they keep a history of the
+// site's pages instead of the state of a single crawl
+async fn crawl_sites(
+    registry: &SiteRegistry<Website>,
+    headers: &SiteHeaders,
+    options: &CrawlOptions,
+    sink: &mut dyn ContentSink,
+    mut renderer: Option<&mut dyn PageRenderer>,
+    metrics: Option<Arc<CrawlMetrics>>,
+) -> Result<(), Box<dyn Error>> {
+    let incremental = options.incremental;
+    if let Some(dir) = &options.state_dir {
+        std::fs::create_dir_all(dir)?;
+    }
+    let state_dir = options.state_dir.as_deref().unwrap_or(Path::new("."));
+    for site in registry.sites() {
+        if site.render.is_some() && renderer.is_none() {
+            println!(
+                "{} needs a browser to render its pages, but there is no --webdriver. \
+                 Reading them as served.",
+                site.name
+            );
+        }
+
+        let name = slug(&site.name);
+        let state_file = state_dir.join(format!("{}_crawl.sqlite", name));
+        let history_file = state_dir.join(format!("{}_history.sqlite", name));
+        let source = PageSource::new(
+            options.respect_robots,
+            Scope::new().for_start(&site.url),
+            headers.for_site(site.headers.as_deref())?,
+        )?;
+        let mut crawler = Crawler::new(
+            site.clone(),
+            source,
+            (!incremental && options.state_dir.is_some()).then_some(state_file.as_path()),
+            incremental.then_some(history_file.as_path()),
+            &mut *sink,
+            options.checkpoint_every,
+        )?
+        .with_renderer(match (&site.render, &mut renderer) {
+            (Some(_), Some(renderer)) => Some(&mut **renderer as &mut dyn PageRenderer),
+            _ => None,
+        })
+        .with_metrics(metrics.clone());
+        crawler.crawl().await?;
+    }
+    Ok(())
+}
+
+async fn search_sites(
+    registry: &SiteRegistry<SearchSite>,
+    headers: &SiteHeaders,
+    topics: &[String],
+    respect_robots: bool,
+    sink: &mut dyn ContentSink,
+    metrics: Option<Arc<CrawlMetrics>>,
+) -> Result<(), Box<dyn Error>> {
+    let mut crawler = SearchCrawler::new(
+        registry.sites().cloned().collect(),
+        respect_robots,
+        headers,
+        sink,
+        metrics,
+    )?;
+    for topic in topics {
+        crawler.search(topic).await?;
+    }
+    crawler.print_findings();
+    Ok(())
+}
+
+// Every field any of the sites defines, for the columns of CSV output
+fn field_names<'a>(fields: impl Iterator<Item = &'a BTreeMap<String, FieldSpec>>) -> Vec<String> {
+    fields
+        .flat_map(BTreeMap::keys)
+        .cloned()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+// Whatever the sinks hold is written out, and the metrics summary with it, when the
+// crawl ends, fails or is interrupted
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let reuters = Website {
-        name: "Reuters",
-        url: "https://www.reuters.com",
-        target_pattern: "^(/world/)",
-        absolute_url: false,
-        title_tag: "h1",
-        body_tag: "div.article-body__content__17Yit",
-    };
-
-    let mut crawler = Crawler::new(reuters);
-
-    crawler.crawl().await?;
+    let cli = Cli::parse();
+    init_logging(&cli.logging.config())?;
+
+    match cli.command {
+        Command::Crawl(args) => {
+            let registry: SiteRegistry<Website> = load_registry(args.sites_dir, "sites")?;
+            let headers = cli
+                .headers
+                .load(registry.sites().map(|site| site.headers.as_deref()))?;
+            let fields = field_names(registry.sites().map(|site| &site.fields));
+            let mut sink = args.output.open(&fields)?;
+            let mut renderer = match args.webdriver {
+                Some(endpoint) => Some(WebDriverRenderer::new(
+                    endpoint,
+                    headers.for_site(None)?.user_agent(USER_AGENT),
+                )?),
+                None => None,
+            };
+            let options = CrawlOptions {
+                respect_robots: !args.ignore_robots,
+                incremental: args.incremental,
+                state_dir: args.state,
+                checkpoint_every: args.output.batch_size,
+            };
+            let metrics = args.metrics.start().await?;
+            let result = tokio::select! {
+                result = crawl_sites(
+                    &registry,
+                    &headers,
+                    &options,
+                    sink.as_mut(),
+                    renderer.as_mut().map(|renderer| renderer as &mut dyn PageRenderer),
+                    metrics.as_ref().map(|run| run.metrics.clone()),
+                ) => result,
+                _ = signal::ctrl_c() => {
+                    println!("Interrupted, stopping the crawl");
+                    Ok(())
+                }
+            };
+            sink.flush()?;
+            if let Some(renderer) = &mut renderer {
+                renderer.close().await?;
+            }
+            if let Some(metrics) = metrics {
+                metrics.finish()?;
+            }
+            result?;
+        }
+        Command::Search(args) => {
+            let registry: SiteRegistry<SearchSite> = load_registry(args.sites, "search_sites")?;
+            let headers = cli
+                .headers
+                .load(registry.sites().map(|site| site.headers.as_deref()))?;
+            let fields = field_names(registry.sites().map(|site| &site.fields));
+            let mut sink = args.output.open(&fields)?;
+            let metrics = args.metrics.start().await?;
+            let result = tokio::select! {
+                result = search_sites(
+                    &registry,
+                    &headers,
+                    &args.topics,
+                    !args.ignore_robots,
+                    sink.as_mut(),
+                    metrics.as_ref().map(|run| run.metrics.clone()),
+                ) => result,
+                _ = signal::ctrl_c() => {
+                    println!("Interrupted, stopping the search");
+                    Ok(())
+                }
+            };
+            sink.flush()?;
+            if let Some(metrics) = metrics {
+                metrics.finish()?;
+            }
+            result?;
+        }
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use web_crawling_models::render::FakeRenderer;
+    use web_crawling_models::website::ConfigFormat;
+
+    #[derive(Default)]
+    struct Collected(Vec<Content>);
+
+    impl ContentSink for Collected {
+        fn write(&mut self, content: Content) -> Result<(), SinkError> {
+            self.0.push(content);
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<(), SinkError> {
+            Ok(())
+        }
+    }
+
+    // Pages as the server sends them, before their scripts run: an empty app shell with
+    // a link the scripts would replace, and an article still loading
+    async fn serve_shells() -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = [0; 1024];
+                let read = stream.read(&mut request).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&request[..read]);
+                let body = match request.split_whitespace().nth(1) {
+                    Some("/") => {
+                        r#"<html><body><div id="app"><a href="/news/old">Old</a></div></body></html>"#
+                    }
+                    _ => r#"<html><body><h1>Loading</h1></body></html>"#,
+                };
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        Url::parse(&format!("http://{}/", address)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn rendered_pages_provide_the_links_and_content() {
+        let home = serve_shells().await;
+        let article = home.join("/news/rendered").unwrap();
+        let site = Website::parse(
+            &format!(
+                "name = \"Rendered\"\nurl = \"{}\"\ntarget_pattern = \"^/news/\"\n\
+                 title_tag = \"h1\"\nbody_tag = \"div.story\"\n[render]\n",
+                home
+            ),
+            ConfigFormat::Toml,
+        )
+        .unwrap();
+        let mut renderer = FakeRenderer::new()
+            .with_page(
+                home.as_str(),
+                r#"<html><body><div id="app"><a href="/news/rendered">Story</a></div></body></html>"#,
+            )
+            .with_page(
+                article.as_str(),
+                r#"<html><body><h1>Rendered title</h1><div class="story">Rendered body</div></body></html>"#,
+            );
+
+        let mut sink = Collected::default();
+        let source = PageSource::new(
+            false,
+            Scope::new().for_start(&site.url),
+            &HeaderProfile::identify(USER_AGENT, None),
+        )
+        .unwrap();
+        Crawler::new(site, source, None, None, &mut sink, 1)
+            .unwrap()
+            .with_renderer(Some(&mut renderer))
+            .crawl()
+            .await
+            .unwrap();
+
+        assert_eq!(renderer.rendered, vec![home, article.clone()]);
+        assert_eq!(sink.0.len(), 1);
+        assert_eq!(sink.0[0].url, article.as_str());
+        assert_eq!(sink.0[0].title, "Rendered title");
+        assert_eq!(sink.0[0].body, "Rendered body");
+    }
+
+    #[tokio::test]
+    async fn aliases_are_written_under_their_canonical_url() {
+        let home = serve_shells().await;
+        let alias = home.join("/news/story?ref=home").unwrap();
+        let story = home.join("/news/story").unwrap();
+        let site = Website::parse(
+            &format!(
+                "name = \"Aliased\"\nurl = \"{}\"\ntarget_pattern = \"^/news/\"\n\
+                 title_tag = \"h1\"\nbody_tag = \"div.story\"\n[render]\n",
+                home
+            ),
+            ConfigFormat::Toml,
+        )
+        .unwrap();
+        let article = r#"<html><head><link rel="canonical" href="/news/story"></head>
+            <body><h1>Title</h1><div class="story">Body</div></body></html>"#;
+        let mut renderer = FakeRenderer::new()
+            .with_page(
+                home.as_str(),
+                r#"<html><body><a href="/news/story?ref=home">Alias</a><a href="/news/story">Story</a></body></html>"#,
+            )
+            .with_page(alias.as_str(), article)
+            .with_page(story.as_str(), article);
+
+        let mut sink = Collected::default();
+        let source = PageSource::new(
+            false,
+            Scope::new().for_start(&site.url),
+            &HeaderProfile::identify(USER_AGENT, None),
+        )
+        .unwrap();
+        Crawler::new(site, source, None, None, &mut sink, 1)
+            .unwrap()
+            .with_renderer(Some(&mut renderer))
+            .crawl()
+            .await
+            .unwrap();
+
+        assert_eq!(sink.0.len(), 1);
+        assert_eq!(sink.0[0].url, story.as_str());
+    }
+
+    // A search whose results page links to one story
+    async fn serve_search() -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = [0; 1024];
+                let read = stream.read(&mut request).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&request[..read]);
+                let body = match request.split_whitespace().nth(1) {
+                    Some(path) if path.starts_with("/search") => {
+                        r#"<html><body><a class="result" href="/story">Story</a></body></html>"#
+                    }
+                    _ => {
+                        r#"<html><body><h1>Found</h1><div class="story">Story body</div></body></html>"#
+                    }
+                };
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        Url::parse(&format!("http://{}/", address)).unwrap()
+    }
+
+    fn search_site(name: &str, origin: &Url) -> SearchSite {
+        SearchSite::parse(
+            &format!(
+                "name = \"{}\"\nsearch_url = \"{}search?q={{topic}}\"\n\
+                 result_selector = \"a.result\"\ntitle_tag = \"h1\"\nbody_tag = \"div.story\"\n",
+                name, origin
+            ),
+            ConfigFormat::Toml,
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn a_failed_search_moves_on_to_the_next_site() {
+        // Nothing listens on a port that was just released
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let down = Url::parse(&format!("http://{}/", closed.local_addr().unwrap())).unwrap();
+        drop(closed);
+        let up = serve_search().await;
+
+        let headers = SiteHeaders {
+            profiles: HeaderProfiles::new(HeaderProfile::identify(USER_AGENT, None)),
+            default: "default".to_string(),
+        };
+        let mut sink = Collected::default();
+        let mut crawler = SearchCrawler::new(
+            vec![search_site("Down", &down), search_site("Up", &up)],
+            false,
+            &headers,
+            &mut sink,
+            None,
+        )
+        .unwrap();
+        crawler.search("rust").await.unwrap();
+        assert_eq!(crawler.failed_pages, 1);
+
+        assert_eq!(sink.0.len(), 1);
+        assert_eq!(sink.0[0].site, "Up");
+        assert_eq!(sink.0[0].title, "Found");
+    }
+}
@@ -0,0 +1,95 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::error::ConfigError;
+use crate::website::{ConfigFormat, Website};
+
+// Anything a site definition file can describe
+pub trait SiteDefinition: Sized {
+    // Parses and validates one definition
+    fn parse(text: &str, format: ConfigFormat) -> Result<Self, String>;
+
+    fn name(&self) -> &str;
+
+    fn load(path: &Path) -> Result<Self, ConfigError> {
+        let format = ConfigFormat::from_path(path)
+            .ok_or_else(|| ConfigError::UnsupportedFormat(path.to_path_buf()))?;
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| ConfigError::Io(path.to_path_buf(), err))?;
+        Self::parse(&text, format)
+            .map_err(|message| ConfigError::InvalidSite(path.to_path_buf(), message))
+    }
+}
+
+// Site definitions by name, loaded from a directory with one file per site
+#[derive(Debug)]
+pub struct SiteRegistry<S = Website> {
+    sites: BTreeMap<String, (S, PathBuf)>,
+}
+
+impl<S> Default for SiteRegistry<S> {
+    fn default() -> Self {
+        Self {
+            sites: BTreeMap::new(),
+        }
+    }
+}
+
+impl<S: SiteDefinition> SiteRegistry<S> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Reads every .toml, .yaml, .yml and .json file in the directory. Other files are
+    // ignored, and the first invalid definition stops the load.
+    pub fn load_dir(dir: &Path) -> Result<Self, ConfigError> {
+        let entries =
+            std::fs::read_dir(dir).map_err(|err| ConfigError::Io(dir.to_path_buf(), err))?;
+
+        let mut paths = Vec::new();
+        for entry in entries {
+            let path = entry
+                .map_err(|err| ConfigError::Io(dir.to_path_buf(), err))?
+                .path();
+            if path.is_file() && ConfigFormat::from_path(&path).is_some() {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        let mut registry = Self::new();
+        for path in paths {
+            let site = S::load(&path)?;
+            registry.add(site, path)?;
+        }
+        Ok(registry)
+    }
+
+    pub fn add(&mut self, site: S, source: PathBuf) -> Result<(), ConfigError> {
+        if let Some((_, existing)) = self.sites.get(site.name()) {
+            return Err(ConfigError::DuplicateSite(
+                site.name().to_string(),
+                source,
+                existing.clone(),
+            ));
+        }
+        self.sites.insert(site.name().to_string(), (site, source));
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&S> {
+        self.sites.get(name).map(|(site, _)| site)
+    }
+
+    pub fn sites(&self) -> impl Iterator<Item = &S> {
+        self.sites.values().map(|(site, _)| site)
+    }
+
+    pub fn len(&self) -> usize {
+        self.sites.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sites.is_empty()
+    }
+}
@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use reqwest::{Client, Method};
+use serde_json::{json, Value};
+use tokio::time::{sleep, Instant};
+use url::Url;
+
+use crate::error::RenderError;
+use crate::website::RenderSettings;
+
+// How often to look for the element a page is waiting for
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// The scroll that makes infinite-scrolling pages load their next batch
+const SCROLL_SCRIPT: &str = "window.scrollTo(0, document.body.scrollHeight);";
+
+#[derive(Debug, Clone)]
+pub struct RenderedPage {
+    // Where the browser ended up, after redirects done by scripts too
+    pub url: Url,
+    // The DOM serialized after scripts ran
+    pub html: String,
+    // A PNG of the viewport, when the settings ask for one
+    pub screenshot: Option<Vec<u8>>,
+    // False when the element the page waited for never showed up
+    pub ready: bool,
+}
+
+// Loads pages in something that runs their scripts, for sites that build their
+// content client-side and send an empty shell over plain HTTP
+#[async_trait(?Send)]
+pub trait PageRenderer {
+    fn name(&self) -> &'static str;
+
+    async fn render(
+        &mut self,
+        url: &Url,
+        settings: &RenderSettings,
+    ) -> Result<RenderedPage, RenderError>;
+
+    // Releases the browser, if the renderer started one
+    async fn close(&mut self) -> Result<(), RenderError> {
+        Ok(())
+    }
+}
+
+// Drives a browser through a WebDriver server such as chromedriver or geckodriver.
+// The browser session is started on the first page and reused for the rest.
+pub struct WebDriverRenderer {
+    client: Client,
+    endpoint: Url,
+    user_agent: String,
+    session: Option<String>,
+}
+
+impl WebDriverRenderer {
+    // `endpoint` is the WebDriver server, e.g. http://localhost:9515 for chromedriver
+    pub fn new(endpoint: Url, user_agent: &str) -> Result<Self, RenderError> {
+        Ok(Self {
+            client: Client::builder().build()?,
+            endpoint,
+            user_agent: user_agent.to_string(),
+            session: None,
+        })
+    }
+
+    // Sends a WebDriver command and returns the response's `value`
+    async fn command(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<Value>,
+    ) -> Result<Value, RenderError> {
+        let url = format!("{}/{}", self.endpoint.as_str().trim_end_matches('/'), path);
+        let mut request = self.client.request(method, url);
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        let mut body: Value = response.json().await?;
+        let value = body
+            .get_mut("value")
+            .map(Value::take)
+            .unwrap_or(Value::Null);
+
+        if !status.is_success() {
+            let field = |name: &str| {
+                value
+                    .get(name)
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .to_string()
+            };
+            return Err(RenderError::WebDriver(field("error"), field("message")));
+        }
+        Ok(value)
+    }
+
+    async fn session(&mut self) -> Result<String, RenderError> {
+        if let Some(session) = &self.session {
+            return Ok(session.clone());
+        }
+
+        let user_agent = format!("--user-agent={}", self.user_agent);
+        let capabilities = json!({
+            "capabilities": {
+                "alwaysMatch": {
+                    "goog:chromeOptions": { "args": ["--headless=new", user_agent] },
+                    "moz:firefoxOptions": {
+                        "args": ["-headless"],
+                        "prefs": { "general.useragent.override": self.user_agent }
+                    }
+                }
+            }
+        });
+        let value = self
+            .command(Method::POST, "session", Some(capabilities))
+            .await?;
+        let session = value
+            .get("sessionId")
+            .and_then(Value::as_str)
+            .ok_or(RenderError::MissingValue("sessionId"))?
+            .to_string();
+        self.session = Some(session.clone());
+        Ok(session)
+    }
+
+    async fn wait_for(
+        &self,
+        session: &str,
+        settings: &RenderSettings,
+    ) -> Result<bool, RenderError> {
+        let Some(selector) = &settings.wait_for else {
+            return Ok(true);
+        };
+        let path = format!("session/{}/element", session);
+        let query = json!({ "using": "css selector", "value": selector.to_string() });
+        let deadline = Instant::now() + settings.wait_timeout();
+
+        loop {
+            match self.command(Method::POST, &path, Some(query.clone())).await {
+                Ok(_) => return Ok(true),
+                Err(RenderError::WebDriver(error, _)) if error == "no such element" => {}
+                Err(err) => return Err(err),
+            }
+            if Instant::now() >= deadline {
+                return Ok(false);
+            }
+            sleep(WAIT_POLL_INTERVAL).await;
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl PageRenderer for WebDriverRenderer {
+    fn name(&self) -> &'static str {
+        "webdriver"
+    }
+
+    async fn render(
+        &mut self,
+        url: &Url,
+        settings: &RenderSettings,
+    ) -> Result<RenderedPage, RenderError> {
+        let session = self.session().await?;
+        self.command(
+            Method::POST,
+            &format!("session/{}/url", session),
+            Some(json!({ "url": url.as_str() })),
+        )
+        .await?;
+
+        let ready = self.wait_for(&session, settings).await?;
+        for _ in 0..settings.scrolls {
+            self.command(
+                Method::POST,
+                &format!("session/{}/execute/sync", session),
+                Some(json!({ "script": SCROLL_SCRIPT, "args": [] })),
+            )
+            .await?;
+            sleep(settings.scroll_pause()).await;
+        }
+
+        let html = self
+            .command(Method::GET, &format!("session/{}/source", session), None)
+            .await?;
+        let html = html
+            .as_str()
+            .ok_or(RenderError::MissingValue("page source"))?
+            .to_string();
+        let current_url = self
+            .command(Method::GET, &format!("session/{}/url", session), None)
+            .await?;
+        let current_url = current_url
+            .as_str()
+            .and_then(|current_url| Url::parse(current_url).ok())
+            .unwrap_or_else(|| url.clone());
+
+        let screenshot = if settings.screenshot_dir.is_some() {
+            let png = self
+                .command(
+                    Method::GET,
+                    &format!("session/{}/screenshot", session),
+                    None,
+                )
+                .await?;
+            let png = png
+                .as_str()
+                .ok_or(RenderError::MissingValue("screenshot"))?;
+            Some(BASE64.decode(png)?)
+        } else {
+            None
+        };
+
+        Ok(RenderedPage {
+            url: current_url,
+            html,
+            screenshot,
+            ready,
+        })
+    }
+
+    async fn close(&mut self) -> Result<(), RenderError> {
+        if let Some(session) = self.session.take() {
+            self.command(Method::DELETE, &format!("session/{}", session), None)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+// Serves canned HTML instead of running a browser, and remembers what it was asked
+// to render. For trying out rendering settings and the code around a renderer.
+#[derive(Debug, Default)]
+pub struct FakeRenderer {
+    pages: HashMap<String, String>,
+    pub rendered: Vec<Url>,
+}
+
+impl FakeRenderer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_page(mut self, url: &str, html: &str) -> Self {
+        self.pages.insert(url.to_string(), html.to_string());
+        self
+    }
+}
+
+#[async_trait(?Send)]
+impl PageRenderer for FakeRenderer {
+    fn name(&self) -> &'static str {
+        "fake"
+    }
+
+    // Unknown pages render as an empty document. A page is ready when it has the
+    // element the settings wait for.
+    async fn render(
+        &mut self,
+        url: &Url,
+        settings: &RenderSettings,
+    ) -> Result<RenderedPage, RenderError> {
+        self.rendered.push(url.clone());
+        let html = self
+            .pages
+            .get(url.as_str())
+            .cloned()
+            .unwrap_or_else(|| "<html><head></head><body></body></html>".to_string());
+        let ready = settings.wait_for.as_ref().is_none_or(|selector| {
+            scraper::Html::parse_document(&html)
+                .select(selector.selector())
+                .next()
+                .is_some()
+        });
+
+        Ok(RenderedPage {
+            url: url.clone(),
+            html,
+            screenshot: settings.screenshot_dir.as_ref().map(|_| Vec::new()),
+            ready,
+        })
+    }
+}
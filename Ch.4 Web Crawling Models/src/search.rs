@@ -0,0 +1,156 @@
+use std::collections::BTreeMap;
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::fields::{validate_layout, FieldSpec, TextLocation};
+use crate::json::{DataSource, JsonQuery};
+use crate::registry::SiteDefinition;
+use crate::website::{ConfigFormat, CssSelector};
+
+// Where the search terms go in `search_url`
+pub const TOPIC_PLACEHOLDER: &str = "{topic}";
+
+// Everything but the unreserved characters, so a topic is safe in a path or a query
+const TOPIC_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+fn default_first_page() -> usize {
+    1
+}
+
+fn default_max_pages() -> usize {
+    1
+}
+
+// A site crawled through its search form: the crawler asks for the results of a topic
+// and follows the result links, instead of finding articles through the home page
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SearchSite {
+    pub name: String,
+    // e.g. `https://example.com/search?q={topic}`
+    pub search_url: String,
+    // Links to results, or elements holding one
+    pub result_selector: CssSelector,
+    // The link to the next page of results
+    pub next_page_selector: Option<CssSelector>,
+    // Or the query parameter numbering the pages of results
+    pub page_param: Option<String>,
+    #[serde(default = "default_first_page")]
+    pub first_page: usize,
+    // Pages of results read per topic
+    #[serde(default = "default_max_pages")]
+    pub max_pages: usize,
+    pub title_tag: Option<CssSelector>,
+    pub body_tag: Option<CssSelector>,
+    pub title_path: Option<JsonQuery>,
+    pub body_path: Option<JsonQuery>,
+    // Extra values to extract from every page, by name
+    #[serde(default)]
+    pub fields: BTreeMap<String, FieldSpec>,
+    // JSON embedded in the result pages
+    pub data: Option<DataSource>,
+    // Header profile for the search and its results
+    pub headers: Option<String>,
+}
+
+impl SearchSite {
+    pub fn parse(text: &str, format: ConfigFormat) -> Result<Self, String> {
+        let site: Self = format.deserialize(text)?;
+        site.validate()?;
+        Ok(site)
+    }
+
+    // The first page of results for the topic
+    pub fn search_url(&self, topic: &str) -> Result<Url, String> {
+        let topic = utf8_percent_encode(topic.trim(), TOPIC_ENCODE_SET).to_string();
+        let url = self.search_url.replace(TOPIC_PLACEHOLDER, &topic);
+        let url = Url::parse(&url).map_err(|err| format!("invalid search URL {}: {}", url, err))?;
+        Ok(match &self.page_param {
+            Some(_) => self.with_page(&url, 0),
+            None => url,
+        })
+    }
+
+    // Page `index` of the results, counting from 0, when pages are numbered by
+    // `page_param`
+    pub fn results_page(&self, search_url: &Url, index: usize) -> Option<Url> {
+        self.page_param
+            .as_ref()
+            .map(|_| self.with_page(search_url, index))
+    }
+
+    pub fn title(&self) -> TextLocation<'_> {
+        TextLocation {
+            tag: self.title_tag.as_ref(),
+            path: self.title_path.as_ref(),
+        }
+    }
+
+    pub fn body(&self) -> TextLocation<'_> {
+        TextLocation {
+            tag: self.body_tag.as_ref(),
+            path: self.body_path.as_ref(),
+        }
+    }
+
+    fn with_page(&self, url: &Url, index: usize) -> Url {
+        let param = self.page_param.as_deref().unwrap_or_default();
+        let params = url
+            .query_pairs()
+            .filter(|(name, _)| name != param)
+            .map(|(name, value)| (name.into_owned(), value.into_owned()))
+            .collect::<Vec<_>>();
+
+        let mut url = url.clone();
+        url.query_pairs_mut()
+            .clear()
+            .extend_pairs(params)
+            .append_pair(param, &(self.first_page + index).to_string());
+        url
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("the site has no name".to_string());
+        }
+        if !self.search_url.contains(TOPIC_PLACEHOLDER) {
+            return Err(format!(
+                "search_url {} has no {} placeholder",
+                self.search_url, TOPIC_PLACEHOLDER
+            ));
+        }
+        let url = self.search_url("topic")?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(format!("{} is not an http or https URL", self.search_url));
+        }
+        if self.next_page_selector.is_some() && self.page_param.is_some() {
+            return Err("set next_page_selector or page_param, not both".to_string());
+        }
+        if self.max_pages == 0 {
+            return Err("max_pages must be at least 1".to_string());
+        }
+        if let Some(DataSource::Api { .. }) = &self.data {
+            return Err(
+                "API data is filled in from target_pattern, which search sites don't have"
+                    .to_string(),
+            );
+        }
+        validate_layout(self.title(), self.body(), &self.fields, self.data.as_ref())
+    }
+}
+
+impl SiteDefinition for SearchSite {
+    fn parse(text: &str, format: ConfigFormat) -> Result<Self, String> {
+        SearchSite::parse(text, format)
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
@@ -0,0 +1,485 @@
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use writing_web_crawlers::dedup::fnv1a;
+
+use crate::content::Content;
+use crate::error::SinkError;
+use crate::fields::FieldValue;
+use crate::history::ChangeKind;
+
+// Contents a sink holds before writing them out
+pub const DEFAULT_BATCH_SIZE: usize = 100;
+
+const SQLITE_SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS contents (
+        site TEXT NOT NULL,
+        topic TEXT NOT NULL DEFAULT '',
+        url TEXT NOT NULL,
+        change TEXT,
+        title TEXT NOT NULL,
+        body TEXT NOT NULL,
+        fields TEXT NOT NULL,
+        PRIMARY KEY (site, topic, url)
+    );
+";
+
+// Somewhere extracted pages go. Sinks may hold contents back and write them in
+// batches; `flush` writes whatever is held, and dropping a sink flushes it too.
+pub trait ContentSink {
+    fn write(&mut self, content: Content) -> Result<(), SinkError>;
+
+    fn flush(&mut self) -> Result<(), SinkError>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SinkFormat {
+    JsonLines,
+    Csv,
+    Sqlite,
+    // One file per page, in a directory
+    Markdown,
+}
+
+impl SinkFormat {
+    // Paths without an extension are directories of Markdown files
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let Some(extension) = path.extension() else {
+            return Some(Self::Markdown);
+        };
+        match extension.to_str()?.to_lowercase().as_str() {
+            "jsonl" | "ndjson" => Some(Self::JsonLines),
+            "csv" => Some(Self::Csv),
+            "sqlite" | "sqlite3" | "db" => Some(Self::Sqlite),
+            _ => None,
+        }
+    }
+}
+
+// Opens a sink of the format the path's extension names. CSV files get a column for
+// each of `field_names`.
+pub fn open_sink(
+    path: &Path,
+    field_names: &[String],
+    batch_size: usize,
+) -> Result<Box<dyn ContentSink>, SinkError> {
+    let format =
+        SinkFormat::from_path(path).ok_or_else(|| SinkError::UnsupportedFormat(path.into()))?;
+    Ok(match format {
+        SinkFormat::JsonLines => Box::new(JsonLinesSink::create(path, batch_size)?),
+        SinkFormat::Csv => Box::new(CsvSink::create(path, field_names, batch_size)?),
+        SinkFormat::Sqlite => Box::new(SqliteSink::open(path, batch_size)?),
+        SinkFormat::Markdown => Box::new(MarkdownSink::create(path, batch_size)?),
+    })
+}
+
+#[derive(Debug)]
+struct Batch {
+    contents: Vec<Content>,
+    size: usize,
+}
+
+impl Batch {
+    fn new(size: usize) -> Self {
+        Self {
+            contents: Vec::new(),
+            size: size.max(1),
+        }
+    }
+
+    // Returns true once the batch is full
+    fn push(&mut self, content: Content) -> bool {
+        self.contents.push(content);
+        self.contents.len() >= self.size
+    }
+
+    fn take(&mut self) -> Vec<Content> {
+        std::mem::take(&mut self.contents)
+    }
+}
+
+// The crawler's old output, printed as each page comes in
+#[derive(Debug, Default)]
+pub struct PrintSink;
+
+impl ContentSink for PrintSink {
+    fn write(&mut self, content: Content) -> Result<(), SinkError> {
+        content.print();
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        Ok(())
+    }
+}
+
+// One JSON object per line, added to whatever the file already holds
+pub struct JsonLinesSink {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    batch: Batch,
+}
+
+impl JsonLinesSink {
+    pub fn create(path: &Path, batch_size: usize) -> Result<Self, SinkError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|err| SinkError::Io(path.into(), err))?;
+        Ok(Self {
+            path: path.into(),
+            writer: BufWriter::new(file),
+            batch: Batch::new(batch_size),
+        })
+    }
+}
+
+impl ContentSink for JsonLinesSink {
+    fn write(&mut self, content: Content) -> Result<(), SinkError> {
+        if self.batch.push(content) {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        for content in self.batch.take() {
+            serde_json::to_writer(&mut self.writer, &content)?;
+            writeln!(self.writer).map_err(|err| SinkError::Io(self.path.clone(), err))?;
+        }
+        self.writer
+            .flush()
+            .map_err(|err| SinkError::Io(self.path.clone(), err))
+    }
+}
+
+impl Drop for JsonLinesSink {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+// One row per page, added to whatever the file already holds. Fields get a column each,
+// with lists joined by commas.
+pub struct CsvSink {
+    writer: csv::Writer<File>,
+    field_names: Vec<String>,
+    batch: Batch,
+}
+
+impl CsvSink {
+    pub fn create(
+        path: &Path,
+        field_names: &[String],
+        batch_size: usize,
+    ) -> Result<Self, SinkError> {
+        let header = ["site", "topic", "url", "change", "title"]
+            .into_iter()
+            .chain(field_names.iter().map(String::as_str))
+            .chain(["body"])
+            .collect::<csv::StringRecord>();
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|err| SinkError::Io(path.into(), err))?;
+        let is_empty = file
+            .metadata()
+            .map_err(|err| SinkError::Io(path.into(), err))?
+            .len()
+            == 0;
+
+        // Rows only line up with the header when the fields are the same as before
+        if !is_empty && *csv::Reader::from_path(path)?.headers()? != header {
+            return Err(SinkError::CsvColumns(path.into()));
+        }
+
+        let mut writer = csv::Writer::from_writer(file);
+        if is_empty {
+            writer.write_record(&header)?;
+        }
+
+        Ok(Self {
+            writer,
+            field_names: field_names.to_vec(),
+            batch: Batch::new(batch_size),
+        })
+    }
+}
+
+impl ContentSink for CsvSink {
+    fn write(&mut self, content: Content) -> Result<(), SinkError> {
+        if self.batch.push(content) {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        for content in self.batch.take() {
+            let fields = self.field_names.iter().map(|name| {
+                content
+                    .fields
+                    .get(name)
+                    .map(ToString::to_string)
+                    .unwrap_or_default()
+            });
+            let record = [
+                content.site.clone(),
+                content.topic.clone().unwrap_or_default(),
+                content.url.clone(),
+                content
+                    .change
+                    .map(|change| change.to_string())
+                    .unwrap_or_default(),
+                content.title.clone(),
+            ]
+            .into_iter()
+            .chain(fields)
+            .chain([content.body.clone()]);
+            self.writer.write_record(record)?;
+        }
+        self.writer.flush().map_err(csv::Error::from)?;
+        Ok(())
+    }
+}
+
+impl Drop for CsvSink {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+// A `contents` table keyed by site, topic and URL, so crawling a page again replaces
+// its row. Removed pages keep their last row, marked as removed. Fields are kept as a
+// JSON object. Each batch is one transaction.
+pub struct SqliteSink {
+    connection: Connection,
+    batch: Batch,
+}
+
+impl SqliteSink {
+    pub fn open(path: &Path, batch_size: usize) -> Result<Self, SinkError> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(SQLITE_SCHEMA)?;
+        Ok(Self {
+            connection,
+            batch: Batch::new(batch_size),
+        })
+    }
+}
+
+impl ContentSink for SqliteSink {
+    fn write(&mut self, content: Content) -> Result<(), SinkError> {
+        if self.batch.push(content) {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        let contents = self.batch.take();
+        if contents.is_empty() {
+            return Ok(());
+        }
+
+        let transaction = self.connection.transaction()?;
+        {
+            let mut insert = transaction.prepare_cached(
+                "INSERT OR REPLACE INTO contents (site, topic, url, change, title, body, fields)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            )?;
+            let mut mark_removed = transaction
+                .prepare_cached("UPDATE contents SET change = ?2 WHERE site = ?1 AND url = ?3")?;
+            for content in &contents {
+                let change = content.change.map(ChangeKind::as_str);
+                if content.change == Some(ChangeKind::Removed) {
+                    mark_removed.execute(params![content.site, change, content.url])?;
+                    continue;
+                }
+                insert.execute(params![
+                    content.site,
+                    content.topic.as_deref().unwrap_or_default(),
+                    content.url,
+                    change,
+                    content.title,
+                    content.body,
+                    serde_json::to_string(&content.fields)?
+                ])?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+impl Drop for SqliteSink {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[derive(Serialize)]
+struct FrontMatter<'a> {
+    site: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    topic: Option<&'a str>,
+    url: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    change: Option<ChangeKind>,
+    title: &'a str,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    fields: &'a BTreeMap<String, FieldValue>,
+}
+
+// A Markdown file per page with everything but the body in YAML front matter, in a
+// directory per site and topic: `dir/site/topic/host-path.md`. The files of removed
+// pages are deleted, so the directory holds the pages that currently exist.
+pub struct MarkdownSink {
+    dir: PathBuf,
+    batch: Batch,
+}
+
+impl MarkdownSink {
+    pub fn create(dir: &Path, batch_size: usize) -> Result<Self, SinkError> {
+        std::fs::create_dir_all(dir).map_err(|err| SinkError::Io(dir.into(), err))?;
+        Ok(Self {
+            dir: dir.into(),
+            batch: Batch::new(batch_size),
+        })
+    }
+
+    fn path(&self, content: &Content) -> PathBuf {
+        let mut path = self.dir.join(slug(&content.site));
+        if let Some(topic) = &content.topic {
+            path.push(slug(topic));
+        }
+        let url = content
+            .url
+            .split_once("://")
+            .map_or(content.url.as_str(), |(_, rest)| rest);
+        // Slugs of different URLs can be the same, so the file name ends with a hash of
+        // the whole URL
+        path.join(format!(
+            "{}-{:08x}.md",
+            slug(url),
+            fnv1a(content.url.as_bytes()) as u32
+        ))
+    }
+
+    fn write_file(&self, content: &Content) -> Result<(), SinkError> {
+        let path = self.path(content);
+        if content.change == Some(ChangeKind::Removed) {
+            return match std::fs::remove_file(&path) {
+                Err(err) if err.kind() != ErrorKind::NotFound => Err(SinkError::Io(path, err)),
+                _ => Ok(()),
+            };
+        }
+
+        let front_matter = serde_yaml::to_string(&FrontMatter {
+            site: &content.site,
+            topic: content.topic.as_deref(),
+            url: &content.url,
+            change: content.change,
+            title: &content.title,
+            fields: &content.fields,
+        })?;
+        let text = format!(
+            "---\n{}---\n\n# {}\n\n{}\n",
+            front_matter,
+            content.title.trim(),
+            content.body.trim()
+        );
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|err| SinkError::Io(dir.into(), err))?;
+        }
+        std::fs::write(&path, text).map_err(|err| SinkError::Io(path, err))
+    }
+}
+
+impl ContentSink for MarkdownSink {
+    fn write(&mut self, content: Content) -> Result<(), SinkError> {
+        if self.batch.push(content) {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        for content in self.batch.take() {
+            self.write_file(&content)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for MarkdownSink {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+// Lowercase letters, digits and dashes, short enough for any file system
+pub fn slug(text: &str) -> String {
+    let slug = text
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    let slug = slug.chars().take(120).collect::<String>();
+    if slug.is_empty() {
+        "index".to_string()
+    } else {
+        slug
+    }
+}
+
+// Writes every content to each of several sinks
+#[derive(Default)]
+pub struct FanOut {
+    sinks: Vec<Box<dyn ContentSink>>,
+}
+
+impl FanOut {
+    pub fn new(sinks: Vec<Box<dyn ContentSink>>) -> Self {
+        Self { sinks }
+    }
+
+    pub fn push(&mut self, sink: Box<dyn ContentSink>) {
+        self.sinks.push(sink);
+    }
+}
+
+impl ContentSink for FanOut {
+    // Every sink gets the content even when an earlier one fails. The first error is
+    // returned.
+    fn write(&mut self, content: Content) -> Result<(), SinkError> {
+        let mut result = Ok(());
+        for sink in &mut self.sinks {
+            let written = sink.write(content.clone());
+            if result.is_ok() {
+                result = written;
+            }
+        }
+        result
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        let mut result = Ok(());
+        for sink in &mut self.sinks {
+            let flushed = sink.flush();
+            if result.is_ok() {
+                result = flushed;
+            }
+        }
+        result
+    }
+}
@@ -0,0 +1,230 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, VecDeque};
+use std::sync::Arc;
+
+use url::Url;
+
+// A link the crawl may follow
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub url: Url,
+    pub depth: usize,
+    pub anchor_text: String,
+}
+
+// Decides the order pages are crawled in. The crawl asks for a depth limit before each
+// pass over the site, then feeds the strategy the links of every page it visits.
+pub trait CrawlStrategy {
+    fn name(&self) -> &'static str;
+
+    // The depth limit for pass number `pass`, or None once the crawl is done. Pages at
+    // the limit are visited but their links aren't followed.
+    fn begin_pass(&mut self, pass: usize) -> Option<usize>;
+
+    // The links of one page, in the order they appear on it
+    fn push(&mut self, candidates: Vec<Candidate>);
+
+    fn pop(&mut self) -> Option<Candidate>;
+
+    // Links waiting to be popped
+    fn queued(&self) -> usize;
+}
+
+// Every page at one depth before any page at the next
+pub struct BreadthFirst {
+    max_depth: usize,
+    queue: VecDeque<Candidate>,
+}
+
+impl BreadthFirst {
+    pub fn new(max_depth: usize) -> Self {
+        Self {
+            max_depth,
+            queue: VecDeque::new(),
+        }
+    }
+}
+
+impl CrawlStrategy for BreadthFirst {
+    fn name(&self) -> &'static str {
+        "breadth-first"
+    }
+
+    fn begin_pass(&mut self, pass: usize) -> Option<usize> {
+        self.queue.clear();
+        (pass == 0).then_some(self.max_depth)
+    }
+
+    fn push(&mut self, candidates: Vec<Candidate>) {
+        self.queue.extend(candidates);
+    }
+
+    fn pop(&mut self) -> Option<Candidate> {
+        self.queue.pop_front()
+    }
+
+    fn queued(&self) -> usize {
+        self.queue.len()
+    }
+}
+
+// Follows the first link of each page as deep as the limit allows before backtracking
+pub struct DepthFirst {
+    max_depth: usize,
+    stack: Vec<Candidate>,
+}
+
+impl DepthFirst {
+    pub fn new(max_depth: usize) -> Self {
+        Self {
+            max_depth,
+            stack: Vec::new(),
+        }
+    }
+}
+
+impl CrawlStrategy for DepthFirst {
+    fn name(&self) -> &'static str {
+        "depth-first"
+    }
+
+    fn begin_pass(&mut self, pass: usize) -> Option<usize> {
+        self.stack.clear();
+        (pass == 0).then_some(self.max_depth)
+    }
+
+    fn push(&mut self, candidates: Vec<Candidate>) {
+        // Reversed, so the first link on the page comes off the stack first
+        self.stack.extend(candidates.into_iter().rev());
+    }
+
+    fn pop(&mut self) -> Option<Candidate> {
+        self.stack.pop()
+    }
+
+    fn queued(&self) -> usize {
+        self.stack.len()
+    }
+}
+
+// Depth-first passes with a limit of 0, 1, 2 and so on up to `max_depth`. Pages are
+// only fetched on the pass that first reaches them, so each pass mostly costs the pages
+// one level deeper than the last, and the crawl stops early once a pass finds nothing
+// beyond its limit.
+pub struct IterativeDeepening {
+    max_depth: usize,
+    stack: Vec<Candidate>,
+}
+
+impl IterativeDeepening {
+    pub fn new(max_depth: usize) -> Self {
+        Self {
+            max_depth,
+            stack: Vec::new(),
+        }
+    }
+}
+
+impl CrawlStrategy for IterativeDeepening {
+    fn name(&self) -> &'static str {
+        "iterative deepening"
+    }
+
+    fn begin_pass(&mut self, pass: usize) -> Option<usize> {
+        self.stack.clear();
+        (pass <= self.max_depth).then_some(pass)
+    }
+
+    fn push(&mut self, candidates: Vec<Candidate>) {
+        self.stack.extend(candidates.into_iter().rev());
+    }
+
+    fn pop(&mut self) -> Option<Candidate> {
+        self.stack.pop()
+    }
+
+    fn queued(&self) -> usize {
+        self.stack.len()
+    }
+}
+
+pub type Scorer = Arc<dyn Fn(&Candidate) -> f64 + Send + Sync>;
+
+// Always crawls the highest scoring link found so far. Links with equal scores are
+// crawled in the order they were found.
+pub struct BestFirst {
+    max_depth: usize,
+    scorer: Scorer,
+    heap: BinaryHeap<Scored>,
+    pushed: u64,
+}
+
+impl BestFirst {
+    pub fn new(max_depth: usize, scorer: Scorer) -> Self {
+        Self {
+            max_depth,
+            scorer,
+            heap: BinaryHeap::new(),
+            pushed: 0,
+        }
+    }
+}
+
+impl CrawlStrategy for BestFirst {
+    fn name(&self) -> &'static str {
+        "best-first"
+    }
+
+    fn begin_pass(&mut self, pass: usize) -> Option<usize> {
+        self.heap.clear();
+        (pass == 0).then_some(self.max_depth)
+    }
+
+    fn push(&mut self, candidates: Vec<Candidate>) {
+        for candidate in candidates {
+            let score = (self.scorer)(&candidate);
+            self.heap.push(Scored {
+                score,
+                order: self.pushed,
+                candidate,
+            });
+            self.pushed += 1;
+        }
+    }
+
+    fn pop(&mut self) -> Option<Candidate> {
+        self.heap.pop().map(|scored| scored.candidate)
+    }
+
+    fn queued(&self) -> usize {
+        self.heap.len()
+    }
+}
+
+struct Scored {
+    score: f64,
+    order: u64,
+    candidate: Candidate,
+}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.score
+            .total_cmp(&other.score)
+            .then_with(|| other.order.cmp(&self.order))
+    }
+}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Scored {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Scored {}
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use url::Url;
+use writing_web_crawlers::links::Link;
+
+use crate::strategy::{Candidate, CrawlStrategy};
+
+#[derive(Debug, Clone)]
+pub struct Visit {
+    pub url: Url,
+    pub depth: usize,
+    // The strategy's pass that first reached the page
+    pub pass: usize,
+}
+
+#[async_trait(?Send)]
+pub trait Visitor {
+    // Fetches and processes a page and returns the links that may be followed from it.
+    // Each page is visited once, however many passes the strategy makes over the site.
+    async fn visit(&mut self, url: &Url, depth: usize) -> Vec<Link>;
+
+    // How many links the strategy has waiting, told before every visit
+    fn queued(&mut self, _links: usize) {}
+}
+
+// Crawls from `seed` in the order the strategy picks, visiting at most `max_pages`
+// pages, and returns the visits in order
+pub async fn traverse<V: Visitor + ?Sized>(
+    strategy: &mut dyn CrawlStrategy,
+    visitor: &mut V,
+    seed: &Url,
+    max_pages: usize,
+) -> Vec<Visit> {
+    let mut visits = Vec::new();
+    let mut links: HashMap<String, Vec<Link>> = HashMap::new();
+    let mut pass = 0;
+
+    while let Some(depth_limit) = strategy.begin_pass(pass) {
+        // The shallowest depth each page was reached at in this pass. A page reached
+        // again at a shallower depth is expanded again, so a depth-first pass doesn't
+        // lose pages behind one it first reached through a longer path.
+        let mut reached: HashMap<String, usize> = HashMap::new();
+        let mut truncated = false;
+        strategy.push(vec![Candidate {
+            url: seed.clone(),
+            depth: 0,
+            anchor_text: String::new(),
+        }]);
+
+        while let Some(candidate) = strategy.pop() {
+            let key = candidate.url.to_string();
+            if reached
+                .get(&key)
+                .is_some_and(|&depth| depth <= candidate.depth)
+            {
+                continue;
+            }
+            if !links.contains_key(&key) {
+                if visits.len() >= max_pages {
+                    continue;
+                }
+                visitor.queued(strategy.queued());
+                let found = visitor.visit(&candidate.url, candidate.depth).await;
+                visits.push(Visit {
+                    url: candidate.url.clone(),
+                    depth: candidate.depth,
+                    pass,
+                });
+                links.insert(key.clone(), found);
+            }
+            reached.insert(key.clone(), candidate.depth);
+
+            let page_links = &links[&key];
+            if candidate.depth >= depth_limit {
+                truncated |= !page_links.is_empty();
+                continue;
+            }
+            let next = page_links
+                .iter()
+                .filter(|link| {
+                    reached
+                        .get(link.url.as_str())
+                        .is_none_or(|&depth| depth > candidate.depth + 1)
+                })
+                .map(|link| Candidate {
+                    url: link.url.clone(),
+                    depth: candidate.depth + 1,
+                    anchor_text: link.anchor_text.clone(),
+                })
+                .collect();
+            strategy.push(next);
+        }
+
+        // Another pass only helps when this one stopped short of some pages
+        if !truncated || visits.len() >= max_pages {
+            break;
+        }
+        pass += 1;
+    }
+
+    visits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use crate::strategy::{BestFirst, BreadthFirst, DepthFirst, IterativeDeepening, Scorer};
+
+    // /f is three links from the home page, and /e links back to it
+    const SITE: &[(&str, &[&str])] = &[
+        ("/", &["/a", "/b"]),
+        ("/a", &["/c", "/d"]),
+        ("/b", &["/e"]),
+        ("/c", &["/f"]),
+        ("/e", &["/"]),
+    ];
+
+    struct LinkGraph;
+
+    #[async_trait(?Send)]
+    impl Visitor for LinkGraph {
+        async fn visit(&mut self, url: &Url, _depth: usize) -> Vec<Link> {
+            let targets = SITE
+                .iter()
+                .find(|(page, _)| *page == url.path())
+                .map_or(&[][..], |(_, targets)| *targets);
+            targets
+                .iter()
+                .map(|target| Link {
+                    url: url.join(target).unwrap(),
+                    anchor_text: target.to_string(),
+                    rel: Vec::new(),
+                })
+                .collect()
+        }
+    }
+
+    async fn crawl(
+        strategy: &mut dyn CrawlStrategy,
+        max_pages: usize,
+    ) -> Vec<(String, usize, usize)> {
+        let seed = Url::parse("https://example.com/").unwrap();
+        traverse(strategy, &mut LinkGraph, &seed, max_pages)
+            .await
+            .into_iter()
+            .map(|visit| (visit.url.path().to_string(), visit.depth, visit.pass))
+            .collect()
+    }
+
+    fn visits(expected: &[(&str, usize, usize)]) -> Vec<(String, usize, usize)> {
+        expected
+            .iter()
+            .map(|&(path, depth, pass)| (path.to_string(), depth, pass))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn breadth_first_visits_a_level_at_a_time() {
+        assert_eq!(
+            crawl(&mut BreadthFirst::new(2), 100).await,
+            visits(&[
+                ("/", 0, 0),
+                ("/a", 1, 0),
+                ("/b", 1, 0),
+                ("/c", 2, 0),
+                ("/d", 2, 0),
+                ("/e", 2, 0),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn depth_first_follows_first_links_down_to_the_limit() {
+        assert_eq!(
+            crawl(&mut DepthFirst::new(2), 100).await,
+            visits(&[
+                ("/", 0, 0),
+                ("/a", 1, 0),
+                ("/c", 2, 0),
+                ("/d", 2, 0),
+                ("/b", 1, 0),
+                ("/e", 2, 0),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn iterative_deepening_visits_each_page_on_the_pass_that_reaches_it() {
+        assert_eq!(
+            crawl(&mut IterativeDeepening::new(3), 100).await,
+            visits(&[
+                ("/", 0, 0),
+                ("/a", 1, 1),
+                ("/b", 1, 1),
+                ("/c", 2, 2),
+                ("/d", 2, 2),
+                ("/e", 2, 2),
+                ("/f", 3, 3),
+            ])
+        );
+        let cut_off = crawl(&mut IterativeDeepening::new(2), 100).await;
+        assert_eq!(cut_off.len(), 6);
+        assert!(cut_off.iter().all(|(path, _, _)| path != "/f"));
+    }
+
+    #[tokio::test]
+    async fn best_first_visits_the_highest_scores_first() {
+        let scorer: Scorer =
+            Arc::new(
+                |candidate: &Candidate| match candidate.anchor_text.as_str() {
+                    "/e" => 3.0,
+                    "/d" => 2.0,
+                    _ => 0.0,
+                },
+            );
+        assert_eq!(
+            crawl(&mut BestFirst::new(2, scorer), 100).await,
+            visits(&[
+                ("/", 0, 0),
+                ("/a", 1, 0),
+                ("/d", 2, 0),
+                ("/b", 1, 0),
+                ("/e", 2, 0),
+                ("/c", 2, 0),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn the_page_budget_ends_the_crawl() {
+        assert_eq!(
+            crawl(&mut BreadthFirst::new(5), 3).await,
+            visits(&[("/", 0, 0), ("/a", 1, 0), ("/b", 1, 0)])
+        );
+    }
+}
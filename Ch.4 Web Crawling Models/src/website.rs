@@ -0,0 +1,358 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use fancy_regex::Regex;
+use scraper::error::SelectorErrorKind;
+use scraper::Selector;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use url::{Position, Url};
+
+use crate::fields::{validate_layout, FieldSpec, TextLocation};
+use crate::json::{DataSource, JsonQuery};
+use crate::registry::SiteDefinition;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl ConfigFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "toml" => Some(Self::Toml),
+            "yaml" | "yml" => Some(Self::Yaml),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+
+    pub fn deserialize<T: DeserializeOwned>(self, text: &str) -> Result<T, String> {
+        match self {
+            Self::Toml => toml::from_str(text).map_err(|err| err.to_string()),
+            Self::Yaml => serde_yaml::from_str(text).map_err(|err| err.to_string()),
+            Self::Json => serde_json::from_str(text).map_err(|err| err.to_string()),
+        }
+    }
+}
+
+// A CSS selector, parsed when the site definition is loaded so a typo is reported
+// there instead of panicking in the middle of a crawl
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct CssSelector {
+    source: String,
+    selector: Selector,
+}
+
+impl CssSelector {
+    pub fn selector(&self) -> &Selector {
+        &self.selector
+    }
+}
+
+impl TryFrom<String> for CssSelector {
+    type Error = String;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        let selector = Selector::parse(&source).map_err(|err| {
+            // scraper's message for these asks for a bug report instead of saying what's wrong
+            let reason = match err {
+                SelectorErrorKind::UnexpectedSelectorParseError(kind) => format!("{:?}", kind),
+                err => err.to_string(),
+            };
+            format!("invalid CSS selector `{}`: {}", source, reason)
+        })?;
+        Ok(Self { source, selector })
+    }
+}
+
+impl From<CssSelector> for String {
+    fn from(selector: CssSelector) -> Self {
+        selector.source
+    }
+}
+
+impl fmt::Display for CssSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+// A regex, compiled when the site definition is loaded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Pattern(Regex);
+
+impl Pattern {
+    pub fn is_match(&self, text: &str) -> bool {
+        self.0.is_match(text).unwrap_or(false)
+    }
+
+    // The first group, or the whole match when the regex has no groups
+    pub fn find<'t>(&self, text: &'t str) -> Option<&'t str> {
+        let captures = self.0.captures(text).ok()??;
+        captures
+            .get(1)
+            .or_else(|| captures.get(0))
+            .map(|m| m.as_str())
+    }
+
+    pub fn group_names(&self) -> impl Iterator<Item = &str> {
+        self.0.capture_names().flatten()
+    }
+
+    // The template with every `{name}` replaced by the group of that name, when the
+    // text matches
+    pub fn fill(&self, template: &str, text: &str) -> Option<String> {
+        let captures = self.0.captures(text).ok()??;
+        let mut filled = template.to_string();
+        for name in self.group_names() {
+            let value = captures.name(name).map_or("", |m| m.as_str());
+            filled = filled.replace(&format!("{{{}}}", name), value);
+        }
+        Some(filled)
+    }
+}
+
+impl TryFrom<String> for Pattern {
+    type Error = String;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        Regex::new(&source)
+            .map(Self)
+            .map_err(|err| format!("invalid regex `{}`: {}", source, err))
+    }
+}
+
+impl From<Pattern> for String {
+    fn from(pattern: Pattern) -> Self {
+        pattern.0.as_str().to_string()
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchOn {
+    // The path and query, like `/world/some-article?page=2`
+    #[default]
+    Path,
+    // The whole URL, like `https://www.reuters.com/world/some-article?page=2`
+    Url,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StrategyKind {
+    #[default]
+    BreadthFirst,
+    DepthFirst,
+    IterativeDeepening,
+    // Target pages first, then the shallowest pages
+    BestFirst,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CrawlSettings {
+    pub strategy: StrategyKind,
+    // Links more than this many clicks from the home page aren't followed
+    pub max_depth: usize,
+    pub max_pages: usize,
+    // Links matching this are followed to look for more target pages. Without it only
+    // target pages are followed.
+    pub follow_pattern: Option<Pattern>,
+}
+
+impl Default for CrawlSettings {
+    fn default() -> Self {
+        Self {
+            strategy: StrategyKind::default(),
+            max_depth: 1,
+            max_pages: 1000,
+            follow_pattern: None,
+        }
+    }
+}
+
+// How to load the pages of a site that builds them with JavaScript
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderSettings {
+    // Wait until the page has an element matching this before reading it
+    pub wait_for: Option<CssSelector>,
+    pub wait_timeout_secs: u64,
+    // Times to scroll to the bottom, for pages that load more as they're scrolled
+    pub scrolls: usize,
+    // How long to let the page load after each scroll
+    pub scroll_pause_ms: u64,
+    // Keep a screenshot of every rendered page here
+    pub screenshot_dir: Option<PathBuf>,
+}
+
+impl RenderSettings {
+    pub fn wait_timeout(&self) -> Duration {
+        Duration::from_secs(self.wait_timeout_secs)
+    }
+
+    pub fn scroll_pause(&self) -> Duration {
+        Duration::from_millis(self.scroll_pause_ms)
+    }
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            wait_for: None,
+            wait_timeout_secs: 10,
+            scrolls: 0,
+            scroll_pause_ms: 500,
+            screenshot_dir: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Website {
+    pub name: String,
+    pub url: Url,
+    // Pages whose URL matches are crawled as articles
+    pub target_pattern: Pattern,
+    // What part of the URL `target_pattern` and `crawl.follow_pattern` match
+    #[serde(default)]
+    pub match_on: MatchOn,
+    // The title and body are the text of the elements these match...
+    pub title_tag: Option<CssSelector>,
+    pub body_tag: Option<CssSelector>,
+    // ...or the values at these paths in the site's data
+    pub title_path: Option<JsonQuery>,
+    pub body_path: Option<JsonQuery>,
+    // Extra values to extract from every page, by name
+    #[serde(default)]
+    pub fields: BTreeMap<String, FieldSpec>,
+    // JSON behind the pages, for sites that ship their content as data
+    pub data: Option<DataSource>,
+    #[serde(default)]
+    pub crawl: CrawlSettings,
+    // Render pages in a browser instead of reading the HTML the server sends
+    #[serde(default)]
+    pub render: Option<RenderSettings>,
+    // Name of the header profile the site's pages are requested with, for a site that
+    // needs different headers from the rest
+    pub headers: Option<String>,
+}
+
+impl Website {
+    pub fn parse(text: &str, format: ConfigFormat) -> Result<Self, String> {
+        let site: Self = format.deserialize(text)?;
+        site.validate()?;
+        Ok(site)
+    }
+
+    // Links are resolved against the page they're on and normalized before they are
+    // matched, however the page wrote them
+    pub fn matches(&self, pattern: &Pattern, url: &Url) -> bool {
+        pattern.is_match(self.matched_part(url))
+    }
+
+    pub fn is_target(&self, url: &Url) -> bool {
+        self.matches(&self.target_pattern, url)
+    }
+
+    // The API endpoint with the data of a target page
+    pub fn api_url(&self, template: &str, url: &Url) -> Result<Url, String> {
+        let api_url = self
+            .target_pattern
+            .fill(template, self.matched_part(url))
+            .ok_or_else(|| format!("{} is not a target page", url))?;
+        Url::parse(&api_url).map_err(|err| format!("invalid API URL {}: {}", api_url, err))
+    }
+
+    pub fn title(&self) -> TextLocation<'_> {
+        TextLocation {
+            tag: self.title_tag.as_ref(),
+            path: self.title_path.as_ref(),
+        }
+    }
+
+    pub fn body(&self) -> TextLocation<'_> {
+        TextLocation {
+            tag: self.body_tag.as_ref(),
+            path: self.body_path.as_ref(),
+        }
+    }
+
+    fn matched_part<'u>(&self, url: &'u Url) -> &'u str {
+        match self.match_on {
+            MatchOn::Path => &url[Position::BeforePath..Position::AfterQuery],
+            MatchOn::Url => url.as_str(),
+        }
+    }
+
+    // What the types don't already check
+    fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("the site has no name".to_string());
+        }
+        if !matches!(self.url.scheme(), "http" | "https") {
+            return Err(format!("{} is not an http or https URL", self.url));
+        }
+        if self.crawl.max_pages == 0 {
+            return Err("crawl.max_pages must be at least 1".to_string());
+        }
+        validate_layout(self.title(), self.body(), &self.fields, self.data.as_ref())?;
+        if let Some(DataSource::Api { url }) = &self.data {
+            self.validate_api(url)?;
+        }
+        Ok(())
+    }
+
+    // Every placeholder has to be a group of `target_pattern`
+    fn validate_api(&self, template: &str) -> Result<(), String> {
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            let Some(end) = rest[start..].find('}') else {
+                break;
+            };
+            let name = &rest[start + 1..start + end];
+            if !self.target_pattern.group_names().any(|group| group == name) {
+                return Err(format!(
+                    "data.url has a {{{}}} placeholder, but target_pattern has no group named {}",
+                    name, name
+                ));
+            }
+            rest = &rest[start + end + 1..];
+        }
+
+        let mut example = template.to_string();
+        for name in self.target_pattern.group_names() {
+            example = example.replace(&format!("{{{}}}", name), name);
+        }
+        match Url::parse(&example) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => Ok(()),
+            _ => Err(format!("data.url {} is not an http or https URL", template)),
+        }
+    }
+}
+
+impl SiteDefinition for Website {
+    fn parse(text: &str, format: ConfigFormat) -> Result<Self, String> {
+        Website::parse(text, format)
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
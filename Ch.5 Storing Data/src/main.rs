@@ -1,4 +1,5 @@
 use async_trait::async_trait;
+use clap::Parser;
 use lettre::{
     address,
     message::header::ContentType,
@@ -7,7 +8,9 @@ use lettre::{
 };
 use scraper::{Html, Selector};
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use tracing::{field, info, instrument, Span};
+use writing_web_crawlers::logging::{init_logging, LogArgs};
 
 #[derive(thiserror::Error, Debug)]
 enum AppError {
@@ -25,6 +28,9 @@ enum AppError {
 
     #[error("HTML parsing error: {0}")]
     HtmlParsing(#[from] scraper::error::SelectorErrorKind<'static>),
+
+    #[error("Logging setup error: {0}")]
+    Logging(#[from] writing_web_crawlers::error::AppError),
 }
 
 // EmailSender trait for sending emails
@@ -75,9 +81,29 @@ struct ReqwestWebScraper;
 
 #[async_trait]
 impl WebScraper for ReqwestWebScraper {
+    #[instrument(
+        name = "request",
+        skip_all,
+        fields(
+            method = "GET",
+            url = %url,
+            status = field::Empty,
+            bytes = field::Empty,
+            duration_ms = field::Empty,
+            retry = field::Empty,
+        ),
+        err
+    )]
     async fn fetch_html(&self, url: &str) -> Result<String, AppError> {
+        let started = Instant::now();
         let response = reqwest::get(url).await?;
+        let span = Span::current();
+        span.record("status", response.status().as_u16());
+
         let html = response.text().await?;
+        span.record("bytes", html.len());
+        span.record("duration_ms", started.elapsed().as_secs_f64() * 1000.0);
+        info!("response");
         Ok(html)
     }
 }
@@ -125,8 +151,18 @@ where
     }
 }
 
+// The checker is configured in code; only its log is set from the command line
+#[derive(Parser)]
+struct Cli {
+    #[command(flatten)]
+    logging: LogArgs,
+}
+
 #[tokio::main]
 async fn main() -> Result<(), AppError> {
+    let cli = Cli::parse();
+    init_logging(&cli.logging.config())?;
+
     let smtp_server = "smtp.gmail.com";
     let smtp_port = 587;
     let smtp_username = "your_smtp_username";
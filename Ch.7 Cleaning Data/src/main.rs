@@ -1,14 +1,21 @@
 use std::collections::HashMap;
+use std::time::Instant;
 
+use clap::Parser;
 use fancy_regex::Regex;
 use reqwest::Client;
 use scraper::Html;
 use scraper::Selector;
+use tracing::{field, info, instrument, Span};
+use writing_web_crawlers::logging::{init_logging, LogArgs};
 
 #[derive(thiserror::Error, Debug)]
 pub enum AppError {
     #[error("HTTP request error: {0}")]
     HttpRequest(#[from] reqwest::Error),
+
+    #[error("Logging setup error: {0}")]
+    Logging(#[from] writing_web_crawlers::error::AppError),
 }
 
 #[derive(Default)]
@@ -21,13 +28,39 @@ impl WebFetcher {
         Self::default()
     }
 
+    #[instrument(
+        name = "request",
+        skip_all,
+        fields(
+            method = "GET",
+            url = %url,
+            status = field::Empty,
+            bytes = field::Empty,
+            duration_ms = field::Empty,
+            retry = field::Empty,
+        ),
+        err
+    )]
     pub async fn fetch_url(&self, url: &str) -> Result<String, AppError> {
+        let started = Instant::now();
         let response = self.client.get(url).send().await?;
+        let span = Span::current();
+        span.record("status", response.status().as_u16());
+
         let body = response.text().await?;
+        span.record("bytes", body.len());
+        span.record("duration_ms", started.elapsed().as_secs_f64() * 1000.0);
+        info!("response");
         Ok(body)
     }
 }
 
+#[derive(Parser)]
+struct Cli {
+    #[command(flatten)]
+    logging: LogArgs,
+}
+
 fn clean_sentence(sentence: &str) -> Vec<String> {
     let words = sentence
         .split_whitespace()
@@ -85,6 +118,9 @@ fn get_ngrams(content: &str, n: usize) -> HashMap<String, usize> {
 
 #[tokio::main]
 async fn main() -> Result<(), AppError> {
+    let cli = Cli::parse();
+    init_logging(&cli.logging.config())?;
+
     let url = "https://en.wikipedia.org/wiki/Rust_(programming_language)";
     let web_fetcher = WebFetcher::new();
     let body = web_fetcher.fetch_url(url).await?;